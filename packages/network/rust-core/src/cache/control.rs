@@ -0,0 +1,474 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Upper bound for heuristic freshness (RFC 9111 §4.2.2)
+const HEURISTIC_MAX_SECONDS: i64 = 24 * 60 * 60;
+
+/// Status codes that may be cached without explicit freshness (RFC 9110 §15.1)
+const HEURISTICALLY_CACHEABLE: &[u16] = &[200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Parsed `Cache-Control` response directives
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheControl {
+    pub max_age: Option<u64>,
+    /// Only meaningful for shared caches; parsed but ignored by `HttpCache`
+    pub s_maxage: Option<u64>,
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub must_revalidate: bool,
+    /// Seconds a stale response may be served while it is revalidated (RFC 5861)
+    pub stale_while_revalidate: Option<u64>,
+    /// Seconds a stale response may be served when the origin is unreachable (RFC 5861)
+    pub stale_if_error: Option<u64>,
+}
+
+impl CacheControl {
+    /// Parse a `Cache-Control` header value. Unknown directives are ignored.
+    pub fn parse(value: &str) -> Self {
+        let mut cc = CacheControl::default();
+
+        for directive in value.split(',') {
+            let directive = directive.trim();
+            if directive.is_empty() {
+                continue;
+            }
+            let (name, arg) = match directive.split_once('=') {
+                Some((n, a)) => (n.trim(), Some(a.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+
+            // An unparseable delta-seconds means the response is already stale
+            let seconds = || arg.map(|a| a.parse::<u64>().unwrap_or(0));
+
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => cc.max_age = seconds(),
+                "s-maxage" => cc.s_maxage = seconds(),
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "private" => cc.private = true,
+                "public" => cc.public = true,
+                "must-revalidate" | "proxy-revalidate" => cc.must_revalidate = true,
+                "stale-while-revalidate" => cc.stale_while_revalidate = seconds(),
+                "stale-if-error" => cc.stale_if_error = seconds(),
+                _ => {}
+            }
+        }
+
+        cc
+    }
+
+    /// How long past expiry the response may still be served in some mode
+    pub fn stale_window_seconds(&self) -> u64 {
+        if self.must_revalidate || self.no_cache {
+            return 0;
+        }
+        self.stale_while_revalidate
+            .unwrap_or(0)
+            .max(self.stale_if_error.unwrap_or(0))
+    }
+}
+
+/// How a stored response may be used right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Freshness {
+    /// Within its freshness lifetime — use as-is
+    Fresh,
+    /// Stale, but within `stale-while-revalidate` — use it and revalidate in the background
+    StaleRevalidatable,
+    /// Stale, but within `stale-if-error` — use it only if the origin can't be
+    /// reached (immediately, when offline)
+    StaleIfError,
+    /// Stale and not servable; revalidate or refetch
+    Unusable,
+}
+
+impl Freshness {
+    /// Whether the entry may be shown to the user without a network round-trip
+    pub fn is_usable(&self, is_online: bool) -> bool {
+        match self {
+            Freshness::Fresh | Freshness::StaleRevalidatable => true,
+            Freshness::StaleIfError => !is_online,
+            Freshness::Unusable => false,
+        }
+    }
+}
+
+/// Classify a stored response given its expiry and the current connectivity
+pub fn freshness_verdict(
+    cc: &CacheControl,
+    expires_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    is_online: bool,
+) -> Freshness {
+    if now < expires_at {
+        return Freshness::Fresh;
+    }
+    if cc.must_revalidate || cc.no_cache {
+        return Freshness::Unusable;
+    }
+
+    let staleness = (now - expires_at).num_seconds().max(0) as u64;
+    let within = |window: Option<u64>| window.map(|w| staleness <= w).unwrap_or(false);
+
+    // Revalidating in the background needs a network; offline only
+    // stale-if-error applies
+    if is_online && within(cc.stale_while_revalidate) {
+        Freshness::StaleRevalidatable
+    } else if within(cc.stale_if_error) {
+        Freshness::StaleIfError
+    } else {
+        Freshness::Unusable
+    }
+}
+
+/// Outcome of evaluating a response for storage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheDecision {
+    /// Whether the response may be stored at all
+    pub cacheable: bool,
+    /// Remaining freshness lifetime (0 = store, but revalidate before use)
+    pub ttl_seconds: u64,
+    /// Whether the lifetime came from the Last-Modified heuristic
+    pub heuristic: bool,
+}
+
+impl CacheDecision {
+    fn not_cacheable() -> Self {
+        CacheDecision {
+            cacheable: false,
+            ttl_seconds: 0,
+            heuristic: false,
+        }
+    }
+}
+
+/// Parse a headers JSON object into a map with lower-cased names.
+///
+/// Non-string values are stringified and arrays are joined with ", " so that
+/// list-valued headers survive platform serializers that split them.
+pub fn parse_headers(headers_json: &str) -> HashMap<String, String> {
+    let value: serde_json::Value = match serde_json::from_str(headers_json) {
+        Ok(v) => v,
+        Err(_) => return HashMap::new(),
+    };
+
+    let mut headers = HashMap::new();
+    if let serde_json::Value::Object(map) = value {
+        for (name, value) in map {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                serde_json::Value::Array(items) => items
+                    .iter()
+                    .map(|v| {
+                        v.as_str()
+                            .map(str::to_string)
+                            .unwrap_or_else(|| v.to_string())
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                other => other.to_string(),
+            };
+            headers.insert(name.to_ascii_lowercase(), value);
+        }
+    }
+    headers
+}
+
+/// Parse an HTTP-date (IMF-fixdate, RFC 850 or asctime format)
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc2822(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    for format in ["%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(naive.and_utc());
+        }
+    }
+    None
+}
+
+/// Decide whether a response is cacheable and for how long, following
+/// RFC 9111 for a private (single-user) cache.
+///
+/// Precedence: `no-store` → `no-cache` → `max-age` → `Expires` − `Date` →
+/// 10% of `Date` − `Last-Modified`. The result is reduced by the response's
+/// current age (`Age` header or time since `Date`).
+pub fn evaluate_response(
+    method: &str,
+    status_code: u16,
+    headers: &HashMap<String, String>,
+    now: DateTime<Utc>,
+) -> CacheDecision {
+    let method = method.to_ascii_uppercase();
+    if method != "GET" && method != "HEAD" {
+        return CacheDecision::not_cacheable();
+    }
+    if status_code < 200 || status_code == 206 || status_code == 304 {
+        return CacheDecision::not_cacheable();
+    }
+
+    let cc = headers
+        .get("cache-control")
+        .map(|v| CacheControl::parse(v))
+        .unwrap_or_default();
+    if cc.no_store {
+        return CacheDecision::not_cacheable();
+    }
+    // `Vary: *` means no stored response can ever match a later request
+    if headers
+        .get("vary")
+        .is_some_and(|v| v.split(',').any(|n| n.trim() == "*"))
+    {
+        return CacheDecision::not_cacheable();
+    }
+
+    let date = headers
+        .get("date")
+        .and_then(|d| parse_http_date(d))
+        .unwrap_or(now);
+
+    let mut heuristic = false;
+    let lifetime: i64 = if cc.no_cache {
+        0
+    } else if let Some(max_age) = cc.max_age {
+        max_age.min(i64::MAX as u64) as i64
+    } else if let Some(expires) = headers.get("expires") {
+        // An invalid Expires value means "already expired"
+        parse_http_date(expires)
+            .map(|e| (e - date).num_seconds().max(0))
+            .unwrap_or(0)
+    } else if HEURISTICALLY_CACHEABLE.contains(&status_code) || cc.public || cc.private {
+        heuristic = true;
+        headers
+            .get("last-modified")
+            .and_then(|lm| parse_http_date(lm))
+            .map(|lm| ((date - lm).num_seconds() / 10).clamp(0, HEURISTIC_MAX_SECONDS))
+            .unwrap_or(0)
+    } else {
+        return CacheDecision::not_cacheable();
+    };
+
+    let apparent_age = (now - date).num_seconds().max(0);
+    let age_value = headers
+        .get("age")
+        .and_then(|a| a.trim().parse::<i64>().ok())
+        .unwrap_or(0);
+    let current_age = apparent_age.max(age_value);
+    let ttl_seconds = (lifetime - current_age).max(0) as u64;
+
+    // A response that is stale on arrival is only worth keeping if it can be
+    // revalidated cheaply
+    let has_validator = headers.contains_key("etag") || headers.contains_key("last-modified");
+    if ttl_seconds == 0 && !has_validator {
+        return CacheDecision::not_cacheable();
+    }
+
+    CacheDecision {
+        cacheable: true,
+        ttl_seconds,
+        heuristic,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_ascii_lowercase(), v.to_string()))
+            .collect()
+    }
+
+    fn at(value: &str) -> DateTime<Utc> {
+        parse_http_date(value).unwrap()
+    }
+
+    #[test]
+    fn test_parse_cache_control() {
+        let cc = CacheControl::parse("public, max-age=600, s-maxage=\"60\", must-revalidate");
+        assert_eq!(cc.max_age, Some(600));
+        assert_eq!(cc.s_maxage, Some(60));
+        assert!(cc.public);
+        assert!(cc.must_revalidate);
+        assert!(!cc.no_store);
+
+        let cc = CacheControl::parse("no-store, max-age=abc");
+        assert!(cc.no_store);
+        assert_eq!(cc.max_age, Some(0));
+    }
+
+    #[test]
+    fn test_parse_stale_directives() {
+        let cc = CacheControl::parse("max-age=60, stale-while-revalidate=30, stale-if-error=86400");
+        assert_eq!(cc.stale_while_revalidate, Some(30));
+        assert_eq!(cc.stale_if_error, Some(86400));
+        assert_eq!(cc.stale_window_seconds(), 86400);
+
+        let cc = CacheControl::parse("max-age=60, must-revalidate, stale-if-error=86400");
+        assert_eq!(cc.stale_window_seconds(), 0);
+    }
+
+    #[test]
+    fn test_freshness_verdict() {
+        let expires = at("Wed, 01 Jan 2025 00:00:00 GMT");
+        let cc = CacheControl::parse("max-age=60, stale-while-revalidate=30, stale-if-error=3600");
+        let secs = |s: i64| expires + chrono::Duration::seconds(s);
+
+        assert_eq!(
+            freshness_verdict(&cc, expires, secs(-1), true),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            freshness_verdict(&cc, expires, secs(10), true),
+            Freshness::StaleRevalidatable
+        );
+        assert_eq!(
+            freshness_verdict(&cc, expires, secs(10), false),
+            Freshness::StaleIfError
+        );
+        assert_eq!(
+            freshness_verdict(&cc, expires, secs(600), true),
+            Freshness::StaleIfError
+        );
+        assert_eq!(
+            freshness_verdict(&cc, expires, secs(7200), false),
+            Freshness::Unusable
+        );
+
+        let strict = CacheControl::parse("max-age=60, must-revalidate, stale-if-error=3600");
+        assert_eq!(
+            freshness_verdict(&strict, expires, secs(10), false),
+            Freshness::Unusable
+        );
+    }
+
+    #[test]
+    fn test_freshness_usable() {
+        assert!(Freshness::StaleRevalidatable.is_usable(true));
+        assert!(Freshness::StaleIfError.is_usable(false));
+        assert!(!Freshness::StaleIfError.is_usable(true));
+        assert!(!Freshness::Unusable.is_usable(false));
+    }
+
+    #[test]
+    fn test_parse_http_date_formats() {
+        let expected = at("Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(expected)
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(expected));
+        assert_eq!(parse_http_date("not a date"), None);
+    }
+
+    #[test]
+    fn test_parse_headers_lowercases_names() {
+        let h = parse_headers(
+            r#"{"Cache-Control":"max-age=60","Age":5,"Vary":["Accept","Accept-Language"]}"#,
+        );
+        assert_eq!(h.get("cache-control").unwrap(), "max-age=60");
+        assert_eq!(h.get("age").unwrap(), "5");
+        assert_eq!(h.get("vary").unwrap(), "Accept, Accept-Language");
+        assert!(parse_headers("not json").is_empty());
+    }
+
+    #[test]
+    fn test_max_age_minus_age() {
+        let now = at("Wed, 01 Jan 2025 00:00:00 GMT");
+        let h = headers(&[("Cache-Control", "max-age=300"), ("Age", "100")]);
+        let d = evaluate_response("GET", 200, &h, now);
+        assert!(d.cacheable);
+        assert_eq!(d.ttl_seconds, 200);
+        assert!(!d.heuristic);
+    }
+
+    #[test]
+    fn test_max_age_overrides_expires() {
+        let now = at("Wed, 01 Jan 2025 00:00:00 GMT");
+        let h = headers(&[
+            ("Cache-Control", "max-age=60"),
+            ("Date", "Wed, 01 Jan 2025 00:00:00 GMT"),
+            ("Expires", "Wed, 01 Jan 2025 01:00:00 GMT"),
+        ]);
+        assert_eq!(evaluate_response("GET", 200, &h, now).ttl_seconds, 60);
+    }
+
+    #[test]
+    fn test_expires_relative_to_date() {
+        // Server clock is an hour ahead of ours; only the difference matters
+        let now = at("Wed, 01 Jan 2025 00:00:00 GMT");
+        let h = headers(&[
+            ("Date", "Wed, 01 Jan 2025 01:00:00 GMT"),
+            ("Expires", "Wed, 01 Jan 2025 01:10:00 GMT"),
+        ]);
+        assert_eq!(evaluate_response("GET", 200, &h, now).ttl_seconds, 600);
+    }
+
+    #[test]
+    fn test_no_store_and_private() {
+        let now = Utc::now();
+        let d = evaluate_response("GET", 200, &headers(&[("Cache-Control", "no-store")]), now);
+        assert!(!d.cacheable);
+
+        // This is a private cache, so `private` responses are fine to keep
+        let d = evaluate_response(
+            "GET",
+            200,
+            &headers(&[("Cache-Control", "private, max-age=60")]),
+            now,
+        );
+        assert!(d.cacheable);
+        assert_eq!(d.ttl_seconds, 60);
+    }
+
+    #[test]
+    fn test_no_cache_stores_for_revalidation() {
+        let now = Utc::now();
+        let d = evaluate_response(
+            "GET",
+            200,
+            &headers(&[
+                ("Cache-Control", "no-cache, max-age=600"),
+                ("ETag", "\"v1\""),
+            ]),
+            now,
+        );
+        assert!(d.cacheable);
+        assert_eq!(d.ttl_seconds, 0);
+
+        // Without a validator there is nothing to revalidate with
+        let d = evaluate_response("GET", 200, &headers(&[("Cache-Control", "no-cache")]), now);
+        assert!(!d.cacheable);
+    }
+
+    #[test]
+    fn test_last_modified_heuristic() {
+        let now = at("Sat, 11 Jan 2025 00:00:00 GMT");
+        let h = headers(&[
+            ("Date", "Sat, 11 Jan 2025 00:00:00 GMT"),
+            ("Last-Modified", "Fri, 10 Jan 2025 00:00:00 GMT"),
+        ]);
+        let d = evaluate_response("GET", 200, &h, now);
+        assert!(d.cacheable);
+        assert!(d.heuristic);
+        assert_eq!(d.ttl_seconds, 8640); // 10% of one day
+
+        // Heuristics don't apply to statuses outside the default-cacheable set
+        assert!(!evaluate_response("GET", 500, &h, now).cacheable);
+    }
+
+    #[test]
+    fn test_uncacheable_methods_and_statuses() {
+        let now = Utc::now();
+        let h = headers(&[("Cache-Control", "max-age=60")]);
+        assert!(!evaluate_response("POST", 200, &h, now).cacheable);
+        assert!(!evaluate_response("GET", 206, &h, now).cacheable);
+        assert!(evaluate_response("HEAD", 200, &h, now).cacheable);
+    }
+}
@@ -1,9 +1,24 @@
 use chrono::Utc;
-use rusqlite::{params, Connection};
+use rusqlite::{Connection, OptionalExtension, params};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::sync::Mutex;
 
+use crate::connectivity::NetworkStatus;
+use crate::optimization;
+use crate::schema::{add_column_if_missing, read_body};
+
+mod control;
+mod stats;
+
+pub use control::{
+    CacheControl, CacheDecision, Freshness, evaluate_response, freshness_verdict, parse_headers,
+    parse_http_date,
+};
+use stats::CacheEvent;
+pub use stats::{CounterStats, StatsScope};
+
 /// A cached HTTP response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CachedResponse {
@@ -13,8 +28,9 @@ pub struct CachedResponse {
     pub status_code: u16,
     /// Response headers as JSON
     pub headers_json: String,
-    /// Response body
-    pub body: String,
+    /// Response body (base64 in JSON)
+    #[serde(with = "crate::optimization::base64_body")]
+    pub body: Vec<u8>,
     /// When this entry was cached
     pub cached_at: String,
     /// When this entry expires
@@ -25,15 +41,31 @@ pub struct CachedResponse {
     pub last_modified: Option<String>,
     /// Size of the body in bytes
     pub body_size: u64,
+    /// Size of the body as stored on disk (smaller if compressed at rest)
+    pub stored_body_size: u64,
+}
+
+/// A cached response together with how it may be used right now
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheLookup {
+    pub entry: CachedResponse,
+    pub freshness: Freshness,
 }
 
 /// Cache statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheStats {
     pub total_entries: u64,
+    /// Storage charged against the budget, including headers and overhead
     pub total_size_bytes: u64,
+    /// Bodies as served to callers
+    pub body_bytes: u64,
+    /// Bodies as stored on disk after compression
+    pub stored_body_bytes: u64,
     pub hit_count: u64,
     pub miss_count: u64,
+    pub revalidation_count: u64,
+    pub eviction_count: u64,
     pub hit_rate: f64,
 }
 
@@ -43,6 +75,8 @@ pub enum CacheError {
     DatabaseError(String),
     #[error("Cache miss")]
     CacheMiss,
+    #[error("Entry of {size} bytes exceeds the {limit} byte limit")]
+    EntryTooLarge { size: u64, limit: u64 },
 }
 
 impl From<rusqlite::Error> for CacheError {
@@ -51,16 +85,247 @@ impl From<rusqlite::Error> for CacheError {
     }
 }
 
+/// Which entries to evict first when the cache is over budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Least recently used
+    Lru,
+    /// Least frequently used (ties broken by recency)
+    Lfu,
+    /// Greedy-Dual-Size-Frequency: large, rarely used entries go first, with
+    /// aging so formerly popular entries eventually leave
+    Gdsf,
+}
+
+impl EvictionPolicy {
+    fn order_by(&self) -> &'static str {
+        match self {
+            EvictionPolicy::Lru => "last_accessed_at ASC",
+            EvictionPolicy::Lfu => "access_count ASC, last_accessed_at ASC",
+            EvictionPolicy::Gdsf => "gdsf_priority ASC, last_accessed_at ASC",
+        }
+    }
+}
+
+/// Size limits and eviction behaviour for `HttpCache`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Total budget for all entries, counted with `entry_size`
+    pub max_size_bytes: u64,
+    pub eviction_policy: EvictionPolicy,
+    /// Entries larger than this are never stored
+    pub max_entry_bytes: Option<u64>,
+    /// Budget for the entries of a single host
+    pub per_host_quota_bytes: Option<u64>,
+    /// Gzip bodies at rest when that makes them at least 20% smaller
+    pub compress_bodies: bool,
+    /// Path segments kept for per-path-prefix statistics
+    pub stats_path_depth: usize,
+}
+
+impl CacheConfig {
+    /// LRU eviction with no per-entry or per-host limits
+    pub fn new(max_size_bytes: u64) -> Self {
+        CacheConfig {
+            max_size_bytes,
+            eviction_policy: EvictionPolicy::Lru,
+            max_entry_bytes: None,
+            per_host_quota_bytes: None,
+            compress_bodies: false,
+            stats_path_depth: 2,
+        }
+    }
+}
+
+/// Approximate per-row cost of timestamps, integers and SQLite bookkeeping
+const ROW_OVERHEAD_BYTES: u64 = 128;
+
+/// Columns read by `row_to_response`, in order
+const ENTRY_COLUMNS: &str = "cache_key, status_code, headers_json, body, cached_at, expires_at, etag, \
+     last_modified, body_size, stored_body_size, body_encoding";
+
+/// `body_encoding` value for bodies gzipped at rest
+const GZIP_ENCODING: &str = "gzip";
+
+/// Headers a 304 must not overwrite on the stored response, since they
+/// describe the stored body's framing and encoding (RFC 9111 §3.2, §4.3.4)
+const NOT_MODIFIED_EXCLUDED_HEADERS: &[&str] = &[
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+    "content-range",
+];
+
+fn row_to_response(row: &rusqlite::Row) -> rusqlite::Result<CachedResponse> {
+    let stored = read_body(row, 3)?.unwrap_or_default();
+    let encoding: Option<String> = row.get(10)?;
+    let body = match encoding.as_deref() {
+        Some(GZIP_ENCODING) => optimization::decompress(&stored).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Blob, Box::new(e))
+        })?,
+        _ => stored,
+    };
+    Ok(CachedResponse {
+        cache_key: row.get(0)?,
+        status_code: row.get(1)?,
+        headers_json: row.get(2)?,
+        body,
+        cached_at: row.get(4)?,
+        expires_at: row.get(5)?,
+        etag: row.get(6)?,
+        last_modified: row.get(7)?,
+        body_size: row.get::<_, i64>(8)? as u64,
+        stored_body_size: row.get::<_, i64>(9)? as u64,
+    })
+}
+
+impl CachedResponse {
+    /// Request headers for revalidating this entry with the origin
+    /// (`If-None-Match` from the ETag, `If-Modified-Since` from Last-Modified)
+    pub fn conditional_headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        if let Some(ref etag) = self.etag {
+            headers.insert("If-None-Match".to_string(), etag.clone());
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            headers.insert("If-Modified-Since".to_string(), last_modified.clone());
+        }
+        headers
+    }
+
+    /// Body as UTF-8 text, if it is valid UTF-8
+    pub fn body_text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+
+    /// Whether this entry carries a validator and can be revalidated
+    pub fn can_revalidate(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+/// Merge the headers of a 304 response into a stored headers JSON object.
+/// Header names are compared case-insensitively; the 304's spelling wins.
+fn merge_headers(stored_json: &str, update_json: &str) -> String {
+    let mut merged = match serde_json::from_str::<serde_json::Value>(stored_json) {
+        Ok(serde_json::Value::Object(map)) => map,
+        _ => serde_json::Map::new(),
+    };
+
+    if let Ok(serde_json::Value::Object(update)) =
+        serde_json::from_str::<serde_json::Value>(update_json)
+    {
+        for (name, value) in update {
+            let lower = name.to_ascii_lowercase();
+            if NOT_MODIFIED_EXCLUDED_HEADERS.contains(&lower.as_str()) {
+                continue;
+            }
+            merged.retain(|k, _| k.to_ascii_lowercase() != lower);
+            merged.insert(name, value);
+        }
+    }
+
+    serde_json::Value::Object(merged).to_string()
+}
+
+/// Fields of a response being written to the cache
+struct NewEntry<'a> {
+    method: &'a str,
+    url: &'a str,
+    status_code: u16,
+    headers_json: &'a str,
+    body: &'a [u8],
+    ttl_seconds: u64,
+    etag: Option<&'a str>,
+    last_modified: Option<&'a str>,
+    /// Surrogate keys supplied by the caller, on top of any in the headers
+    tags: &'a [&'a str],
+}
+
+/// Normalized request header names listed in a `Vary` value: lower-cased,
+/// sorted and de-duplicated so equivalent headers produce the same key
+pub fn vary_header_names(vary: &str) -> Vec<String> {
+    let mut names: Vec<String> = vary
+        .split(',')
+        .map(|n| n.trim().to_ascii_lowercase())
+        .filter(|n| !n.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Collapse whitespace so `en-US,  hi` and `en-US, hi` select the same variant
+fn normalize_header_value(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Host (with port, if any) of a URL, lower-cased. Empty if there is none.
+pub(crate) fn url_host(url: &str) -> String {
+    let rest = url.split_once("://").map(|(_, r)| r).unwrap_or(url);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host = authority
+        .rsplit_once('@')
+        .map(|(_, h)| h)
+        .unwrap_or(authority);
+    host.to_ascii_lowercase()
+}
+
+/// Path of a URL without query or fragment (`/` if empty)
+pub(crate) fn url_path(url: &str) -> String {
+    let rest = url.split_once("://").map(|(_, r)| r).unwrap_or(url);
+    let rest = rest.split(['?', '#']).next().unwrap_or("");
+    match rest.find('/') {
+        Some(i) => rest[i..].to_string(),
+        None => "/".to_string(),
+    }
+}
+
+/// Surrogate keys from `Surrogate-Key` (space separated), `Cache-Tag`
+/// (comma separated) and the caller, stored as ` a b c ` so a single key can
+/// be matched with `instr`
+fn surrogate_keys(headers: &HashMap<String, String>, extra: &[&str]) -> String {
+    let mut keys: Vec<&str> = Vec::new();
+    if let Some(v) = headers.get("surrogate-key") {
+        keys.extend(v.split_whitespace());
+    }
+    if let Some(v) = headers.get("cache-tag") {
+        keys.extend(v.split(',').flat_map(str::split_whitespace));
+    }
+    keys.extend(extra.iter().flat_map(|t| t.split_whitespace()));
+    keys.sort_unstable();
+    keys.dedup();
+
+    if keys.is_empty() {
+        String::new()
+    } else {
+        format!(" {} ", keys.join(" "))
+    }
+}
+
+/// Seconds past expiry an entry with these headers may still be served
+fn stale_window(headers_json: &str) -> u64 {
+    parse_headers(headers_json)
+        .get("cache-control")
+        .map(|v| CacheControl::parse(v).stale_window_seconds())
+        .unwrap_or(0)
+}
+
 /// HTTP response cache backed by SQLite
 pub struct HttpCache {
     conn: Mutex<Connection>,
-    max_size_bytes: u64,
-    hit_count: Mutex<u64>,
-    miss_count: Mutex<u64>,
+    config: CacheConfig,
+    /// GDSF aging value `L`: priority of the last evicted entry
+    gdsf_inflation: Mutex<f64>,
 }
 
 impl HttpCache {
     pub fn new(db_path: &str, max_size_bytes: u64) -> Result<Self, CacheError> {
+        Self::with_config(db_path, CacheConfig::new(max_size_bytes))
+    }
+
+    /// Create a cache with an explicit eviction policy and limits
+    pub fn with_config(db_path: &str, config: CacheConfig) -> Result<Self, CacheError> {
         let conn = if db_path == ":memory:" {
             Connection::open_in_memory()?
         } else {
@@ -69,11 +334,11 @@ impl HttpCache {
 
         let cache = HttpCache {
             conn: Mutex::new(conn),
-            max_size_bytes,
-            hit_count: Mutex::new(0),
-            miss_count: Mutex::new(0),
+            config,
+            gdsf_inflation: Mutex::new(0.0),
         };
         cache.initialize_db()?;
+        cache.load_gdsf_inflation()?;
         Ok(cache)
     }
 
@@ -85,13 +350,34 @@ impl HttpCache {
                 cache_key TEXT PRIMARY KEY,
                 status_code INTEGER NOT NULL,
                 headers_json TEXT NOT NULL DEFAULT '{}',
-                body TEXT NOT NULL,
+                body BLOB NOT NULL,
                 cached_at TEXT NOT NULL,
                 expires_at TEXT NOT NULL,
                 etag TEXT,
                 last_modified TEXT,
                 body_size INTEGER NOT NULL,
-                last_accessed_at TEXT NOT NULL
+                last_accessed_at TEXT NOT NULL,
+                stale_until TEXT,
+                base_key TEXT,
+                host TEXT NOT NULL DEFAULT '',
+                entry_size INTEGER NOT NULL DEFAULT 0,
+                access_count INTEGER NOT NULL DEFAULT 0,
+                gdsf_priority REAL NOT NULL DEFAULT 0,
+                url TEXT,
+                path TEXT,
+                surrogate_keys TEXT NOT NULL DEFAULT '',
+                stored_body_size INTEGER,
+                body_encoding TEXT
+            );
+
+            CREATE TABLE IF NOT EXISTS cache_meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS http_cache_vary (
+                base_key TEXT PRIMARY KEY,
+                vary TEXT NOT NULL
             );
 
             CREATE INDEX IF NOT EXISTS idx_cache_expires ON http_cache(expires_at);
@@ -99,6 +385,73 @@ impl HttpCache {
             CREATE INDEX IF NOT EXISTS idx_cache_size ON http_cache(body_size);
             ",
         )?;
+
+        // Columns added after the initial schema
+        add_column_if_missing(&conn, "http_cache", "stale_until", "TEXT")?;
+        add_column_if_missing(&conn, "http_cache", "base_key", "TEXT")?;
+        add_column_if_missing(&conn, "http_cache", "host", "TEXT NOT NULL DEFAULT ''")?;
+        add_column_if_missing(
+            &conn,
+            "http_cache",
+            "entry_size",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(
+            &conn,
+            "http_cache",
+            "access_count",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(
+            &conn,
+            "http_cache",
+            "gdsf_priority",
+            "REAL NOT NULL DEFAULT 0",
+        )?;
+        // Rows cached before URLs were stored can't match prefix/glob invalidation
+        add_column_if_missing(&conn, "http_cache", "url", "TEXT")?;
+        add_column_if_missing(&conn, "http_cache", "path", "TEXT")?;
+        add_column_if_missing(
+            &conn,
+            "http_cache",
+            "surrogate_keys",
+            "TEXT NOT NULL DEFAULT ''",
+        )?;
+        add_column_if_missing(&conn, "http_cache", "stored_body_size", "INTEGER")?;
+        add_column_if_missing(&conn, "http_cache", "body_encoding", "TEXT")?;
+        conn.execute_batch(
+            "
+            UPDATE http_cache SET stale_until = expires_at WHERE stale_until IS NULL;
+            UPDATE http_cache SET base_key = cache_key WHERE base_key IS NULL;
+            UPDATE http_cache SET stored_body_size = body_size WHERE stored_body_size IS NULL;
+            UPDATE http_cache
+               SET entry_size = body_size + LENGTH(headers_json) + LENGTH(cache_key) * 2 + 128
+             WHERE entry_size = 0;
+            CREATE INDEX IF NOT EXISTS idx_cache_host ON http_cache(host);
+            CREATE INDEX IF NOT EXISTS idx_cache_url ON http_cache(url);
+            CREATE INDEX IF NOT EXISTS idx_cache_stale_until ON http_cache(stale_until);
+            CREATE INDEX IF NOT EXISTS idx_cache_base_key ON http_cache(base_key);
+            ",
+        )?;
+        stats::initialize(&conn)?;
+        Ok(())
+    }
+
+    fn load_gdsf_inflation(&self) -> Result<(), CacheError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM cache_meta WHERE key = 'gdsf_inflation'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let (Some(v), Ok(mut l)) = (value, self.gdsf_inflation.lock()) {
+            *l = v.parse().unwrap_or(0.0);
+        }
         Ok(())
     }
 
@@ -112,165 +465,709 @@ impl HttpCache {
         base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, hash)
     }
 
+    /// Generate the key for one variant of a response that carries `Vary`.
+    ///
+    /// `vary` holds normalized header names (see `vary_header_names`) and
+    /// `request_headers` uses lower-cased names. Without any Vary headers this
+    /// is the same as `generate_key`.
+    pub fn generate_variant_key(
+        method: &str,
+        url: &str,
+        vary: &[String],
+        request_headers: &HashMap<String, String>,
+    ) -> String {
+        if vary.is_empty() {
+            return Self::generate_key(method, url);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(method.as_bytes());
+        hasher.update(b":");
+        hasher.update(url.as_bytes());
+        for name in vary {
+            hasher.update(b"\n");
+            hasher.update(name.as_bytes());
+            // A missing header is a distinct variant from an empty one
+            if let Some(value) = request_headers.get(name) {
+                hasher.update(b"=");
+                hasher.update(normalize_header_value(value).as_bytes());
+            }
+        }
+        let hash = hasher.finalize();
+        base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, hash)
+    }
+
+    /// Find the key of the stored variant matching these request headers
+    fn resolve_key(
+        conn: &Connection,
+        method: &str,
+        url: &str,
+        request_headers: &HashMap<String, String>,
+    ) -> Result<String, CacheError> {
+        let base_key = Self::generate_key(method, url);
+        let vary: Option<String> = conn
+            .query_row(
+                "SELECT vary FROM http_cache_vary WHERE base_key = ?1",
+                params![base_key],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(match vary {
+            Some(v) => {
+                let names: Vec<String> = v.split(',').map(str::to_string).collect();
+                Self::generate_variant_key(method, url, &names, request_headers)
+            }
+            None => base_key,
+        })
+    }
+
     /// Get a cached response (returns None if expired or not found)
     pub fn get(&self, method: &str, url: &str) -> Result<Option<CachedResponse>, CacheError> {
-        let cache_key = Self::generate_key(method, url);
+        self.get_with_headers(method, url, "{}")
+    }
+
+    /// Get the cached variant matching the request's headers (for responses
+    /// stored with `Vary`). Returns None if expired or not found.
+    pub fn get_with_headers(
+        &self,
+        method: &str,
+        url: &str,
+        request_headers_json: &str,
+    ) -> Result<Option<CachedResponse>, CacheError> {
+        let request_headers = parse_headers(request_headers_json);
         let conn = self.conn.lock().map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        let cache_key = Self::resolve_key(&conn, method, url, &request_headers)?;
         let now = Utc::now().to_rfc3339();
 
         let result = conn.query_row(
-            "SELECT cache_key, status_code, headers_json, body, cached_at, expires_at, 
-                    etag, last_modified, body_size
-             FROM http_cache 
-             WHERE cache_key = ?1 AND expires_at > ?2",
+            &format!(
+                "SELECT {} FROM http_cache WHERE cache_key = ?1 AND expires_at > ?2",
+                ENTRY_COLUMNS
+            ),
             params![cache_key, now],
-            |row| {
-                Ok(CachedResponse {
-                    cache_key: row.get(0)?,
-                    status_code: row.get(1)?,
-                    headers_json: row.get(2)?,
-                    body: row.get(3)?,
-                    cached_at: row.get(4)?,
-                    expires_at: row.get(5)?,
-                    etag: row.get(6)?,
-                    last_modified: row.get(7)?,
-                    body_size: row.get::<_, i64>(8)? as u64,
-                })
-            },
+            row_to_response,
         );
 
         match result {
             Ok(entry) => {
-                // Update last_accessed_at for LRU
-                let _ = conn.execute(
-                    "UPDATE http_cache SET last_accessed_at = ?1 WHERE cache_key = ?2",
-                    params![now, cache_key],
-                );
-                if let Ok(mut hits) = self.hit_count.lock() {
-                    *hits += 1;
-                }
+                self.touch(&conn, &cache_key, &now);
+                self.record(&conn, CacheEvent::Hit, url, 1);
                 Ok(Some(entry))
             }
             Err(rusqlite::Error::QueryReturnedNoRows) => {
-                if let Ok(mut misses) = self.miss_count.lock() {
-                    *misses += 1;
-                }
+                self.record(&conn, CacheEvent::Miss, url, 1);
                 Ok(None)
             }
             Err(e) => Err(CacheError::DatabaseError(e.to_string())),
         }
     }
 
-    /// Store a response in the cache
+    /// Store a response in the cache. Bodies are stored as raw bytes, so
+    /// images, protobuf and pre-compressed payloads need no re-encoding.
     pub fn put(
         &self,
         method: &str,
         url: &str,
         status_code: u16,
         headers_json: &str,
-        body: &str,
+        body: &[u8],
         ttl_seconds: u64,
         etag: Option<&str>,
         last_modified: Option<&str>,
     ) -> Result<(), CacheError> {
-        let cache_key = Self::generate_key(method, url);
+        self.store(
+            NewEntry {
+                method,
+                url,
+                status_code,
+                headers_json,
+                body,
+                ttl_seconds,
+                etag,
+                last_modified,
+                tags: &[],
+            },
+            &HashMap::new(),
+        )
+    }
+
+    /// Write an entry under the variant key its `Vary` header and the
+    /// request headers select
+    fn store(
+        &self,
+        entry: NewEntry,
+        request_headers: &HashMap<String, String>,
+    ) -> Result<(), CacheError> {
+        let base_key = Self::generate_key(entry.method, entry.url);
+        let response_headers = parse_headers(entry.headers_json);
+        let vary = response_headers
+            .get("vary")
+            .map(|v| vary_header_names(v))
+            .unwrap_or_default();
+        let surrogate_keys = surrogate_keys(&response_headers, entry.tags);
+        let cache_key = Self::generate_variant_key(entry.method, entry.url, &vary, request_headers);
+
         let now = Utc::now();
-        let expires_at = now + chrono::Duration::seconds(ttl_seconds as i64);
-        let body_size = body.len() as u64;
+        let expires_at = now + chrono::Duration::seconds(entry.ttl_seconds as i64);
+        let stale_until =
+            expires_at + chrono::Duration::seconds(stale_window(entry.headers_json) as i64);
+        let body_size = entry.body.len() as u64;
+        let compressed = if self.config.compress_bodies {
+            optimization::compress_if_beneficial(entry.body)
+        } else {
+            None
+        };
+        let (stored_body, body_encoding) = match compressed {
+            Some(ref gz) => (gz.as_slice(), Some(GZIP_ENCODING)),
+            None => (entry.body, None),
+        };
+        let stored_body_size = stored_body.len() as u64;
+        let host = url_host(entry.url);
+        let path = url_path(entry.url);
+        let entry_size = stored_body_size
+            + entry.headers_json.len() as u64
+            + (entry.url.len() + path.len() + surrogate_keys.len()) as u64
+            + (cache_key.len() + base_key.len()) as u64
+            + entry.etag.map(|e| e.len() as u64).unwrap_or(0)
+            + entry.last_modified.map(|l| l.len() as u64).unwrap_or(0)
+            + ROW_OVERHEAD_BYTES;
 
-        // Evict if necessary to make room
-        self.evict_if_needed(body_size)?;
+        let limit = self
+            .config
+            .max_entry_bytes
+            .unwrap_or(u64::MAX)
+            .min(self.config.max_size_bytes)
+            .min(self.config.per_host_quota_bytes.unwrap_or(u64::MAX));
+        if entry_size > limit {
+            return Err(CacheError::EntryTooLarge {
+                size: entry_size,
+                limit,
+            });
+        }
 
         let conn = self.conn.lock().map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+
+        // Evict if necessary to make room
+        self.evict_if_needed(&conn, &host, entry_size, &cache_key)?;
+
+        // If the origin changed which headers it varies on, the old variants
+        // are keyed under a different scheme and can never be found again
+        let previous_vary: Option<String> = conn
+            .query_row(
+                "SELECT vary FROM http_cache_vary WHERE base_key = ?1",
+                params![base_key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let vary_joined = vary.join(",");
+        let inflation = self.gdsf_inflation.lock().map(|l| *l).unwrap_or(0.0);
+        if previous_vary.unwrap_or_default() != vary_joined {
+            conn.execute(
+                "DELETE FROM http_cache WHERE base_key = ?1",
+                params![base_key],
+            )?;
+        }
+        if vary.is_empty() {
+            conn.execute(
+                "DELETE FROM http_cache_vary WHERE base_key = ?1",
+                params![base_key],
+            )?;
+        } else {
+            conn.execute(
+                "INSERT OR REPLACE INTO http_cache_vary (base_key, vary) VALUES (?1, ?2)",
+                params![base_key, vary_joined],
+            )?;
+        }
+
         conn.execute(
             "INSERT OR REPLACE INTO http_cache 
-             (cache_key, status_code, headers_json, body, cached_at, expires_at, etag, last_modified, body_size, last_accessed_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?5)",
+             (cache_key, status_code, headers_json, body, cached_at, expires_at, etag, last_modified, body_size,
+              last_accessed_at, stale_until, base_key, host, entry_size, access_count, gdsf_priority,
+              url, path, surrogate_keys, stored_body_size, body_encoding)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?5, ?10, ?11, ?12, ?13, 1, ?14, ?15, ?16, ?17, ?18, ?19)",
             params![
                 cache_key,
-                status_code,
-                headers_json,
-                body,
+                entry.status_code,
+                entry.headers_json,
+                stored_body,
                 now.to_rfc3339(),
                 expires_at.to_rfc3339(),
-                etag,
-                last_modified,
+                entry.etag,
+                entry.last_modified,
                 body_size as i64,
+                stale_until.to_rfc3339(),
+                base_key,
+                host,
+                entry_size as i64,
+                inflation + 1.0 / entry_size as f64,
+                entry.url,
+                path,
+                surrogate_keys,
+                stored_body_size as i64,
+                body_encoding,
             ],
         )?;
 
         Ok(())
     }
 
-    /// Invalidate a specific cache entry
+    /// Store a response using the freshness its own headers describe.
+    ///
+    /// `Cache-Control`, `Expires`, `Date` and `Age` decide whether the response
+    /// is stored and for how long; `ETag` and `Last-Modified` are picked up as
+    /// validators. `request_headers_json` are the headers the request was
+    /// sent with; they select the variant when the response has `Vary`.
+    /// `tags` are surrogate keys for `invalidate_tag`, in addition to any
+    /// the origin sent in `Surrogate-Key` / `Cache-Tag`.
+    /// Returns the decision so callers can tell what happened.
+    #[allow(clippy::too_many_arguments)]
+    pub fn put_response(
+        &self,
+        method: &str,
+        url: &str,
+        request_headers_json: &str,
+        status_code: u16,
+        headers_json: &str,
+        body: &[u8],
+        tags: &[&str],
+    ) -> Result<CacheDecision, CacheError> {
+        let headers = parse_headers(headers_json);
+        let decision = evaluate_response(method, status_code, &headers, Utc::now());
+
+        if decision.cacheable {
+            self.store(
+                NewEntry {
+                    method,
+                    url,
+                    status_code,
+                    headers_json,
+                    body,
+                    ttl_seconds: decision.ttl_seconds,
+                    etag: headers.get("etag").map(String::as_str),
+                    last_modified: headers.get("last-modified").map(String::as_str),
+                    tags,
+                },
+                &parse_headers(request_headers_json),
+            )?;
+        }
+
+        Ok(decision)
+    }
+
+    /// Get a cached response with a freshness verdict, including expired
+    /// entries that `stale-while-revalidate` / `stale-if-error` still allow.
+    ///
+    /// Returns None only if nothing is stored. Usable verdicts count as hits.
+    pub fn lookup(
+        &self,
+        method: &str,
+        url: &str,
+        request_headers_json: &str,
+        status: &NetworkStatus,
+    ) -> Result<Option<CacheLookup>, CacheError> {
+        let entry = match self.peek(method, url, request_headers_json)? {
+            Some(e) => e,
+            None => {
+                let conn = self
+                    .conn
+                    .lock()
+                    .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+                self.record(&conn, CacheEvent::Miss, url, 1);
+                return Ok(None);
+            }
+        };
+
+        let now = Utc::now();
+        let expires_at = chrono::DateTime::parse_from_rfc3339(&entry.expires_at)
+            .map(|d| d.with_timezone(&Utc))
+            .unwrap_or(now);
+        let cc = parse_headers(&entry.headers_json)
+            .get("cache-control")
+            .map(|v| CacheControl::parse(v))
+            .unwrap_or_default();
+        let freshness = freshness_verdict(&cc, expires_at, now, status.is_online);
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        if freshness.is_usable(status.is_online) {
+            self.touch(&conn, &entry.cache_key, &now.to_rfc3339());
+            self.record(&conn, CacheEvent::Hit, url, 1);
+        } else {
+            self.record(&conn, CacheEvent::Miss, url, 1);
+        }
+        drop(conn);
+
+        Ok(Some(CacheLookup { entry, freshness }))
+    }
+
+    /// Record an access for the eviction policies
+    fn touch(&self, conn: &Connection, cache_key: &str, now: &str) {
+        let inflation = self.gdsf_inflation.lock().map(|l| *l).unwrap_or(0.0);
+        let _ = conn.execute(
+            "UPDATE http_cache
+             SET last_accessed_at = ?1,
+                 access_count = access_count + 1,
+                 gdsf_priority = ?2 + (access_count + 1) * 1.0 / MAX(entry_size, 1)
+             WHERE cache_key = ?3",
+            params![now, inflation, cache_key],
+        );
+    }
+
+    /// Count an event for `url` in the persistent statistics. Failures are
+    /// ignored so statistics can never break a lookup.
+    fn record(&self, conn: &Connection, event: CacheEvent, url: &str, count: u64) {
+        let prefix = stats::path_prefix(&url_path(url), self.config.stats_path_depth);
+        let _ = stats::record(conn, event, &url_host(url), &prefix, count, Utc::now());
+    }
+
+    /// Get a cached response even if it has expired, without touching hit/miss
+    /// counters. Used to build revalidation requests for stale entries.
+    pub fn peek(
+        &self,
+        method: &str,
+        url: &str,
+        request_headers_json: &str,
+    ) -> Result<Option<CachedResponse>, CacheError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        Self::peek_locked(&conn, method, url, request_headers_json)
+    }
+
+    fn peek_locked(
+        conn: &Connection,
+        method: &str,
+        url: &str,
+        request_headers_json: &str,
+    ) -> Result<Option<CachedResponse>, CacheError> {
+        let request_headers = parse_headers(request_headers_json);
+        let cache_key = Self::resolve_key(conn, method, url, &request_headers)?;
+
+        let result = conn.query_row(
+            &format!(
+                "SELECT {} FROM http_cache WHERE cache_key = ?1",
+                ENTRY_COLUMNS
+            ),
+            params![cache_key],
+            row_to_response,
+        );
+
+        match result {
+            Ok(entry) => Ok(Some(entry)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(CacheError::DatabaseError(e.to_string())),
+        }
+    }
+
+    /// Conditional request headers for a stored entry (fresh or stale).
+    /// Returns None if nothing is cached or the entry has no validator.
+    pub fn revalidation_headers(
+        &self,
+        method: &str,
+        url: &str,
+        request_headers_json: &str,
+    ) -> Result<Option<HashMap<String, String>>, CacheError> {
+        Ok(self
+            .peek(method, url, request_headers_json)?
+            .filter(|e| e.can_revalidate())
+            .map(|e| e.conditional_headers()))
+    }
+
+    /// Apply a `304 Not Modified` to the stored entry.
+    ///
+    /// The 304's headers are merged into the stored ones, freshness is
+    /// recomputed from the merged set and the stored body is kept. Returns the
+    /// refreshed entry, or None if nothing was cached. If the new headers
+    /// forbid storage (`no-store`) the entry is removed, but still returned so
+    /// the caller can use it for this response.
+    pub fn refresh_not_modified(
+        &self,
+        method: &str,
+        url: &str,
+        request_headers_json: &str,
+        headers_json: &str,
+    ) -> Result<Option<CachedResponse>, CacheError> {
+        // Read, merge and write under one lock so a concurrent store can't
+        // be overwritten with headers merged from the older entry
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        let mut entry = match Self::peek_locked(&conn, method, url, request_headers_json)? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+
+        let merged_json = merge_headers(&entry.headers_json, headers_json);
+        let merged = parse_headers(&merged_json);
+        let now = Utc::now();
+        let decision = evaluate_response(method, entry.status_code, &merged, now);
+
+        entry.headers_json = merged_json;
+        entry.cached_at = now.to_rfc3339();
+        let expires_at = now + chrono::Duration::seconds(decision.ttl_seconds as i64);
+        let stale_until =
+            expires_at + chrono::Duration::seconds(stale_window(&entry.headers_json) as i64);
+        entry.expires_at = expires_at.to_rfc3339();
+        if let Some(etag) = merged.get("etag") {
+            entry.etag = Some(etag.clone());
+        }
+        if let Some(last_modified) = merged.get("last-modified") {
+            entry.last_modified = Some(last_modified.clone());
+        }
+
+        self.record(&conn, CacheEvent::Revalidation, url, 1);
+        if !decision.cacheable {
+            conn.execute(
+                "DELETE FROM http_cache WHERE cache_key = ?1",
+                params![entry.cache_key],
+            )?;
+            return Ok(Some(entry));
+        }
+
+        // The size counts the headers and validators, so shift it by how
+        // much they grew or shrank
+        conn.execute(
+            "UPDATE http_cache
+             SET entry_size = entry_size
+                     - LENGTH(CAST(headers_json AS BLOB)) - COALESCE(LENGTH(CAST(etag AS BLOB)), 0)
+                     - COALESCE(LENGTH(CAST(last_modified AS BLOB)), 0)
+                     + LENGTH(CAST(?1 AS BLOB)) + COALESCE(LENGTH(CAST(?4 AS BLOB)), 0)
+                     + COALESCE(LENGTH(CAST(?5 AS BLOB)), 0),
+                 headers_json = ?1, cached_at = ?2, expires_at = ?3, etag = ?4,
+                 last_modified = ?5, last_accessed_at = ?2, stale_until = ?6
+             WHERE cache_key = ?7",
+            params![
+                entry.headers_json,
+                entry.cached_at,
+                entry.expires_at,
+                entry.etag,
+                entry.last_modified,
+                stale_until.to_rfc3339(),
+                entry.cache_key,
+            ],
+        )?;
+
+        Ok(Some(entry))
+    }
+
+    /// Invalidate a specific cache entry (all of its Vary variants)
     pub fn invalidate(&self, method: &str, url: &str) -> Result<bool, CacheError> {
-        let cache_key = Self::generate_key(method, url);
+        let base_key = Self::generate_key(method, url);
         let conn = self.conn.lock().map_err(|e| CacheError::DatabaseError(e.to_string()))?;
         let rows = conn.execute(
-            "DELETE FROM http_cache WHERE cache_key = ?1",
-            params![cache_key],
+            "DELETE FROM http_cache WHERE base_key = ?1",
+            params![base_key],
+        )?;
+        conn.execute(
+            "DELETE FROM http_cache_vary WHERE base_key = ?1",
+            params![base_key],
         )?;
         Ok(rows > 0)
     }
 
-    /// Clear expired entries
+    /// Invalidate every entry whose URL starts with `prefix`. A prefix that
+    /// starts with `/` is matched against the path on any host.
+    pub fn invalidate_prefix(&self, prefix: &str) -> Result<u64, CacheError> {
+        let column = if prefix.starts_with('/') {
+            "path"
+        } else {
+            "url"
+        };
+        self.invalidate_where(
+            &format!(
+                "{col} IS NOT NULL AND substr({col}, 1, length(?1)) = ?1",
+                col = column
+            ),
+            prefix,
+        )
+    }
+
+    /// Invalidate every entry whose URL matches a glob (`*` matches any run
+    /// of characters including `/`, `?` one character, `[...]` a class).
+    /// A pattern that starts with `/` is matched against the path on any host.
+    pub fn invalidate_glob(&self, pattern: &str) -> Result<u64, CacheError> {
+        let column = if pattern.starts_with('/') {
+            "path"
+        } else {
+            "url"
+        };
+        self.invalidate_where(&format!("{} GLOB ?1", column), pattern)
+    }
+
+    /// Invalidate every entry tagged with a surrogate key
+    pub fn invalidate_tag(&self, tag: &str) -> Result<u64, CacheError> {
+        let tag = tag.trim();
+        if tag.is_empty() || tag.contains(char::is_whitespace) {
+            return Ok(0);
+        }
+        self.invalidate_where("instr(surrogate_keys, ' ' || ?1 || ' ') > 0", tag)
+    }
+
+    fn invalidate_where(&self, condition: &str, arg: &str) -> Result<u64, CacheError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        let rows = conn.execute(
+            &format!("DELETE FROM http_cache WHERE {}", condition),
+            params![arg],
+        )?;
+        conn.execute(
+            "DELETE FROM http_cache_vary WHERE base_key NOT IN (SELECT base_key FROM http_cache)",
+            [],
+        )?;
+        Ok(rows as u64)
+    }
+
+    /// Clear expired entries that are past any stale-serving window
     pub fn cleanup_expired(&self) -> Result<u64, CacheError> {
         let conn = self.conn.lock().map_err(|e| CacheError::DatabaseError(e.to_string()))?;
         let now = Utc::now().to_rfc3339();
         let rows = conn.execute(
-            "DELETE FROM http_cache WHERE expires_at <= ?1",
+            "DELETE FROM http_cache WHERE COALESCE(stale_until, expires_at) <= ?1",
             params![now],
         )?;
+        stats::prune(
+            &conn,
+            Utc::now() - chrono::Duration::days(stats::RETENTION_DAYS),
+        )?;
         Ok(rows as u64)
     }
 
-    /// Evict least-recently-used entries if cache is too large
-    fn evict_if_needed(&self, new_entry_size: u64) -> Result<(), CacheError> {
-        let conn = self.conn.lock().map_err(|e| CacheError::DatabaseError(e.to_string()))?;
-
-        let total_size: u64 = conn.query_row(
-            "SELECT COALESCE(SUM(body_size), 0) FROM http_cache",
-            [],
-            |row| row.get::<_, i64>(0).map(|v| v as u64),
-        )?;
+    /// Make room for an entry of `new_entry_size` bytes on `host`, first
+    /// within the host's quota and then within the total budget. The entry
+    /// being replaced (`replacing_key`) doesn't count and is never evicted.
+    fn evict_if_needed(
+        &self,
+        conn: &Connection,
+        host: &str,
+        new_entry_size: u64,
+        replacing_key: &str,
+    ) -> Result<(), CacheError> {
+        if let Some(quota) = self.config.per_host_quota_bytes {
+            let host_size = Self::stored_size(conn, Some(host), replacing_key)?;
+            if host_size + new_entry_size > quota {
+                self.evict(
+                    conn,
+                    Some(host),
+                    host_size + new_entry_size - quota,
+                    replacing_key,
+                )?;
+            }
+        }
 
-        if total_size + new_entry_size <= self.max_size_bytes {
+        let total_size = Self::stored_size(conn, None, replacing_key)?;
+        if total_size + new_entry_size <= self.config.max_size_bytes {
             return Ok(());
         }
 
-        // First, remove expired entries
+        // First, remove entries that can no longer be served at all
         let now = Utc::now().to_rfc3339();
         conn.execute(
-            "DELETE FROM http_cache WHERE expires_at <= ?1",
-            params![now],
+            "DELETE FROM http_cache WHERE COALESCE(stale_until, expires_at) <= ?1 AND cache_key != ?2",
+            params![now, replacing_key],
         )?;
 
-        // If still over limit, remove LRU entries
-        let new_total: u64 = conn.query_row(
-            "SELECT COALESCE(SUM(body_size), 0) FROM http_cache",
-            [],
+        // If still over limit, evict by policy until the new entry fits
+        let total_size = Self::stored_size(conn, None, replacing_key)?;
+        if total_size + new_entry_size > self.config.max_size_bytes {
+            self.evict(
+                conn,
+                None,
+                total_size + new_entry_size - self.config.max_size_bytes,
+                replacing_key,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Total `entry_size` of stored entries, optionally for one host
+    fn stored_size(
+        conn: &Connection,
+        host: Option<&str>,
+        excluding_key: &str,
+    ) -> Result<u64, CacheError> {
+        let size = conn.query_row(
+            "SELECT COALESCE(SUM(entry_size), 0) FROM http_cache
+             WHERE cache_key != ?1 AND (?2 IS NULL OR host = ?2)",
+            params![excluding_key, host],
             |row| row.get::<_, i64>(0).map(|v| v as u64),
         )?;
+        Ok(size)
+    }
 
-        if new_total + new_entry_size > self.max_size_bytes {
-            // Remove oldest accessed entries until we have space
+    /// Delete entries in eviction-policy order until at least `bytes_needed`
+    /// have been freed. Returns the number of entries evicted.
+    fn evict(
+        &self,
+        conn: &Connection,
+        host: Option<&str>,
+        bytes_needed: u64,
+        excluding_key: &str,
+    ) -> Result<u64, CacheError> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT cache_key, entry_size, gdsf_priority, host, COALESCE(path, '') FROM http_cache
+             WHERE cache_key != ?1 AND (?2 IS NULL OR host = ?2)
+             ORDER BY {}",
+            self.config.eviction_policy.order_by()
+        ))?;
+        let mut rows = stmt.query(params![excluding_key, host])?;
+
+        let mut victims = Vec::new();
+        let mut freed = 0u64;
+        let mut max_priority = 0.0f64;
+        while freed < bytes_needed {
+            let row = match rows.next()? {
+                Some(r) => r,
+                None => break,
+            };
+            victims.push((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ));
+            freed += row.get::<_, i64>(1)? as u64;
+            max_priority = max_priority.max(row.get::<_, f64>(2)?);
+        }
+        drop(rows);
+        drop(stmt);
+
+        let now = Utc::now();
+        for (key, victim_host, path) in &victims {
+            conn.execute("DELETE FROM http_cache WHERE cache_key = ?1", params![key])?;
+            let prefix = stats::path_prefix(path, self.config.stats_path_depth);
+            let _ = stats::record(conn, CacheEvent::Eviction, victim_host, &prefix, 1, now);
+        }
+
+        if self.config.eviction_policy == EvictionPolicy::Gdsf
+            && !victims.is_empty()
+            && let Ok(mut inflation) = self.gdsf_inflation.lock()
+        {
+            *inflation = inflation.max(max_priority);
             conn.execute(
-                "DELETE FROM http_cache WHERE cache_key IN (
-                    SELECT cache_key FROM http_cache 
-                    ORDER BY last_accessed_at ASC 
-                    LIMIT 10
-                )",
-                [],
+                "INSERT OR REPLACE INTO cache_meta (key, value) VALUES ('gdsf_inflation', ?1)",
+                params![inflation.to_string()],
             )?;
         }
 
-        Ok(())
+        Ok(victims.len() as u64)
     }
 
     /// Clear entire cache
     pub fn clear(&self) -> Result<(), CacheError> {
         let conn = self.conn.lock().map_err(|e| CacheError::DatabaseError(e.to_string()))?;
         conn.execute("DELETE FROM http_cache", [])?;
+        conn.execute("DELETE FROM http_cache_vary", [])?;
+        conn.execute("DELETE FROM cache_meta WHERE key = 'gdsf_inflation'", [])?;
+        if let Ok(mut inflation) = self.gdsf_inflation.lock() {
+            *inflation = 0.0;
+        }
         Ok(())
     }
 
@@ -284,14 +1181,25 @@ impl HttpCache {
             |row| row.get::<_, i64>(0).map(|v| v as u64),
         )?;
 
-        let total_size_bytes: u64 = conn.query_row(
-            "SELECT COALESCE(SUM(body_size), 0) FROM http_cache",
+        let (total_size_bytes, body_bytes, stored_body_bytes) = conn.query_row(
+            "SELECT COALESCE(SUM(entry_size), 0), COALESCE(SUM(body_size), 0),
+                    COALESCE(SUM(stored_body_size), 0)
+             FROM http_cache",
             [],
-            |row| row.get::<_, i64>(0).map(|v| v as u64),
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    row.get::<_, i64>(1)? as u64,
+                    row.get::<_, i64>(2)? as u64,
+                ))
+            },
         )?;
 
-        let hits = self.hit_count.lock().map(|g| *g).unwrap_or(0);
-        let misses = self.miss_count.lock().map(|g| *g).unwrap_or(0);
+        let counters = stats::query(&conn, StatsScope::Global, None)?;
+        let (hits, misses, revalidations, evictions) = counters
+            .first()
+            .map(|c| (c.hits, c.misses, c.revalidations, c.evictions))
+            .unwrap_or_default();
         let total_requests = hits + misses;
         let hit_rate = if total_requests > 0 {
             hits as f64 / total_requests as f64
@@ -302,11 +1210,41 @@ impl HttpCache {
         Ok(CacheStats {
             total_entries,
             total_size_bytes,
+            body_bytes,
+            stored_body_bytes,
             hit_count: hits,
             miss_count: misses,
+            revalidation_count: revalidations,
+            eviction_count: evictions,
             hit_rate,
         })
     }
+
+    /// Hit/miss/revalidation/eviction counters broken down by `scope`,
+    /// over the last `window_hours` (all time if None), busiest first.
+    /// Counters are kept in hourly buckets for `stats::RETENTION_DAYS` days.
+    pub fn stats_breakdown(
+        &self,
+        scope: StatsScope,
+        window_hours: Option<u32>,
+    ) -> Result<Vec<CounterStats>, CacheError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        let since = window_hours.map(|h| Utc::now() - chrono::Duration::hours(h as i64));
+        Ok(stats::query(&conn, scope, since)?)
+    }
+
+    /// Reset all statistics counters (stored entries are kept)
+    pub fn reset_stats(&self) -> Result<(), CacheError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| CacheError::DatabaseError(e.to_string()))?;
+        stats::reset(&conn)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -321,14 +1259,25 @@ mod tests {
     fn test_put_and_get() {
         let cache = create_test_cache();
 
-        cache.put("GET", "https://api.test.com/users", 200, "{}", "{\"users\":[]}", 300, None, None).unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.test.com/users",
+                200,
+                "{}",
+                b"{\"users\":[]}",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
 
         let result = cache.get("GET", "https://api.test.com/users").unwrap();
         assert!(result.is_some());
 
         let entry = result.unwrap();
         assert_eq!(entry.status_code, 200);
-        assert_eq!(entry.body, "{\"users\":[]}");
+        assert_eq!(entry.body, b"{\"users\":[]}");
     }
 
     #[test]
@@ -352,7 +1301,18 @@ mod tests {
     fn test_invalidate() {
         let cache = create_test_cache();
 
-        cache.put("GET", "https://api.com/data", 200, "{}", "data", 300, None, None).unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/data",
+                200,
+                "{}",
+                b"data",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
         assert!(cache.get("GET", "https://api.com/data").unwrap().is_some());
 
         cache.invalidate("GET", "https://api.com/data").unwrap();
@@ -363,8 +1323,12 @@ mod tests {
     fn test_clear() {
         let cache = create_test_cache();
 
-        cache.put("GET", "https://a.com", 200, "{}", "a", 300, None, None).unwrap();
-        cache.put("GET", "https://b.com", 200, "{}", "b", 300, None, None).unwrap();
+        cache
+            .put("GET", "https://a.com", 200, "{}", b"a", 300, None, None)
+            .unwrap();
+        cache
+            .put("GET", "https://b.com", 200, "{}", b"b", 300, None, None)
+            .unwrap();
 
         cache.clear().unwrap();
 
@@ -376,7 +1340,18 @@ mod tests {
     fn test_stats() {
         let cache = create_test_cache();
 
-        cache.put("GET", "https://a.com", 200, "{}", "response-body", 300, None, None).unwrap();
+        cache
+            .put(
+                "GET",
+                "https://a.com",
+                200,
+                "{}",
+                b"response-body",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
 
         // Hit
         cache.get("GET", "https://a.com").unwrap();
@@ -394,26 +1369,1135 @@ mod tests {
     fn test_etag_storage() {
         let cache = create_test_cache();
 
-        cache.put(
-            "GET", "https://api.com/data", 200, "{}",
-            "data", 300,
-            Some("\"abc123\""),
-            Some("Wed, 01 Jan 2025 00:00:00 GMT"),
-        ).unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/data",
+                200,
+                "{}",
+                b"data",
+                300,
+                Some("\"abc123\""),
+                Some("Wed, 01 Jan 2025 00:00:00 GMT"),
+            )
+            .unwrap();
 
         let entry = cache.get("GET", "https://api.com/data").unwrap().unwrap();
         assert_eq!(entry.etag, Some("\"abc123\"".to_string()));
         assert_eq!(entry.last_modified, Some("Wed, 01 Jan 2025 00:00:00 GMT".to_string()));
     }
 
+    #[test]
+    fn test_put_response_uses_headers() {
+        let cache = create_test_cache();
+
+        let headers = r#"{"Cache-Control":"max-age=600","ETag":"\"v1\""}"#;
+        let decision = cache
+            .put_response(
+                "GET",
+                "https://api.com/feed",
+                "{}",
+                200,
+                headers,
+                b"feed",
+                &[],
+            )
+            .unwrap();
+        assert!(decision.cacheable);
+        assert_eq!(decision.ttl_seconds, 600);
+
+        let entry = cache.get("GET", "https://api.com/feed").unwrap().unwrap();
+        assert_eq!(entry.etag, Some("\"v1\"".to_string()));
+        assert_eq!(entry.headers_json, headers);
+    }
+
+    #[test]
+    fn test_put_response_no_store() {
+        let cache = create_test_cache();
+
+        let decision = cache
+            .put_response(
+                "GET",
+                "https://api.com/me",
+                "{}",
+                200,
+                r#"{"Cache-Control":"no-store"}"#,
+                b"secret",
+                &[],
+            )
+            .unwrap();
+        assert!(!decision.cacheable);
+        assert!(cache.get("GET", "https://api.com/me").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_conditional_headers_for_stale_entry() {
+        let cache = create_test_cache();
+
+        cache
+            .put(
+                "GET",
+                "https://api.com/data",
+                200,
+                "{}",
+                b"data",
+                0,
+                Some("\"abc123\""),
+                Some("Wed, 01 Jan 2025 00:00:00 GMT"),
+            )
+            .unwrap();
+
+        // Expired, so a normal get misses, but revalidation still works
+        assert!(cache.get("GET", "https://api.com/data").unwrap().is_none());
+
+        let headers = cache
+            .revalidation_headers("GET", "https://api.com/data", "{}")
+            .unwrap()
+            .unwrap();
+        assert_eq!(headers.get("If-None-Match").unwrap(), "\"abc123\"");
+        assert_eq!(
+            headers.get("If-Modified-Since").unwrap(),
+            "Wed, 01 Jan 2025 00:00:00 GMT"
+        );
+
+        cache
+            .put(
+                "GET",
+                "https://api.com/plain",
+                200,
+                "{}",
+                b"data",
+                0,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(
+            cache
+                .revalidation_headers("GET", "https://api.com/plain", "{}")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_refresh_not_modified() {
+        let cache = create_test_cache();
+
+        let stored =
+            r#"{"Content-Type":"application/json","ETag":"\"v1\"","Cache-Control":"max-age=0"}"#;
+        cache
+            .put(
+                "GET",
+                "https://api.com/feed",
+                200,
+                stored,
+                b"[1,2,3]",
+                0,
+                Some("\"v1\""),
+                None,
+            )
+            .unwrap();
+        assert!(cache.get("GET", "https://api.com/feed").unwrap().is_none());
+
+        let refreshed = cache
+            .refresh_not_modified(
+                "GET",
+                "https://api.com/feed",
+                "{}",
+                r#"{"cache-control":"max-age=120","etag":"\"v2\"","Content-Length":"0"}"#,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(refreshed.body, b"[1,2,3]");
+        assert_eq!(refreshed.etag, Some("\"v2\"".to_string()));
+
+        let entry = cache.get("GET", "https://api.com/feed").unwrap().unwrap();
+        assert_eq!(entry.body, b"[1,2,3]");
+        let headers = parse_headers(&entry.headers_json);
+        assert_eq!(headers.get("cache-control").unwrap(), "max-age=120");
+        assert_eq!(headers.get("content-type").unwrap(), "application/json");
+        assert!(!headers.contains_key("content-length"));
+    }
+
+    #[test]
+    fn test_refresh_not_modified_keeps_encoding_and_size() {
+        let cache = create_test_cache();
+        let stored = r#"{"Content-Encoding":"br","Cache-Control":"max-age=0"}"#;
+        cache
+            .put(
+                "GET",
+                "https://api.com/feed",
+                200,
+                stored,
+                b"data",
+                0,
+                Some("\"v1\""),
+                None,
+            )
+            .unwrap();
+
+        let update = format!(
+            r#"{{"content-encoding":"identity","transfer-encoding":"chunked","content-range":"bytes 0-1/4","etag":"\"v2-longer\"","x-padding":"{}"}}"#,
+            "p".repeat(500)
+        );
+        let refreshed = cache
+            .refresh_not_modified("GET", "https://api.com/feed", "{}", &update)
+            .unwrap()
+            .unwrap();
+        let headers = parse_headers(&refreshed.headers_json);
+        assert_eq!(headers.get("content-encoding").unwrap(), "br");
+        assert!(!headers.contains_key("transfer-encoding"));
+        assert!(!headers.contains_key("content-range"));
+
+        // Same size as storing the merged response outright
+        let fresh = create_test_cache();
+        fresh
+            .put(
+                "GET",
+                "https://api.com/feed",
+                200,
+                &refreshed.headers_json,
+                b"data",
+                0,
+                refreshed.etag.as_deref(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(total_size(&cache), total_size(&fresh));
+    }
+
+    #[test]
+    fn test_refresh_not_modified_missing_entry() {
+        let cache = create_test_cache();
+        assert!(
+            cache
+                .refresh_not_modified("GET", "https://api.com/none", "{}", "{}")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_lookup_serves_stale_offline() {
+        use crate::connectivity::ConnectionType;
+
+        let cache = create_test_cache();
+        let headers =
+            r#"{"Cache-Control":"max-age=0, stale-while-revalidate=60, stale-if-error=86400"}"#;
+        cache
+            .put(
+                "GET",
+                "https://api.com/feed",
+                200,
+                headers,
+                b"feed",
+                0,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let online = NetworkStatus::from_connection_type(ConnectionType::WiFi);
+        let offline = NetworkStatus::offline();
+
+        let hit = cache
+            .lookup("GET", "https://api.com/feed", "{}", &online)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hit.freshness, Freshness::StaleRevalidatable);
+        assert_eq!(hit.entry.body, b"feed");
+
+        let hit = cache
+            .lookup("GET", "https://api.com/feed", "{}", &offline)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hit.freshness, Freshness::StaleIfError);
+
+        // Still inside the stale-if-error window, so cleanup keeps it
+        assert_eq!(cache.cleanup_expired().unwrap(), 0);
+        assert!(
+            cache
+                .lookup("GET", "https://api.com/none", "{}", &online)
+                .unwrap()
+                .is_none()
+        );
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.hit_count, 2);
+        assert_eq!(stats.miss_count, 1);
+    }
+
+    #[test]
+    fn test_lookup_unusable_without_directives() {
+        let cache = create_test_cache();
+        cache
+            .put(
+                "GET",
+                "https://api.com/data",
+                200,
+                "{}",
+                b"data",
+                0,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let hit = cache
+            .lookup(
+                "GET",
+                "https://api.com/data",
+                "{}",
+                &NetworkStatus::offline(),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(hit.freshness, Freshness::Unusable);
+        assert_eq!(cache.cleanup_expired().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_vary_selects_variant() {
+        let cache = create_test_cache();
+        let response_headers = r#"{"Cache-Control":"max-age=300","Vary":"Accept-Language"}"#;
+
+        cache
+            .put_response(
+                "GET",
+                "https://api.com/home",
+                r#"{"Accept-Language":"en"}"#,
+                200,
+                response_headers,
+                b"Hello",
+                &[],
+            )
+            .unwrap();
+        cache
+            .put_response(
+                "GET",
+                "https://api.com/home",
+                r#"{"accept-language":"hi"}"#,
+                200,
+                response_headers,
+                b"Namaste",
+                &[],
+            )
+            .unwrap();
+
+        let en = cache
+            .get_with_headers("GET", "https://api.com/home", r#"{"Accept-Language":"en"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(en.body, b"Hello");
+        let hi = cache
+            .get_with_headers("GET", "https://api.com/home", r#"{"Accept-Language":"hi"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hi.body, b"Namaste");
+
+        assert!(
+            cache
+                .get_with_headers("GET", "https://api.com/home", r#"{"Accept-Language":"ta"}"#)
+                .unwrap()
+                .is_none()
+        );
+        assert!(cache.get("GET", "https://api.com/home").unwrap().is_none());
+
+        // Invalidation removes every variant
+        assert!(cache.invalidate("GET", "https://api.com/home").unwrap());
+        assert_eq!(cache.stats().unwrap().total_entries, 0);
+    }
+
+    #[test]
+    fn test_vary_change_drops_old_variants() {
+        let cache = create_test_cache();
+
+        cache
+            .put_response(
+                "GET",
+                "https://api.com/home",
+                r#"{"Accept-Language":"en"}"#,
+                200,
+                r#"{"Cache-Control":"max-age=300","Vary":"Accept-Language"}"#,
+                b"Hello",
+                &[],
+            )
+            .unwrap();
+        cache
+            .put_response(
+                "GET",
+                "https://api.com/home",
+                "{}",
+                200,
+                r#"{"Cache-Control":"max-age=300"}"#,
+                b"Plain",
+                &[],
+            )
+            .unwrap();
+
+        assert_eq!(cache.stats().unwrap().total_entries, 1);
+        let entry = cache
+            .get_with_headers("GET", "https://api.com/home", r#"{"Accept-Language":"en"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.body, b"Plain");
+    }
+
+    #[test]
+    fn test_vary_star_not_cacheable() {
+        let cache = create_test_cache();
+        let decision = cache
+            .put_response(
+                "GET",
+                "https://api.com/x",
+                "{}",
+                200,
+                r#"{"Cache-Control":"max-age=300","Vary":"*"}"#,
+                b"x",
+                &[],
+            )
+            .unwrap();
+        assert!(!decision.cacheable);
+    }
+
+    #[test]
+    fn test_variant_key_normalization() {
+        let vary = vary_header_names("Accept-Encoding, accept-language,Accept-Language");
+        assert_eq!(
+            vary,
+            vec!["accept-encoding".to_string(), "accept-language".to_string()]
+        );
+
+        let a: HashMap<String, String> =
+            [("accept-language".to_string(), "en-US,  hi".to_string())].into();
+        let b: HashMap<String, String> =
+            [("accept-language".to_string(), "en-US, hi".to_string())].into();
+        assert_eq!(
+            HttpCache::generate_variant_key("GET", "https://a.com", &vary, &a),
+            HttpCache::generate_variant_key("GET", "https://a.com", &vary, &b)
+        );
+        assert_eq!(
+            HttpCache::generate_variant_key("GET", "https://a.com", &[], &a),
+            HttpCache::generate_key("GET", "https://a.com")
+        );
+    }
+
+    #[test]
+    fn test_binary_body_roundtrip() {
+        let cache = create_test_cache();
+        let png = vec![0x89u8, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff];
+
+        cache
+            .put(
+                "GET",
+                "https://cdn.com/logo.png",
+                200,
+                "{}",
+                &png,
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let entry = cache
+            .get("GET", "https://cdn.com/logo.png")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.body, png);
+        assert_eq!(entry.body_size, png.len() as u64);
+        assert!(entry.body_text().is_none());
+
+        let json = serde_json::to_string(&entry).unwrap();
+        let decoded: CachedResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.body, png);
+    }
+
+    fn create_compressing_cache(max_size_bytes: u64) -> HttpCache {
+        let config = CacheConfig {
+            compress_bodies: true,
+            ..CacheConfig::new(max_size_bytes)
+        };
+        HttpCache::with_config(":memory:", config).unwrap()
+    }
+
+    #[test]
+    fn test_compressed_body_roundtrip() {
+        let cache = create_compressing_cache(10 * 1024 * 1024);
+        let json = r#"{"id":1,"name":"item","tags":["a","b"]},"#.repeat(200);
+
+        cache
+            .put(
+                "GET",
+                "https://api.com/items",
+                200,
+                "{}",
+                json.as_bytes(),
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let entry = cache.get("GET", "https://api.com/items").unwrap().unwrap();
+        assert_eq!(entry.body, json.as_bytes());
+        assert_eq!(entry.body_size, json.len() as u64);
+        assert!(entry.stored_body_size < entry.body_size / 3);
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.body_bytes, entry.body_size);
+        assert_eq!(stats.stored_body_bytes, entry.stored_body_size);
+        assert!(stats.total_size_bytes < stats.body_bytes);
+    }
+
+    #[test]
+    fn test_small_or_incompressible_bodies_stored_raw() {
+        let cache = create_compressing_cache(10 * 1024 * 1024);
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+
+        cache
+            .put(
+                "GET",
+                "https://a.com/small",
+                200,
+                "{}",
+                b"tiny",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://a.com/noise",
+                200,
+                "{}",
+                &noise,
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let small = cache.get("GET", "https://a.com/small").unwrap().unwrap();
+        assert_eq!(small.stored_body_size, small.body_size);
+        let entry = cache.get("GET", "https://a.com/noise").unwrap().unwrap();
+        assert_eq!(entry.body, noise);
+        assert_eq!(entry.stored_body_size, entry.body_size);
+    }
+
+    #[test]
+    fn test_compression_fits_more_entries() {
+        let json = r#"{"id":1,"name":"item","tags":["a","b"]},"#.repeat(100);
+        let plain = HttpCache::new(":memory:", 20_000).unwrap();
+        let compressed = create_compressing_cache(20_000);
+
+        for i in 0..10 {
+            let url = format!("https://api.com/items/{}", i);
+            plain
+                .put("GET", &url, 200, "{}", json.as_bytes(), 300, None, None)
+                .unwrap();
+            compressed
+                .put("GET", &url, 200, "{}", json.as_bytes(), 300, None, None)
+                .unwrap();
+        }
+
+        assert!(plain.stats().unwrap().total_entries < 10);
+        assert_eq!(compressed.stats().unwrap().total_entries, 10);
+    }
+
+    #[test]
+    fn test_stats_persist_across_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.db");
+        let path = path.to_str().unwrap();
+
+        {
+            let cache = HttpCache::new(path, 10 * 1024 * 1024).unwrap();
+            cache
+                .put("GET", "https://a.com/x", 200, "{}", b"x", 300, None, None)
+                .unwrap();
+            cache.get("GET", "https://a.com/x").unwrap();
+            cache.get("GET", "https://a.com/y").unwrap();
+        }
+
+        let cache = HttpCache::new(path, 10 * 1024 * 1024).unwrap();
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.hit_count, 1);
+        assert_eq!(stats.miss_count, 1);
+
+        cache.reset_stats().unwrap();
+        assert_eq!(cache.stats().unwrap().hit_count, 0);
+        assert_eq!(cache.stats().unwrap().total_entries, 1);
+    }
+
+    #[test]
+    fn test_stats_breakdown_by_host_and_path() {
+        let cache = create_test_cache();
+        cache
+            .put(
+                "GET",
+                "https://api.com/v1/users/1",
+                200,
+                "{}",
+                b"u",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache.get("GET", "https://api.com/v1/users/1").unwrap();
+        cache.get("GET", "https://api.com/v1/users/2").unwrap();
+        cache.get("GET", "https://api.com/v1/orders/9").unwrap();
+        cache.get("GET", "https://cdn.com/img/a.png").unwrap();
+
+        let hosts = cache.stats_breakdown(StatsScope::Host, None).unwrap();
+        assert_eq!(hosts[0].key, "api.com");
+        assert_eq!((hosts[0].hits, hosts[0].misses), (1, 2));
+        assert_eq!(hosts[1].key, "cdn.com");
+
+        let paths = cache
+            .stats_breakdown(StatsScope::PathPrefix, Some(1))
+            .unwrap();
+        let users = paths.iter().find(|c| c.key == "/v1/users").unwrap();
+        assert_eq!(users.hit_rate, 0.5);
+        assert!(paths.iter().any(|c| c.key == "/v1/orders"));
+    }
+
+    #[test]
+    fn test_stats_count_revalidations_and_evictions() {
+        let cache = HttpCache::new(":memory:", 1000).unwrap();
+        let h = r#"{"Cache-Control":"max-age=300","ETag":"\"v1\""}"#;
+        cache
+            .put_response("GET", "https://a.com/1", "{}", 200, h, &[b'a'; 300], &[])
+            .unwrap();
+        cache
+            .refresh_not_modified("GET", "https://a.com/1", "{}", h)
+            .unwrap();
+        cache
+            .put_response("GET", "https://a.com/2", "{}", 200, h, &[b'b'; 300], &[])
+            .unwrap();
+        cache
+            .put_response("GET", "https://a.com/3", "{}", 200, h, &[b'c'; 300], &[])
+            .unwrap();
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.revalidation_count, 1);
+        assert!(stats.eviction_count >= 1);
+        let hosts = cache.stats_breakdown(StatsScope::Host, None).unwrap();
+        assert_eq!(hosts[0].evictions, stats.eviction_count);
+    }
+
+    #[test]
+    fn test_legacy_text_rows_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.db");
+        let path = path.to_str().unwrap();
+
+        // Schema and data as written by the first release
+        {
+            let conn = Connection::open(path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE http_cache (
+                    cache_key TEXT PRIMARY KEY, status_code INTEGER NOT NULL,
+                    headers_json TEXT NOT NULL DEFAULT '{}', body TEXT NOT NULL,
+                    cached_at TEXT NOT NULL, expires_at TEXT NOT NULL, etag TEXT,
+                    last_modified TEXT, body_size INTEGER NOT NULL, last_accessed_at TEXT NOT NULL
+                );",
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO http_cache VALUES (?1, 200, '{}', 'old text body', ?2, ?3, NULL, NULL, 13, ?2)",
+                params![
+                    HttpCache::generate_key("GET", "https://api.com/old"),
+                    Utc::now().to_rfc3339(),
+                    (Utc::now() + chrono::Duration::hours(1)).to_rfc3339(),
+                ],
+            )
+            .unwrap();
+        }
+
+        let cache = HttpCache::new(path, 10 * 1024 * 1024).unwrap();
+        let entry = cache.get("GET", "https://api.com/old").unwrap().unwrap();
+        assert_eq!(entry.body_text(), Some("old text body"));
+    }
+
+    fn limited_cache(policy: EvictionPolicy, max_size_bytes: u64) -> HttpCache {
+        HttpCache::with_config(
+            ":memory:",
+            CacheConfig {
+                eviction_policy: policy,
+                ..CacheConfig::new(max_size_bytes)
+            },
+        )
+        .unwrap()
+    }
+
+    fn total_size(cache: &HttpCache) -> u64 {
+        cache.stats().unwrap().total_size_bytes
+    }
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(url_host("https://API.test.com/users?x=1"), "api.test.com");
+        assert_eq!(url_host("http://user:pw@cdn.com:8080/a"), "cdn.com:8080");
+        assert_eq!(url_host("https://a.com"), "a.com");
+    }
+
+    #[test]
+    fn test_eviction_loops_until_budget_met() {
+        let cache = limited_cache(EvictionPolicy::Lru, 20_000);
+        for i in 0..10 {
+            cache
+                .put(
+                    "GET",
+                    &format!("https://a.com/{}", i),
+                    200,
+                    "{}",
+                    &[b'x'; 1_500],
+                    300,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        // One large insert needs several small entries to go
+        cache
+            .put(
+                "GET",
+                "https://a.com/big",
+                200,
+                "{}",
+                &[b'y'; 12_000],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(total_size(&cache) <= 20_000);
+        assert!(cache.get("GET", "https://a.com/big").unwrap().is_some());
+        assert!(cache.get("GET", "https://a.com/0").unwrap().is_none());
+        assert!(cache.get("GET", "https://a.com/9").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_entry_size_counts_headers() {
+        let cache = create_test_cache();
+        let headers = format!(r#"{{"X-Padding":"{}"}}"#, "h".repeat(1_000));
+        cache
+            .put(
+                "GET",
+                "https://a.com",
+                200,
+                &headers,
+                b"tiny",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(total_size(&cache) > 1_000);
+    }
+
+    #[test]
+    fn test_lfu_keeps_popular_entries() {
+        let cache = limited_cache(EvictionPolicy::Lfu, 4_000);
+        cache
+            .put(
+                "GET",
+                "https://a.com/popular",
+                200,
+                "{}",
+                &[b'p'; 1_000],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://a.com/once",
+                200,
+                "{}",
+                &[b'o'; 1_000],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        for _ in 0..5 {
+            cache.get("GET", "https://a.com/popular").unwrap();
+        }
+        cache.get("GET", "https://a.com/once").unwrap(); // most recent, but rarely used
+
+        cache
+            .put(
+                "GET",
+                "https://a.com/new",
+                200,
+                "{}",
+                &[b'n'; 2_000],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(cache.get("GET", "https://a.com/popular").unwrap().is_some());
+        assert!(cache.get("GET", "https://a.com/once").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_gdsf_prefers_evicting_large_entries() {
+        let cache = limited_cache(EvictionPolicy::Gdsf, 12_000);
+        cache
+            .put(
+                "GET",
+                "https://a.com/small",
+                200,
+                "{}",
+                &[b's'; 500],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://a.com/large",
+                200,
+                "{}",
+                &[b'l'; 8_000],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache.get("GET", "https://a.com/large").unwrap();
+
+        cache
+            .put(
+                "GET",
+                "https://a.com/new",
+                200,
+                "{}",
+                &[b'n'; 3_000],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(cache.get("GET", "https://a.com/small").unwrap().is_some());
+        assert!(cache.get("GET", "https://a.com/large").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_max_entry_size() {
+        let cache = HttpCache::with_config(
+            ":memory:",
+            CacheConfig {
+                max_entry_bytes: Some(2_000),
+                ..CacheConfig::new(1024 * 1024)
+            },
+        )
+        .unwrap();
+
+        let result = cache.put(
+            "GET",
+            "https://a.com/huge",
+            200,
+            "{}",
+            &[b'x'; 5_000],
+            300,
+            None,
+            None,
+        );
+        assert!(matches!(result, Err(CacheError::EntryTooLarge { .. })));
+        assert_eq!(cache.stats().unwrap().total_entries, 0);
+    }
+
+    #[test]
+    fn test_per_host_quota() {
+        let cache = HttpCache::with_config(
+            ":memory:",
+            CacheConfig {
+                per_host_quota_bytes: Some(5_000),
+                ..CacheConfig::new(1024 * 1024)
+            },
+        )
+        .unwrap();
+
+        cache
+            .put(
+                "GET",
+                "https://api.com/auth/token",
+                200,
+                "{}",
+                &[b't'; 500],
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        for i in 0..10 {
+            cache
+                .put(
+                    "GET",
+                    &format!("https://images.com/{}.jpg", i),
+                    200,
+                    "{}",
+                    &[b'i'; 1_500],
+                    300,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        // The image host churns within its own quota; the API entry survives
+        assert!(
+            cache
+                .get("GET", "https://api.com/auth/token")
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            cache
+                .get("GET", "https://images.com/9.jpg")
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            cache
+                .get("GET", "https://images.com/0.jpg")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_url_path() {
+        assert_eq!(
+            url_path("https://a.com/api/users/42?x=1#top"),
+            "/api/users/42"
+        );
+        assert_eq!(url_path("https://a.com"), "/");
+        assert_eq!(url_path("https://a.com?x=1"), "/");
+    }
+
+    #[test]
+    fn test_invalidate_prefix() {
+        let cache = create_test_cache();
+        cache
+            .put(
+                "GET",
+                "https://api.com/api/users/42/profile",
+                200,
+                "{}",
+                b"p",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/api/users/42/posts?page=2",
+                200,
+                "{}",
+                b"q",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/api/users/420",
+                200,
+                "{}",
+                b"r",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://cdn.com/api/users/42/avatar",
+                200,
+                "{}",
+                b"s",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(
+            cache
+                .invalidate_prefix("https://api.com/api/users/42/")
+                .unwrap(),
+            2
+        );
+        assert!(
+            cache
+                .get("GET", "https://api.com/api/users/420")
+                .unwrap()
+                .is_some()
+        );
+
+        // Path prefixes match on every host
+        assert_eq!(cache.invalidate_prefix("/api/users/42/").unwrap(), 1);
+        assert_eq!(cache.stats().unwrap().total_entries, 1);
+    }
+
+    #[test]
+    fn test_invalidate_glob() {
+        let cache = create_test_cache();
+        cache
+            .put(
+                "GET",
+                "https://api.com/api/users/42/profile",
+                200,
+                "{}",
+                b"p",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/api/users/7/profile",
+                200,
+                "{}",
+                b"q",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/api/orders/1",
+                200,
+                "{}",
+                b"r",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(cache.invalidate_glob("/api/users/*/profile").unwrap(), 2);
+        assert_eq!(
+            cache
+                .invalidate_glob("https://api.com/api/orders/?")
+                .unwrap(),
+            1
+        );
+        assert_eq!(cache.stats().unwrap().total_entries, 0);
+    }
+
+    #[test]
+    fn test_invalidate_tag() {
+        let cache = create_test_cache();
+        let h = r#"{"Cache-Control":"max-age=300","Surrogate-Key":"user:42 feed"}"#;
+        cache
+            .put_response("GET", "https://api.com/me", "{}", 200, h, b"me", &[])
+            .unwrap();
+        cache
+            .put_response(
+                "GET",
+                "https://api.com/settings",
+                "{}",
+                200,
+                r#"{"Cache-Control":"max-age=300","Cache-Tag":"user:42,settings"}"#,
+                b"s",
+                &[],
+            )
+            .unwrap();
+        cache
+            .put_response(
+                "GET",
+                "https://api.com/friends",
+                "{}",
+                200,
+                r#"{"Cache-Control":"max-age=300"}"#,
+                b"f",
+                &["user:42"],
+            )
+            .unwrap();
+        cache
+            .put_response(
+                "GET",
+                "https://api.com/user/420",
+                "{}",
+                200,
+                r#"{"Cache-Control":"max-age=300"}"#,
+                b"x",
+                &["user:420"],
+            )
+            .unwrap();
+
+        assert_eq!(cache.invalidate_tag("user:42").unwrap(), 3);
+        assert!(
+            cache
+                .get("GET", "https://api.com/user/420")
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(cache.invalidate_tag("feed").unwrap(), 0);
+    }
+
     #[test]
     fn test_overwrite_existing() {
         let cache = create_test_cache();
 
-        cache.put("GET", "https://api.com/data", 200, "{}", "old-data", 300, None, None).unwrap();
-        cache.put("GET", "https://api.com/data", 200, "{}", "new-data", 300, None, None).unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/data",
+                200,
+                "{}",
+                b"old-data",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
+        cache
+            .put(
+                "GET",
+                "https://api.com/data",
+                200,
+                "{}",
+                b"new-data",
+                300,
+                None,
+                None,
+            )
+            .unwrap();
 
         let entry = cache.get("GET", "https://api.com/data").unwrap().unwrap();
-        assert_eq!(entry.body, "new-data");
+        assert_eq!(entry.body, b"new-data");
     }
 }
@@ -0,0 +1,223 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+
+/// Counters older than this are dropped by `HttpCache::cleanup_expired`
+pub(super) const RETENTION_DAYS: i64 = 90;
+
+/// How counters are broken down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatsScope {
+    /// One row for the whole cache
+    Global,
+    /// One row per host
+    Host,
+    /// One row per leading path segments (see `CacheConfig::stats_path_depth`)
+    PathPrefix,
+}
+
+impl StatsScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StatsScope::Global => "global",
+            StatsScope::Host => "host",
+            StatsScope::PathPrefix => "path",
+        }
+    }
+
+    /// Parse `global`, `host` or `path`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "global" => Some(StatsScope::Global),
+            "host" => Some(StatsScope::Host),
+            "path" | "path_prefix" | "pathprefix" => Some(StatsScope::PathPrefix),
+            _ => None,
+        }
+    }
+}
+
+/// Counters for one host, path prefix, or the whole cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CounterStats {
+    pub scope: StatsScope,
+    /// Host or path prefix (empty for `Global`)
+    pub key: String,
+    pub hits: u64,
+    pub misses: u64,
+    /// 304 responses that refreshed a stored entry
+    pub revalidations: u64,
+    pub evictions: u64,
+    pub hit_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum CacheEvent {
+    Hit,
+    Miss,
+    Revalidation,
+    Eviction,
+}
+
+impl CacheEvent {
+    fn column(&self) -> &'static str {
+        match self {
+            CacheEvent::Hit => "hits",
+            CacheEvent::Miss => "misses",
+            CacheEvent::Revalidation => "revalidations",
+            CacheEvent::Eviction => "evictions",
+        }
+    }
+}
+
+pub(super) fn initialize(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS cache_stats (
+            bucket TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            key TEXT NOT NULL,
+            hits INTEGER NOT NULL DEFAULT 0,
+            misses INTEGER NOT NULL DEFAULT 0,
+            revalidations INTEGER NOT NULL DEFAULT 0,
+            evictions INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (bucket, scope, key)
+        );
+        ",
+    )
+}
+
+/// Hour bucket a timestamp falls into
+fn bucket(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%dT%H:00:00Z").to_string()
+}
+
+/// First `depth` segments of a path (`/api/users/42` → `/api/users` at depth 2)
+pub(super) fn path_prefix(path: &str, depth: usize) -> String {
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .take(depth)
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+/// Add `count` events to the global, host and path-prefix counters
+pub(super) fn record(
+    conn: &Connection,
+    event: CacheEvent,
+    host: &str,
+    path_prefix: &str,
+    count: u64,
+    now: DateTime<Utc>,
+) -> rusqlite::Result<()> {
+    let column = event.column();
+    let sql = format!(
+        "INSERT INTO cache_stats (bucket, scope, key, {col}) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (bucket, scope, key) DO UPDATE SET {col} = {col} + excluded.{col}",
+        col = column
+    );
+    let bucket = bucket(now);
+    for (scope, key) in [
+        (StatsScope::Global, ""),
+        (StatsScope::Host, host),
+        (StatsScope::PathPrefix, path_prefix),
+    ] {
+        conn.execute(&sql, params![bucket, scope.as_str(), key, count as i64])?;
+    }
+    Ok(())
+}
+
+/// Counters summed over every bucket since `since` (all time if None),
+/// busiest first
+pub(super) fn query(
+    conn: &Connection,
+    scope: StatsScope,
+    since: Option<DateTime<Utc>>,
+) -> rusqlite::Result<Vec<CounterStats>> {
+    let since = since.map(bucket).unwrap_or_default();
+    let mut stmt = conn.prepare(
+        "SELECT key, SUM(hits), SUM(misses), SUM(revalidations), SUM(evictions)
+         FROM cache_stats
+         WHERE scope = ?1 AND bucket >= ?2
+         GROUP BY key
+         ORDER BY SUM(hits) + SUM(misses) DESC, key ASC",
+    )?;
+    let rows = stmt.query_map(params![scope.as_str(), since], |row| {
+        let hits = row.get::<_, i64>(1)? as u64;
+        let misses = row.get::<_, i64>(2)? as u64;
+        Ok(CounterStats {
+            scope,
+            key: row.get(0)?,
+            hits,
+            misses,
+            revalidations: row.get::<_, i64>(3)? as u64,
+            evictions: row.get::<_, i64>(4)? as u64,
+            hit_rate: if hits + misses > 0 {
+                hits as f64 / (hits + misses) as f64
+            } else {
+                0.0
+            },
+        })
+    })?;
+    rows.collect()
+}
+
+/// Drop every counter
+pub(super) fn reset(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM cache_stats", [])?;
+    Ok(())
+}
+
+/// Drop counters from buckets before `before`
+pub(super) fn prune(conn: &Connection, before: DateTime<Utc>) -> rusqlite::Result<u64> {
+    let rows = conn.execute(
+        "DELETE FROM cache_stats WHERE bucket < ?1",
+        params![bucket(before)],
+    )?;
+    Ok(rows as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_prefix() {
+        assert_eq!(path_prefix("/api/users/42", 2), "/api/users");
+        assert_eq!(path_prefix("/api", 2), "/api");
+        assert_eq!(path_prefix("/", 2), "/");
+        assert_eq!(path_prefix("/api/users/42", 1), "/api");
+    }
+
+    #[test]
+    fn test_record_and_window() {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize(&conn).unwrap();
+        let now = Utc::now();
+        let old = now - chrono::Duration::hours(5);
+
+        record(&conn, CacheEvent::Hit, "a.com", "/api", 3, now).unwrap();
+        record(&conn, CacheEvent::Miss, "a.com", "/api", 1, now).unwrap();
+        record(&conn, CacheEvent::Hit, "b.com", "/img", 2, old).unwrap();
+
+        let all = query(&conn, StatsScope::Global, None).unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].hits, 5);
+
+        let recent = query(
+            &conn,
+            StatsScope::Host,
+            Some(now - chrono::Duration::hours(1)),
+        )
+        .unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].key, "a.com");
+        assert_eq!(recent[0].hit_rate, 0.75);
+
+        assert_eq!(prune(&conn, now - chrono::Duration::hours(1)).unwrap(), 3);
+        assert_eq!(
+            query(&conn, StatsScope::PathPrefix, None).unwrap()[0].key,
+            "/api"
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::ConnectionType;
+
+/// Transfers smaller than this are dominated by latency rather than
+/// throughput, so they feed the RTT estimate instead
+pub const MIN_SAMPLE_BYTES: u64 = 4 * 1024;
+
+/// Time for the weight of past throughput samples to halve
+const HALF_LIFE: Duration = Duration::from_secs(60);
+
+/// Current throughput and latency estimates
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthSnapshot {
+    /// Time-decayed average throughput (None until a large enough transfer)
+    pub kbps: Option<u32>,
+    /// Percentiles over the retained throughput samples
+    pub p10_kbps: Option<u32>,
+    pub p50_kbps: Option<u32>,
+    pub p90_kbps: Option<u32>,
+    /// Smoothed round-trip time
+    pub rtt_ms: Option<u32>,
+    /// Mean deviation of the round-trip time
+    pub rtt_variance_ms: Option<u32>,
+    /// Throughput samples retained
+    pub samples: usize,
+    /// Decayed number of samples behind `kbps`, as of now
+    pub weight: f64,
+}
+
+/// Smoothed RTT and its variation, as in RFC 6298
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct LatencyEstimate {
+    srtt_ms: f64,
+    rttvar_ms: f64,
+    samples: u64,
+}
+
+impl LatencyEstimate {
+    fn record(&mut self, rtt_ms: f64) {
+        if self.samples == 0 {
+            self.srtt_ms = rtt_ms;
+            self.rttvar_ms = rtt_ms / 2.0;
+        } else {
+            self.rttvar_ms = 0.75 * self.rttvar_ms + 0.25 * (self.srtt_ms - rtt_ms).abs();
+            self.srtt_ms = 0.875 * self.srtt_ms + 0.125 * rtt_ms;
+        }
+        self.samples += 1;
+    }
+}
+
+/// What the estimator knows, as persisted by `export_state`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct EstimatorState {
+    kbps: f64,
+    /// Decayed number of samples behind `kbps`
+    weight: f64,
+    /// Recent throughput samples in Kbps, oldest first
+    samples: VecDeque<u32>,
+    latency: LatencyEstimate,
+    /// Connection the samples were measured on
+    #[serde(default)]
+    connection_type: Option<ConnectionType>,
+    saved_at: Option<String>,
+}
+
+struct Inner {
+    state: EstimatorState,
+    last_sample: Option<Instant>,
+}
+
+/// Bandwidth estimator that tracks actual transfer speeds and latency
+pub struct BandwidthEstimator {
+    inner: Mutex<Inner>,
+    /// Maximum throughput samples kept for percentiles
+    max_samples: usize,
+}
+
+impl BandwidthEstimator {
+    pub fn new(max_samples: usize) -> Self {
+        BandwidthEstimator {
+            inner: Mutex::new(Inner {
+                state: EstimatorState::default(),
+                last_sample: None,
+            }),
+            max_samples: max_samples.max(1),
+        }
+    }
+
+    /// Record a completed transfer. Transfers under `MIN_SAMPLE_BYTES`
+    /// count as a round-trip time sample instead.
+    pub fn record_transfer(&self, bytes: u64, duration_ms: u64) {
+        self.record_transfer_at(bytes, duration_ms, Instant::now());
+    }
+
+    fn record_transfer_at(&self, bytes: u64, duration_ms: u64, now: Instant) {
+        if duration_ms == 0 {
+            return;
+        }
+        let mut inner = match self.inner.lock() {
+            Ok(i) => i,
+            Err(_) => return,
+        };
+        if bytes < MIN_SAMPLE_BYTES {
+            inner.state.latency.record(duration_ms as f64);
+            return;
+        }
+
+        let kbps = (bytes as f64 * 8.0) / (duration_ms as f64); // bits per ms = Kbps
+        // Older samples lose weight with time, not just with newer samples
+        let decay = inner
+            .last_sample
+            .map(|t| half_life_decay(now.saturating_duration_since(t).as_secs_f64()))
+            .unwrap_or(1.0);
+        let state = &mut inner.state;
+        let old_weight = state.weight * decay;
+        state.kbps = (state.kbps * old_weight + kbps) / (old_weight + 1.0);
+        state.weight = old_weight + 1.0;
+        state.samples.push_back(kbps.min(u32::MAX as f64) as u32);
+        while state.samples.len() > self.max_samples {
+            state.samples.pop_front();
+        }
+        inner.last_sample = Some(now);
+    }
+
+    /// Record a round-trip time measured by the platform or a probe
+    pub fn record_rtt(&self, rtt_ms: u64) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.state.latency.record(rtt_ms as f64);
+        }
+    }
+
+    /// Time-decayed average throughput in Kbps (0 if unknown)
+    pub fn estimate_kbps(&self) -> u32 {
+        match self.inner.lock() {
+            Ok(inner) if inner.state.weight > 0.0 => inner.state.kbps.round() as u32,
+            _ => 0,
+        }
+    }
+
+    /// Throughput percentile (0-100) over the retained samples
+    pub fn percentile_kbps(&self, percentile: u8) -> Option<u32> {
+        let inner = self.inner.lock().ok()?;
+        percentile_of(&inner.state.samples, percentile)
+    }
+
+    /// Smoothed round-trip time in ms, if any latency sample was recorded
+    pub fn estimate_rtt_ms(&self) -> Option<u32> {
+        let inner = self.inner.lock().ok()?;
+        let latency = inner.state.latency;
+        (latency.samples > 0).then_some(latency.srtt_ms.round() as u32)
+    }
+
+    pub fn snapshot(&self) -> BandwidthSnapshot {
+        let inner = match self.inner.lock() {
+            Ok(i) => i,
+            Err(_) => return BandwidthSnapshot::default(),
+        };
+        let state = &inner.state;
+        let has_latency = state.latency.samples > 0;
+        let idle = inner.last_sample.map_or(0.0, |t| t.elapsed().as_secs_f64());
+        BandwidthSnapshot {
+            kbps: (state.weight > 0.0).then_some(state.kbps.round() as u32),
+            p10_kbps: percentile_of(&state.samples, 10),
+            p50_kbps: percentile_of(&state.samples, 50),
+            p90_kbps: percentile_of(&state.samples, 90),
+            rtt_ms: has_latency.then_some(state.latency.srtt_ms.round() as u32),
+            rtt_variance_ms: has_latency.then_some(state.latency.rttvar_ms.round() as u32),
+            samples: state.samples.len(),
+            weight: state.weight * half_life_decay(idle),
+        }
+    }
+
+    /// Estimator state as JSON, for `import_state` after a restart
+    pub fn export_state(&self) -> String {
+        let mut state = match self.inner.lock() {
+            Ok(inner) => inner.state.clone(),
+            Err(_) => return "{}".to_string(),
+        };
+        state.saved_at = Some(Utc::now().to_rfc3339());
+        serde_json::to_string(&state).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    /// Restore state saved by `export_state`. Throughput weight decays by
+    /// the time spent saved, so a stale estimate yields quickly to new
+    /// samples. Returns false if `json` isn't valid state.
+    pub fn import_state(&self, json: &str) -> bool {
+        let mut state: EstimatorState = match serde_json::from_str(json) {
+            Ok(s) => s,
+            Err(_) => return false,
+        };
+        if let Some(saved_at) = state
+            .saved_at
+            .take()
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+        {
+            let idle = (Utc::now() - saved_at.with_timezone(&Utc))
+                .num_seconds()
+                .max(0) as f64;
+            state.weight *= half_life_decay(idle);
+        }
+        while state.samples.len() > self.max_samples {
+            state.samples.pop_front();
+        }
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                inner.state = state;
+                inner.last_sample = Some(Instant::now());
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Tie the samples to the link they're measured on: switching to a
+    /// different connection type starts over. Going offline or to an
+    /// unknown type doesn't count as a switch.
+    pub fn set_connection_type(&self, connection_type: ConnectionType) {
+        if matches!(
+            connection_type,
+            ConnectionType::Offline | ConnectionType::Unknown
+        ) {
+            return;
+        }
+        if let Ok(mut inner) = self.inner.lock() {
+            if inner
+                .state
+                .connection_type
+                .is_some_and(|t| t != connection_type)
+            {
+                inner.state = EstimatorState::default();
+                inner.last_sample = None;
+            }
+            inner.state.connection_type = Some(connection_type);
+        }
+    }
+
+    /// Clear all samples
+    pub fn reset(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.state = EstimatorState::default();
+            inner.last_sample = None;
+        }
+    }
+}
+
+/// Fraction of weight left after `elapsed_secs`
+fn half_life_decay(elapsed_secs: f64) -> f64 {
+    0.5f64.powf(elapsed_secs / HALF_LIFE.as_secs_f64())
+}
+
+/// Nearest-rank percentile
+fn percentile_of(samples: &VecDeque<u32>, percentile: u8) -> Option<u32> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<u32> = samples.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = (percentile.min(100) as f64 / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth_estimator() {
+        let estimator = BandwidthEstimator::new(10);
+
+        // Simulate: 100KB transferred in 100ms = 8000 Kbps
+        estimator.record_transfer(100_000, 100);
+        let estimate = estimator.estimate_kbps();
+        assert!(estimate > 0);
+
+        // Simulate slower transfer
+        estimator.record_transfer(10_000, 500);
+        let new_estimate = estimator.estimate_kbps();
+        // Should be lower due to slow sample having more recent weight
+        assert!(new_estimate < estimate);
+    }
+
+    #[test]
+    fn test_bandwidth_estimator_empty() {
+        let estimator = BandwidthEstimator::new(10);
+        assert_eq!(estimator.estimate_kbps(), 0);
+    }
+
+    #[test]
+    fn test_bandwidth_estimator_reset() {
+        let estimator = BandwidthEstimator::new(10);
+        estimator.record_transfer(100_000, 100);
+        assert!(estimator.estimate_kbps() > 0);
+
+        estimator.reset();
+        assert_eq!(estimator.estimate_kbps(), 0);
+    }
+
+    #[test]
+    fn test_time_decay() {
+        let estimator = BandwidthEstimator::new(10);
+        let start = Instant::now();
+        for _ in 0..9 {
+            estimator.record_transfer_at(100_000, 100, start); // 8000 Kbps
+        }
+        // Right away, one slow sample barely moves the estimate...
+        estimator.record_transfer_at(10_000, 100, start); // 800 Kbps
+        assert_eq!(estimator.estimate_kbps(), 7280);
+
+        // ...but after ten idle minutes the old ones have almost no weight
+        estimator.reset();
+        for _ in 0..9 {
+            estimator.record_transfer_at(100_000, 100, start);
+        }
+        estimator.record_transfer_at(10_000, 100, start + Duration::from_secs(600));
+        assert!(estimator.estimate_kbps() < 900);
+    }
+
+    #[test]
+    fn test_small_transfers_feed_rtt() {
+        let estimator = BandwidthEstimator::new(10);
+        estimator.record_transfer(200, 80);
+        estimator.record_transfer(300, 120);
+        assert_eq!(estimator.estimate_kbps(), 0);
+        assert_eq!(estimator.estimate_rtt_ms(), Some(85));
+
+        let snapshot = estimator.snapshot();
+        assert_eq!(snapshot.rtt_ms, Some(85));
+        assert_eq!(snapshot.rtt_variance_ms, Some(40));
+        assert_eq!(snapshot.samples, 0);
+    }
+
+    #[test]
+    fn test_percentiles_and_window() {
+        let estimator = BandwidthEstimator::new(5);
+        for kb in 1..=10u64 {
+            estimator.record_transfer(kb * 10_000, 80); // kb * 1000 Kbps
+        }
+        // Only the last 5 samples (6000-10000 Kbps) are kept
+        assert_eq!(estimator.percentile_kbps(0), Some(6000));
+        assert_eq!(estimator.percentile_kbps(50), Some(8000));
+        assert_eq!(estimator.percentile_kbps(100), Some(10000));
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let estimator = BandwidthEstimator::new(10);
+        estimator.record_transfer(100_000, 100);
+        estimator.record_rtt(40);
+        let saved = estimator.export_state();
+
+        let restored = BandwidthEstimator::new(10);
+        assert!(restored.import_state(&saved));
+        let (before, after) = (estimator.snapshot(), restored.snapshot());
+        assert!((before.weight - after.weight).abs() < 0.01);
+        assert_eq!(
+            BandwidthSnapshot {
+                weight: 0.0,
+                ..after
+            },
+            BandwidthSnapshot {
+                weight: 0.0,
+                ..before
+            }
+        );
+        assert!(!restored.import_state("not json"));
+    }
+
+    #[test]
+    fn test_weight_decays_while_idle() {
+        let estimator = BandwidthEstimator::new(10);
+        let start = Instant::now() - Duration::from_secs(120);
+        for _ in 0..4 {
+            estimator.record_transfer_at(100_000, 100, start);
+        }
+        // Two half-lives without a sample
+        assert!((estimator.snapshot().weight - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_connection_type_change_resets() {
+        let estimator = BandwidthEstimator::new(10);
+        estimator.set_connection_type(ConnectionType::WiFi);
+        estimator.record_transfer(100_000, 100);
+
+        // Dropping offline and back keeps what the Wi-Fi measured
+        estimator.set_connection_type(ConnectionType::Offline);
+        estimator.set_connection_type(ConnectionType::WiFi);
+        assert!(estimator.estimate_kbps() > 0);
+
+        estimator.set_connection_type(ConnectionType::Cellular3G);
+        assert_eq!(estimator.snapshot(), BandwidthSnapshot::default());
+
+        // The link survives a save and restore
+        estimator.record_transfer(100_000, 100);
+        let restored = BandwidthEstimator::new(10);
+        restored.import_state(&estimator.export_state());
+        restored.set_connection_type(ConnectionType::WiFi);
+        assert_eq!(restored.estimate_kbps(), 0);
+    }
+}
@@ -1,7 +1,16 @@
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
 use std::time::Duration;
 
+use crate::reachability::{ProbeResult, Reachability};
+
+mod bandwidth;
+mod monitor;
+
+pub use bandwidth::{BandwidthEstimator, BandwidthSnapshot, MIN_SAMPLE_BYTES};
+pub use monitor::{
+    ConnectivityEvent, ConnectivityListener, ConnectivityMonitor, MonitorConfig, QualityBand,
+};
+
 /// Network connection type
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ConnectionType {
@@ -64,6 +73,15 @@ impl ConnectionType {
     }
 }
 
+/// Device power state as reported by the platform layer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerState {
+    /// Plugged in (charging or full)
+    pub is_charging: bool,
+    /// Battery level 0-100, if known
+    pub battery_percent: Option<u8>,
+}
+
 /// Current network status with all relevant info
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkStatus {
@@ -81,6 +99,9 @@ pub struct NetworkStatus {
     pub quality_score: u8,
     /// Whether we're currently online
     pub is_online: bool,
+    /// Result of the last reachability probe on this connection
+    #[serde(default)]
+    pub reachability: Reachability,
 }
 
 impl NetworkStatus {
@@ -94,6 +115,7 @@ impl NetworkStatus {
             is_metered: false,
             quality_score: 0,
             is_online: false,
+            reachability: Reachability::Unknown,
         }
     }
 
@@ -120,9 +142,68 @@ impl NetworkStatus {
             is_metered: conn_type.is_metered(),
             quality_score: conn_type.quality_score(),
             is_online: conn_type != ConnectionType::Offline,
+            reachability: Reachability::Unknown,
+        }
+    }
+
+    /// Fold a probe result in. Anything short of `Reachable` means requests
+    /// would fail, so the status goes offline with a quality of 0 until a
+    /// later probe succeeds.
+    pub fn apply_reachability(&mut self, result: &ProbeResult) {
+        self.reachability = result.reachability;
+        match result.reachability {
+            Reachability::Unknown => {}
+            Reachability::Reachable => {
+                self.is_online = self.connection_type != ConnectionType::Offline;
+                self.quality_score = self.connection_type.quality_score();
+                if let Some(latency) = result.latency_ms.filter(|l| *l > 0) {
+                    self.rtt_ms = latency.min(u32::MAX as u64) as u32;
+                }
+            }
+            Reachability::CaptivePortal | Reachability::DnsFailure | Reachability::NoRoute => {
+                self.is_online = false;
+                self.quality_score = 0;
+            }
         }
     }
 
+    /// Blend measured throughput and RTT into the status. The quality score
+    /// and downlink move from the platform's values toward the measured
+    /// ones, further the more (time-decayed) samples back them, at most 75%.
+    pub fn blend_measurements(&mut self, measured: &BandwidthSnapshot) {
+        if !self.is_online {
+            return;
+        }
+        if let Some(rtt) = measured.rtt_ms.filter(|r| *r > 0) {
+            self.rtt_ms = rtt;
+        }
+        let kbps = match measured.kbps.filter(|k| *k > 0) {
+            Some(k) => k,
+            None => return,
+        };
+        let confidence = (measured.weight / 10.0).min(1.0) * 0.75;
+        self.downlink_kbps = if self.downlink_kbps == 0 {
+            kbps
+        } else {
+            let platform = self.downlink_kbps as f64;
+            (platform + (kbps as f64 - platform) * confidence).round() as u32
+        };
+
+        // Log scale through the 2G (15) and 4G (70) defaults
+        let (lo, hi) = (125.0f64, 27_500.0f64);
+        let throughput = 15.0 + (kbps as f64 / lo).ln() / (hi / lo).ln() * 55.0;
+        // Full marks up to 50ms, half at 1s and beyond
+        let rtt_factor = measured
+            .rtt_ms
+            .map(|rtt| 1.0 - 0.5 * ((rtt as f64 - 50.0) / 950.0).clamp(0.0, 1.0))
+            .unwrap_or(1.0);
+        let measured_score = (throughput * rtt_factor).clamp(1.0, 100.0);
+
+        let default_score = self.connection_type.quality_score() as f64;
+        let blended = default_score + (measured_score - default_score) * confidence;
+        self.quality_score = blended.round().clamp(1.0, 100.0) as u8;
+    }
+
     /// Suggest a timeout duration based on network quality
     pub fn suggested_timeout(&self) -> Duration {
         match self.quality_score {
@@ -135,6 +216,18 @@ impl NetworkStatus {
         }
     }
 
+    /// Bytes that can be moved within `suggested_timeout` at `estimated_kbps`
+    /// (falling back to `downlink_kbps` when there's no estimate yet)
+    pub fn transfer_budget_bytes(&self, estimated_kbps: u32) -> u64 {
+        let kbps = if estimated_kbps > 0 {
+            estimated_kbps
+        } else {
+            self.downlink_kbps
+        };
+        // Kbps is bits per millisecond
+        kbps as u64 * self.suggested_timeout().as_millis() as u64 / 8
+    }
+
     /// Suggest image quality based on network
     pub fn suggested_image_quality(&self) -> ImageQuality {
         if self.save_data {
@@ -200,73 +293,6 @@ impl ImageQuality {
     }
 }
 
-/// Bandwidth estimator that tracks actual transfer speeds
-pub struct BandwidthEstimator {
-    /// Recent samples: (bytes_transferred, duration_ms)
-    samples: Mutex<Vec<(u64, u64)>>,
-    /// Maximum samples to keep
-    max_samples: usize,
-}
-
-impl BandwidthEstimator {
-    pub fn new(max_samples: usize) -> Self {
-        BandwidthEstimator {
-            samples: Mutex::new(Vec::with_capacity(max_samples)),
-            max_samples,
-        }
-    }
-
-    /// Record a completed transfer
-    pub fn record_transfer(&self, bytes: u64, duration_ms: u64) {
-        if duration_ms == 0 {
-            return;
-        }
-        if let Ok(mut samples) = self.samples.lock() {
-            if samples.len() >= self.max_samples {
-                samples.remove(0);
-            }
-            samples.push((bytes, duration_ms));
-        }
-    }
-
-    /// Get estimated bandwidth in Kbps using weighted moving average
-    /// Recent samples have more weight
-    pub fn estimate_kbps(&self) -> u32 {
-        let samples = match self.samples.lock() {
-            Ok(s) => s.clone(),
-            Err(_) => return 0,
-        };
-
-        if samples.is_empty() {
-            return 0;
-        }
-
-        let mut weighted_sum: f64 = 0.0;
-        let mut weight_total: f64 = 0.0;
-        let len = samples.len();
-
-        for (i, (bytes, duration_ms)) in samples.iter().enumerate() {
-            let kbps = (*bytes as f64 * 8.0) / (*duration_ms as f64); // bits per ms = Kbps
-            let weight = (i + 1) as f64 / len as f64; // More recent = higher weight
-            weighted_sum += kbps * weight;
-            weight_total += weight;
-        }
-
-        if weight_total > 0.0 {
-            (weighted_sum / weight_total) as u32
-        } else {
-            0
-        }
-    }
-
-    /// Clear all samples
-    pub fn reset(&self) {
-        if let Ok(mut samples) = self.samples.lock() {
-            samples.clear();
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -311,6 +337,16 @@ mod tests {
         assert!(fast.suggested_timeout() < slow.suggested_timeout());
     }
 
+    #[test]
+    fn test_transfer_budget() {
+        let status = NetworkStatus::from_connection_type(ConnectionType::Cellular2G);
+        // 60s at 80 Kbps = 600 KB
+        assert_eq!(status.transfer_budget_bytes(80), 600_000);
+        // No estimate yet: use the status' downlink (125 Kbps)
+        assert_eq!(status.transfer_budget_bytes(0), 937_500);
+        assert_eq!(NetworkStatus::offline().transfer_budget_bytes(1000), 0);
+    }
+
     #[test]
     fn test_image_quality_suggestions() {
         let status_2g = NetworkStatus::from_connection_type(ConnectionType::Cellular2G);
@@ -330,35 +366,42 @@ mod tests {
     }
 
     #[test]
-    fn test_bandwidth_estimator() {
-        let estimator = BandwidthEstimator::new(10);
-
-        // Simulate: 100KB transferred in 100ms = 8000 Kbps
-        estimator.record_transfer(100_000, 100);
-        let estimate = estimator.estimate_kbps();
-        assert!(estimate > 0);
-
-        // Simulate slower transfer
-        estimator.record_transfer(10_000, 500);
-        let new_estimate = estimator.estimate_kbps();
-        // Should be lower due to slow sample having more recent weight
-        assert!(new_estimate < estimate);
-    }
-
-    #[test]
-    fn test_bandwidth_estimator_empty() {
-        let estimator = BandwidthEstimator::new(10);
-        assert_eq!(estimator.estimate_kbps(), 0);
-    }
-
-    #[test]
-    fn test_bandwidth_estimator_reset() {
-        let estimator = BandwidthEstimator::new(10);
-        estimator.record_transfer(100_000, 100);
-        assert!(estimator.estimate_kbps() > 0);
+    fn test_blend_measurements() {
+        // 4G that measures like 2G, with plenty of samples
+        let mut status = NetworkStatus::from_connection_type(ConnectionType::Cellular4G);
+        status.blend_measurements(&BandwidthSnapshot {
+            kbps: Some(125),
+            rtt_ms: Some(50),
+            samples: 10,
+            weight: 10.0,
+            ..BandwidthSnapshot::default()
+        });
+        assert_eq!(status.downlink_kbps, 6969); // 27500 + (125 - 27500) * 0.75
+        assert_eq!(status.quality_score, 29); // 70 + (15 - 70) * 0.75
+
+        // A single sample barely moves it, and neither do many stale ones
+        for (samples, weight) in [(1, 1.0), (10, 1.0)] {
+            let mut status = NetworkStatus::from_connection_type(ConnectionType::Cellular4G);
+            status.blend_measurements(&BandwidthSnapshot {
+                kbps: Some(125),
+                samples,
+                weight,
+                ..BandwidthSnapshot::default()
+            });
+            assert_eq!(status.quality_score, 66);
+        }
 
-        estimator.reset();
-        assert_eq!(estimator.estimate_kbps(), 0);
+        // Nothing measured, or offline: unchanged
+        let mut status = NetworkStatus::from_connection_type(ConnectionType::WiFi);
+        status.blend_measurements(&BandwidthSnapshot::default());
+        assert_eq!(status.quality_score, 80);
+        let mut status = NetworkStatus::offline();
+        status.blend_measurements(&BandwidthSnapshot {
+            kbps: Some(50_000),
+            samples: 10,
+            ..BandwidthSnapshot::default()
+        });
+        assert_eq!(status.quality_score, 0);
     }
 
     #[test]
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{ConnectionType, NetworkStatus};
+
+/// Coarse quality levels, matching the `suggested_timeout` steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum QualityBand {
+    Offline,
+    /// 1-20
+    Poor,
+    /// 21-40
+    Fair,
+    /// 41-70
+    Moderate,
+    /// 71-90
+    Good,
+    /// 91-100
+    Excellent,
+}
+
+impl QualityBand {
+    pub fn from_score(score: u8) -> Self {
+        match score {
+            0 => QualityBand::Offline,
+            1..=20 => QualityBand::Poor,
+            21..=40 => QualityBand::Fair,
+            41..=70 => QualityBand::Moderate,
+            71..=90 => QualityBand::Good,
+            _ => QualityBand::Excellent,
+        }
+    }
+
+    /// Band for `score` given the band currently reported: leaving it takes
+    /// `margin` points past the boundary, so a score hovering on a boundary
+    /// doesn't flip back and forth. Going offline or coming back is immediate.
+    pub fn with_hysteresis(current: QualityBand, score: u8, margin: u8) -> Self {
+        let raw = QualityBand::from_score(score);
+        if raw == QualityBand::Offline || current == QualityBand::Offline {
+            return raw;
+        }
+        if raw > current {
+            QualityBand::from_score(score.saturating_sub(margin)).max(current)
+        } else {
+            QualityBand::from_score(score.saturating_add(margin)).min(current)
+        }
+    }
+}
+
+/// A change in connectivity, reported once it has outlasted the debounce
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectivityEvent {
+    WentOnline {
+        connection_type: ConnectionType,
+    },
+    WentOffline,
+    ConnectionTypeChanged {
+        from: ConnectionType,
+        to: ConnectionType,
+    },
+    QualityBandChanged {
+        from: QualityBand,
+        to: QualityBand,
+    },
+}
+
+/// Debounce and hysteresis for `ConnectivityMonitor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MonitorConfig {
+    /// How long a new state must hold before it is reported. A change that
+    /// reverts within this window is never reported.
+    pub debounce_ms: u64,
+    /// Quality points past a band boundary needed to change band
+    pub quality_hysteresis: u8,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        MonitorConfig {
+            debounce_ms: 3_000,
+            quality_hysteresis: 5,
+        }
+    }
+}
+
+/// Receives connectivity events (see `ConnectivityMonitor::add_listener`)
+pub trait ConnectivityListener: Send + Sync {
+    /// Called after the change is committed; `status` is the latest status
+    fn on_connectivity_event(&self, event: &ConnectivityEvent, status: &NetworkStatus);
+}
+
+impl<F> ConnectivityListener for F
+where
+    F: Fn(&ConnectivityEvent, &NetworkStatus) + Send + Sync,
+{
+    fn on_connectivity_event(&self, event: &ConnectivityEvent, status: &NetworkStatus) {
+        self(event, status)
+    }
+}
+
+/// What listeners have been told
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    is_online: bool,
+    connection_type: ConnectionType,
+    band: QualityBand,
+}
+
+struct MonitorState {
+    config: MonitorConfig,
+    reported: Snapshot,
+    /// A different state seen since `reported`, and when it was first seen
+    pending: Option<(Snapshot, Instant)>,
+    latest: NetworkStatus,
+    listeners: Vec<(u64, Arc<dyn ConnectivityListener>)>,
+    next_listener_id: u64,
+}
+
+/// Turns raw status updates into debounced connectivity events
+pub struct ConnectivityMonitor {
+    state: Mutex<MonitorState>,
+}
+
+impl ConnectivityMonitor {
+    pub fn new(initial: &NetworkStatus, config: MonitorConfig) -> Self {
+        ConnectivityMonitor {
+            state: Mutex::new(MonitorState {
+                config,
+                reported: Snapshot {
+                    is_online: initial.is_online,
+                    connection_type: initial.connection_type,
+                    band: QualityBand::from_score(initial.quality_score),
+                },
+                pending: None,
+                latest: initial.clone(),
+                listeners: Vec::new(),
+                next_listener_id: 1,
+            }),
+        }
+    }
+
+    pub fn set_config(&self, config: MonitorConfig) {
+        if let Ok(mut state) = self.state.lock() {
+            state.config = config;
+        }
+    }
+
+    /// Register a listener, returning an ID for `remove_listener`
+    pub fn add_listener(&self, listener: Arc<dyn ConnectivityListener>) -> u64 {
+        let mut state = match self.state.lock() {
+            Ok(s) => s,
+            Err(_) => return 0,
+        };
+        let id = state.next_listener_id;
+        state.next_listener_id += 1;
+        state.listeners.push((id, listener));
+        id
+    }
+
+    pub fn remove_listener(&self, id: u64) -> bool {
+        match self.state.lock() {
+            Ok(mut state) => {
+                let before = state.listeners.len();
+                state.listeners.retain(|(l, _)| *l != id);
+                state.listeners.len() != before
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Feed a status update observed at `now`. Returns (and sends to
+    /// listeners) the events for any change that has now held for the
+    /// debounce window.
+    pub fn observe(&self, status: &NetworkStatus, now: Instant) -> Vec<ConnectivityEvent> {
+        if let Ok(mut state) = self.state.lock() {
+            let band = QualityBand::with_hysteresis(
+                state.reported.band,
+                status.quality_score,
+                state.config.quality_hysteresis,
+            );
+            let seen = Snapshot {
+                is_online: status.is_online,
+                connection_type: status.connection_type,
+                band,
+            };
+            state.latest = status.clone();
+            if seen == state.reported {
+                state.pending = None; // Flapped back before the debounce ran out
+            } else if state.pending.is_none_or(|(p, _)| p != seen) {
+                state.pending = Some((seen, now));
+            }
+        }
+        self.poll(now)
+    }
+
+    /// Report a pending change whose debounce window has run out by `now`.
+    /// Call periodically if status updates can be sparse.
+    pub fn poll(&self, now: Instant) -> Vec<ConnectivityEvent> {
+        let (events, status, listeners) = {
+            let mut state = match self.state.lock() {
+                Ok(s) => s,
+                Err(_) => return Vec::new(),
+            };
+            let debounce = Duration::from_millis(state.config.debounce_ms);
+            let next = match state.pending {
+                Some((next, since)) if now.saturating_duration_since(since) >= debounce => next,
+                _ => return Vec::new(),
+            };
+            let events = changes(&state.reported, &next);
+            state.reported = next;
+            state.pending = None;
+            let listeners: Vec<_> = state.listeners.iter().map(|(_, l)| l.clone()).collect();
+            (events, state.latest.clone(), listeners)
+        };
+
+        // Outside the lock, so listeners may call back into the monitor
+        for event in &events {
+            for listener in &listeners {
+                listener.on_connectivity_event(event, &status);
+            }
+        }
+        events
+    }
+}
+
+fn changes(from: &Snapshot, to: &Snapshot) -> Vec<ConnectivityEvent> {
+    let mut events = Vec::new();
+    match (from.is_online, to.is_online) {
+        (false, true) => events.push(ConnectivityEvent::WentOnline {
+            connection_type: to.connection_type,
+        }),
+        (true, false) => events.push(ConnectivityEvent::WentOffline),
+        _ => {}
+    }
+    if from.connection_type != to.connection_type {
+        events.push(ConnectivityEvent::ConnectionTypeChanged {
+            from: from.connection_type,
+            to: to.connection_type,
+        });
+    }
+    if from.band != to.band {
+        events.push(ConnectivityEvent::QualityBandChanged {
+            from: from.band,
+            to: to.band,
+        });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(conn_type: ConnectionType) -> NetworkStatus {
+        NetworkStatus::from_connection_type(conn_type)
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        // 71 is Good, but not far enough past the 70/71 boundary to leave Moderate
+        assert_eq!(
+            QualityBand::with_hysteresis(QualityBand::Moderate, 71, 5),
+            QualityBand::Moderate
+        );
+        assert_eq!(
+            QualityBand::with_hysteresis(QualityBand::Moderate, 76, 5),
+            QualityBand::Good
+        );
+        assert_eq!(
+            QualityBand::with_hysteresis(QualityBand::Good, 68, 5),
+            QualityBand::Good
+        );
+        assert_eq!(
+            QualityBand::with_hysteresis(QualityBand::Good, 15, 5),
+            QualityBand::Poor
+        );
+        assert_eq!(
+            QualityBand::with_hysteresis(QualityBand::Good, 0, 5),
+            QualityBand::Offline
+        );
+    }
+
+    #[test]
+    fn test_debounce_suppresses_flapping() {
+        let monitor = ConnectivityMonitor::new(
+            &status(ConnectionType::Cellular4G),
+            MonitorConfig::default(),
+        );
+        let start = Instant::now();
+
+        assert!(monitor.observe(&NetworkStatus::offline(), start).is_empty());
+        assert!(
+            monitor
+                .observe(
+                    &status(ConnectionType::Cellular4G),
+                    start + Duration::from_secs(1)
+                )
+                .is_empty()
+        );
+        assert!(monitor.poll(start + Duration::from_secs(10)).is_empty());
+
+        assert!(
+            monitor
+                .observe(&NetworkStatus::offline(), start + Duration::from_secs(11))
+                .is_empty()
+        );
+        let events = monitor.poll(start + Duration::from_secs(14));
+        assert_eq!(events[0], ConnectivityEvent::WentOffline);
+        assert!(events.contains(&ConnectivityEvent::QualityBandChanged {
+            from: QualityBand::Moderate,
+            to: QualityBand::Offline
+        }));
+    }
+
+    #[test]
+    fn test_listeners() {
+        let monitor = ConnectivityMonitor::new(
+            &NetworkStatus::offline(),
+            MonitorConfig {
+                debounce_ms: 0,
+                ..MonitorConfig::default()
+            },
+        );
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let id = monitor.add_listener(Arc::new(
+            move |event: &ConnectivityEvent, _: &NetworkStatus| {
+                sink.lock().unwrap().push(event.clone());
+            },
+        ));
+
+        monitor.observe(&status(ConnectionType::WiFi), Instant::now());
+        assert_eq!(
+            seen.lock().unwrap()[0],
+            ConnectivityEvent::WentOnline {
+                connection_type: ConnectionType::WiFi
+            }
+        );
+
+        assert!(monitor.remove_listener(id));
+        monitor.observe(&status(ConnectionType::Cellular2G), Instant::now());
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+}
@@ -2,15 +2,35 @@ pub mod cache;
 pub mod connectivity;
 pub mod optimization;
 pub mod queue;
-
-use std::sync::Mutex;
-
-pub use cache::{CacheStats, CachedResponse, HttpCache};
+pub mod reachability;
+pub mod request;
+mod schema;
+pub mod transport;
+pub mod upload;
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub use cache::{
+    CacheConfig, CacheDecision, CacheLookup, CacheStats, CachedResponse, CounterStats,
+    EvictionPolicy, Freshness, HttpCache, StatsScope,
+};
 pub use connectivity::{
-    BandwidthEstimator, ConnectionType, ImageQuality, NetworkStatus,
+    BandwidthEstimator, BandwidthSnapshot, ConnectionType, ConnectivityEvent, ConnectivityListener,
+    ConnectivityMonitor, ImageQuality, MonitorConfig, NetworkStatus, PowerState, QualityBand,
 };
 pub use optimization::{compress_string, decompress_string, should_compress};
-pub use queue::{Priority, QueuedRequest, RequestQueue};
+pub use queue::{
+    AttemptRecord, BackoffStrategy, CapacityLimit, CoalescePolicy, DeadLetter, DeadLetterReason,
+    EnqueueOptions, EnqueueOutcome, Priority, QueueLimits, QueuedRequest, RequestConstraints,
+    RequestFailure, RequestQueue, RetryPolicy, SchedulerConfig, SendConditions,
+};
+pub use reachability::{ProbeConfig, ProbeResult, Reachability};
+pub use request::{HttpMethod, RequestBody, RequestError, RequestSpec};
+pub use transport::{
+    DrainExecutor, DrainReport, ReconnectDrain, Transport, TransportError, TransportResponse,
+};
+pub use upload::{UploadError, UploadJob, UploadOptions, UploadQueue, UploadState};
 
 // ─── Error Type ─────────────────────────────────────────────────────
 
@@ -26,6 +46,10 @@ pub enum NetworkError {
     NotInitialized,
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
+    #[error("IO error: {0}")]
+    IoError(String),
+    #[error("Upload error: {0}")]
+    UploadError(String),
 }
 
 impl From<queue::QueueError> for NetworkError {
@@ -34,6 +58,18 @@ impl From<queue::QueueError> for NetworkError {
     }
 }
 
+impl From<request::RequestError> for NetworkError {
+    fn from(e: request::RequestError) -> Self {
+        NetworkError::InvalidConfig(e.to_string())
+    }
+}
+
+impl From<upload::UploadError> for NetworkError {
+    fn from(e: upload::UploadError) -> Self {
+        NetworkError::UploadError(e.to_string())
+    }
+}
+
 impl From<cache::CacheError> for NetworkError {
     fn from(e: cache::CacheError) -> Self {
         NetworkError::CacheError(e.to_string())
@@ -48,6 +84,15 @@ impl From<optimization::CompressionError> for NetworkError {
 
 // ─── Configuration ──────────────────────────────────────────────────
 
+/// Where bandwidth estimates are saved between launches (None in memory)
+fn bandwidth_state_path(config: &NetworkConfig) -> Option<String> {
+    (config.db_dir != ":memory:")
+        .then(|| format!("{}/{}.network.bandwidth.json", config.db_dir, config.app_id))
+}
+
+/// How long `cleanup` keeps dead letters around for inspection
+const DEAD_LETTER_RETENTION_HOURS: u32 = 30 * 24;
+
 /// Configuration for the network engine
 pub struct NetworkConfig {
     /// App identifier (used for database naming)
@@ -62,6 +107,14 @@ pub struct NetworkConfig {
     pub enable_cache: bool,
     /// Whether to auto-compress large request bodies
     pub auto_compress: bool,
+    /// Which cache entries to evict first when over budget
+    pub cache_eviction_policy: EvictionPolicy,
+    /// Responses larger than this are never cached (None = no limit)
+    pub max_cache_entry_bytes: Option<u64>,
+    /// Cache budget per host, so one host can't evict the rest (None = no limit)
+    pub cache_host_quota_bytes: Option<u64>,
+    /// Whether to gzip cached bodies at rest when they compress well
+    pub compress_cache_bodies: bool,
 }
 
 // ─── Main Network Engine ────────────────────────────────────────────
@@ -69,9 +122,18 @@ pub struct NetworkConfig {
 /// The main network engine exposed to all platforms
 pub struct RajeevNetwork {
     queue: Option<RequestQueue>,
+    /// Resumable uploads, enabled with the queue
+    uploads: Option<UploadQueue>,
     cache: Option<HttpCache>,
     bandwidth: BandwidthEstimator,
     status: Mutex<NetworkStatus>,
+    power: Mutex<Option<PowerState>>,
+    monitor: ConnectivityMonitor,
+    /// What to send when coming online
+    reconnect_drain: Mutex<Option<ReconnectDrain>>,
+    probe_config: Mutex<ProbeConfig>,
+    /// Last probe result, kept while the connection type stays the same
+    last_probe: Mutex<Option<ProbeResult>>,
     config: NetworkConfig,
 }
 
@@ -85,18 +147,51 @@ impl RajeevNetwork {
             None
         };
 
+        let uploads = if config.enable_queue {
+            let uploads_path = format!("{}/{}.network.uploads.db", config.db_dir, config.app_id);
+            Some(UploadQueue::new(&uploads_path)?)
+        } else {
+            None
+        };
+
         let cache = if config.enable_cache {
             let cache_path = format!("{}/{}.network.cache.db", config.db_dir, config.app_id);
-            Some(HttpCache::new(&cache_path, config.max_cache_bytes).map_err(|e| NetworkError::CacheError(e.to_string()))?)
+            let cache_config = CacheConfig {
+                max_size_bytes: config.max_cache_bytes,
+                eviction_policy: config.cache_eviction_policy,
+                max_entry_bytes: config.max_cache_entry_bytes,
+                per_host_quota_bytes: config.cache_host_quota_bytes,
+                compress_bodies: config.compress_cache_bodies,
+                ..CacheConfig::new(config.max_cache_bytes)
+            };
+            Some(
+                HttpCache::with_config(&cache_path, cache_config)
+                    .map_err(|e| NetworkError::CacheError(e.to_string()))?,
+            )
         } else {
             None
         };
 
+        // Start from what was measured last run, if saved
+        let bandwidth = BandwidthEstimator::new(50);
+        if let Some(path) = bandwidth_state_path(&config)
+            && let Ok(saved) = std::fs::read_to_string(&path)
+        {
+            bandwidth.import_state(&saved);
+        }
+
+        let status = NetworkStatus::from_connection_type(ConnectionType::Unknown);
         Ok(RajeevNetwork {
             queue,
+            uploads,
             cache,
-            bandwidth: BandwidthEstimator::new(50),
-            status: Mutex::new(NetworkStatus::from_connection_type(ConnectionType::Unknown)),
+            bandwidth,
+            monitor: ConnectivityMonitor::new(&status, MonitorConfig::default()),
+            status: Mutex::new(status),
+            power: Mutex::new(None),
+            reconnect_drain: Mutex::new(None),
+            probe_config: Mutex::new(ProbeConfig::default()),
+            last_probe: Mutex::new(None),
             config,
         })
     }
@@ -131,19 +226,166 @@ impl RajeevNetwork {
         }
         status.save_data = save_data;
 
+        // Measurements and probe results only describe the connection they
+        // were taken on
+        self.bandwidth.set_connection_type(conn_type);
+        let same_connection = self.raw_status().connection_type == conn_type;
+        if let Ok(mut last_probe) = self.last_probe.lock() {
+            match *last_probe {
+                Some(ref probe) if same_connection => status.apply_reachability(probe),
+                _ => *last_probe = None,
+            }
+        }
+
+        self.set_status(status);
+    }
+
+    /// Store `status` and report any resulting connectivity change
+    fn set_status(&self, status: NetworkStatus) {
         if let Ok(mut current) = self.status.lock() {
-            *current = status;
+            *current = status.clone();
         }
+        let mut observed = status;
+        observed.blend_measurements(&self.bandwidth.snapshot());
+        let events = self.monitor.observe(&observed, Instant::now());
+        self.handle_connectivity_events(&events);
     }
 
-    /// Get current network status
+    /// Set the reachability probe from a `ProbeConfig` JSON object
+    pub fn set_reachability_probe(&self, config_json: String) -> Result<(), NetworkError> {
+        let config: ProbeConfig = serde_json::from_str(&config_json)
+            .map_err(|e| NetworkError::InvalidConfig(format!("Invalid probe config: {}", e)))?;
+        if let Ok(mut current) = self.probe_config.lock() {
+            *current = config;
+        }
+        Ok(())
+    }
+
+    /// Probe reachability through `transport` and fold the result into the
+    /// network status (a captive portal or dead link reads as offline).
+    /// Returns the `ProbeResult` as JSON.
+    pub fn check_reachability(&self, transport: &dyn Transport) -> String {
+        let mut status = self.raw_status();
+        let result = if status.connection_type == ConnectionType::Offline {
+            ProbeResult {
+                reachability: Reachability::NoRoute,
+                status_code: None,
+                latency_ms: None,
+                error: Some("No connection".to_string()),
+            }
+        } else {
+            let config = self
+                .probe_config
+                .lock()
+                .map(|c| c.clone())
+                .unwrap_or_default();
+            reachability::probe(transport, &config)
+        };
+
+        if let Some(latency) = result.latency_ms {
+            self.bandwidth.record_rtt(latency);
+        }
+        status.apply_reachability(&result);
+        if let Ok(mut last_probe) = self.last_probe.lock() {
+            *last_probe = Some(result.clone());
+        }
+        self.set_status(status);
+        serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    /// Report a connectivity change whose debounce window has run out, as a
+    /// JSON array of events. Call periodically (e.g. from a timer) when
+    /// status updates are sparse.
+    pub fn poll_connectivity(&self) -> String {
+        let events = self.monitor.poll(Instant::now());
+        self.handle_connectivity_events(&events);
+        serde_json::to_string(&events).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Set debounce and hysteresis from a `MonitorConfig` JSON object
+    pub fn set_connectivity_config(&self, config_json: String) -> Result<(), NetworkError> {
+        let config: MonitorConfig = serde_json::from_str(&config_json).map_err(|e| {
+            NetworkError::InvalidConfig(format!("Invalid connectivity config: {}", e))
+        })?;
+        self.monitor.set_config(config);
+        Ok(())
+    }
+
+    /// Be told about online/offline, connection type and quality band
+    /// changes. Returns an ID for `remove_connectivity_listener`.
+    pub fn add_connectivity_listener(&self, listener: Arc<dyn ConnectivityListener>) -> u64 {
+        self.monitor.add_listener(listener)
+    }
+
+    pub fn remove_connectivity_listener(&self, listener_id: u64) -> bool {
+        self.monitor.remove_listener(listener_id)
+    }
+
+    /// Ask `drain.executor` to drain the queue and resume unfinished
+    /// uploads whenever the device comes back online (None to stop)
+    pub fn set_drain_on_reconnect(&self, drain: Option<ReconnectDrain>) {
+        if let Ok(mut current) = self.reconnect_drain.lock() {
+            *current = drain;
+        }
+    }
+
+    /// Send what `set_drain_on_reconnect` asked for. Call from the thread
+    /// the `DrainExecutor` picked. Returns None if no drain is set.
+    pub fn drain_on_reconnect(&self) -> Result<Option<DrainReport>, NetworkError> {
+        let drain = match self.reconnect_drain.lock().ok().and_then(|d| d.clone()) {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+        let report = self.drain_queue(drain.transport.as_ref(), drain.max_requests)?;
+        // An interrupted upload keeps its offset for the next drain
+        let _ = self.resume_uploads(drain.transport.as_ref(), drain.max_upload_chunks);
+        Ok(Some(report))
+    }
+
+    fn handle_connectivity_events(&self, events: &[ConnectivityEvent]) {
+        if !events
+            .iter()
+            .any(|e| matches!(e, ConnectivityEvent::WentOnline { .. }))
+        {
+            return;
+        }
+        let drain = self.reconnect_drain.lock().ok().and_then(|d| d.clone());
+        if let Some(drain) = drain {
+            drain.executor.schedule_drain();
+        }
+    }
+
+    /// Get current network status, with measured bandwidth and RTT blended in
     pub fn get_status(&self) -> NetworkStatus {
+        let mut status = self.raw_status();
+        status.blend_measurements(&self.bandwidth.snapshot());
+        status
+    }
+
+    /// Status as last reported by the platform and probes
+    fn raw_status(&self) -> NetworkStatus {
         self.status
             .lock()
             .map(|s| s.clone())
             .unwrap_or_else(|_| NetworkStatus::offline())
     }
 
+    /// Update device power state (called from platform layer)
+    pub fn update_power_state(&self, is_charging: bool, battery_percent: Option<u8>) {
+        if let Ok(mut power) = self.power.lock() {
+            *power = Some(PowerState {
+                is_charging,
+                battery_percent: battery_percent.map(|b| b.min(100)),
+            });
+        }
+    }
+
+    /// Network and power state that queued requests are checked against
+    fn send_conditions(&self) -> SendConditions {
+        let power = self.power.lock().ok().and_then(|p| *p);
+        SendConditions::new(&self.get_status(), power)
+    }
+
     /// Get suggested timeout for current network conditions
     pub fn get_suggested_timeout_ms(&self) -> u64 {
         let status = self.get_status();
@@ -167,68 +409,129 @@ impl RajeevNetwork {
         self.bandwidth.estimate_kbps()
     }
 
+    /// Record a round-trip time measured by the platform layer
+    pub fn record_rtt(&self, rtt_ms: u64) {
+        self.bandwidth.record_rtt(rtt_ms);
+    }
+
+    /// Throughput percentiles and RTT as a `BandwidthSnapshot` JSON object
+    pub fn get_bandwidth_stats(&self) -> String {
+        serde_json::to_string(&self.bandwidth.snapshot()).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    /// Persist bandwidth estimates so the next launch starts from them
+    /// (no-op for in-memory databases). Also done by `cleanup`.
+    pub fn save_bandwidth_state(&self) -> Result<(), NetworkError> {
+        match bandwidth_state_path(&self.config) {
+            Some(path) => std::fs::write(&path, self.bandwidth.export_state())
+                .map_err(|e| NetworkError::IoError(format!("{}: {}", path, e))),
+            None => Ok(()),
+        }
+    }
+
     // ─── Request Queue ──────────────────────────────────────────────
 
     /// Queue a request for later sending
+    pub fn enqueue(&self, spec: RequestSpec) -> Result<EnqueueOutcome, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+
+        let headers_json = spec.to_headers_json();
+        let mut options = spec.options;
+        let (final_body, compressed) = self.prepare_body(spec.body.to_bytes(), options.compress)?;
+        options.compress = compressed;
+
+        Ok(queue.enqueue_with_options(
+            spec.method.as_str(),
+            &spec.url,
+            &headers_json,
+            final_body.as_deref(),
+            &options,
+        )?)
+    }
+
+    /// String form of `enqueue` for FFI. An unknown method or priority, or
+    /// headers that aren't a JSON object of strings, is an error.
+    /// Returns the request ID.
+    #[allow(clippy::too_many_arguments)]
     pub fn enqueue_request(
         &self,
         method: String,
         url: String,
         headers_json: String,
-        body: Option<String>,
+        body: Option<Vec<u8>>,
         priority: String,
         compress: bool,
         tag: Option<String>,
+        idempotency_key: Option<String>,
     ) -> Result<String, NetworkError> {
-        let queue = self.queue.as_ref().ok_or(NetworkError::InvalidConfig(
-            "Queue not enabled".to_string(),
-        ))?;
+        let mut spec = RequestSpec::new(method.parse()?, url)
+            .headers_json(&headers_json)?
+            .priority(priority.parse()?)
+            .compress(compress);
+        spec.body = body.map(RequestBody::Bytes).unwrap_or_default();
+        spec.options.tag = tag;
+        spec.options.idempotency_key = idempotency_key;
+
+        Ok(self.enqueue(spec)?.id)
+    }
 
-        let pri = match priority.to_lowercase().as_str() {
-            "low" => Priority::Low,
-            "high" => Priority::High,
-            "critical" => Priority::Critical,
-            _ => Priority::Normal,
-        };
+    /// Queue a request with `EnqueueOptions` given as JSON (e.g.
+    /// `{"priority":"Critical","coalesce":"MergeJson"}`; omitted fields take
+    /// their defaults). `compress` asks for the body to be compressed.
+    /// Returns the `EnqueueOutcome` as JSON.
+    pub fn enqueue_request_with_options(
+        &self,
+        method: String,
+        url: String,
+        headers_json: String,
+        body: Option<Vec<u8>>,
+        options_json: String,
+    ) -> Result<String, NetworkError> {
+        let options: EnqueueOptions = serde_json::from_str(&options_json)
+            .map_err(|e| NetworkError::InvalidConfig(format!("Invalid enqueue options: {}", e)))?;
+        let mut spec = RequestSpec::new(method.parse()?, url).headers_json(&headers_json)?;
+        spec.body = body.map(RequestBody::Bytes).unwrap_or_default();
+        spec.options = options;
+
+        let outcome = self.enqueue(spec)?;
+        serde_json::to_string(&outcome).map_err(|e| NetworkError::QueueError(e.to_string()))
+    }
 
-        // Auto-compress if enabled and body is large
-        let final_body = if self.config.auto_compress && compress {
-            if let Some(ref b) = body {
-                if should_compress(b.as_bytes()) {
-                    Some(compress_string(b)?)
-                } else {
-                    body
-                }
-            } else {
-                body
+    /// Auto-compress a body if enabled, requested and worthwhile. The flag
+    /// records whether the body actually ended up gzipped.
+    fn prepare_body(
+        &self,
+        body: Option<Vec<u8>>,
+        compress: bool,
+    ) -> Result<(Option<Vec<u8>>, bool), NetworkError> {
+        match body {
+            Some(b) if self.config.auto_compress && compress && should_compress(&b) => {
+                Ok((Some(optimization::compress(&b)?), true))
             }
-        } else {
-            body
-        };
-
-        let id = queue.enqueue(
-            &method,
-            &url,
-            &headers_json,
-            final_body.as_deref(),
-            pri,
-            compress,
-            tag.as_deref(),
-        )?;
-
-        Ok(id)
+            other => Ok((other, false)),
+        }
     }
 
-    /// Get next request to send based on current network quality
-    pub fn dequeue_request(&self) -> Result<Option<String>, NetworkError> {
+    /// Get next request to send based on current network quality. It is
+    /// leased to the caller; pass its `lease_token` to
+    /// `complete_leased_request` / `fail_leased_request`.
+    pub fn dequeue(&self) -> Result<Option<QueuedRequest>, NetworkError> {
         let queue = self.queue.as_ref().ok_or(NetworkError::InvalidConfig(
             "Queue not enabled".to_string(),
         ))?;
 
-        let quality = self.get_status().quality_score;
-        let request = queue.dequeue(quality)?;
+        Ok(queue.dequeue_when(
+            &self.send_conditions(),
+            std::time::Duration::from_secs(queue::DEFAULT_LEASE_SECONDS),
+        )?)
+    }
 
-        match request {
+    /// `dequeue` with the request as JSON, for FFI
+    pub fn dequeue_request(&self) -> Result<Option<String>, NetworkError> {
+        match self.dequeue()? {
             Some(req) => Ok(Some(serde_json::to_string(&req).map_err(|e| {
                 NetworkError::QueueError(e.to_string())
             })?)),
@@ -236,6 +539,23 @@ impl RajeevNetwork {
         }
     }
 
+    /// Lease up to `max_requests` requests that fit in what the current
+    /// connection can move within the suggested timeout, as a JSON array.
+    /// Each must be finished with its own `lease_token`.
+    pub fn dequeue_batch(&self, max_requests: u32) -> Result<String, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+
+        let status = self.get_status();
+        let budget = status.transfer_budget_bytes(self.bandwidth.estimate_kbps());
+        let lease = status.suggested_timeout()
+            + std::time::Duration::from_secs(queue::DEFAULT_LEASE_SECONDS);
+        let batch = queue.dequeue_batch(&self.send_conditions(), max_requests, budget, lease)?;
+        serde_json::to_string(&batch).map_err(|e| NetworkError::QueueError(e.to_string()))
+    }
+
     /// Mark a queued request as completed
     pub fn complete_request(&self, request_id: String) -> Result<bool, NetworkError> {
         let queue = self.queue.as_ref().ok_or(NetworkError::InvalidConfig(
@@ -252,6 +572,171 @@ impl RajeevNetwork {
         Ok(queue.fail(&request_id)?)
     }
 
+    /// Mark a queued request as failed, recording the status code and/or
+    /// error in its attempt history. Permanent 4xx failures are not retried;
+    /// `retry_after` (the response header) is honored for 429 and 503.
+    pub fn fail_request_with(
+        &self,
+        request_id: String,
+        status_code: Option<u16>,
+        error: Option<String>,
+        retry_after: Option<String>,
+    ) -> Result<bool, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let failure = RequestFailure {
+            status_code,
+            error,
+            retry_after,
+        };
+        Ok(queue.fail_with(&request_id, &failure)?)
+    }
+
+    /// Complete a request dequeued with `lease_token`. Errors if the lease
+    /// lapsed and another worker picked the request up.
+    pub fn complete_leased_request(
+        &self,
+        request_id: String,
+        lease_token: String,
+    ) -> Result<(), NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(queue.complete_leased(&request_id, &lease_token)?)
+    }
+
+    /// Fail a request dequeued with `lease_token` (see `fail_request_with`)
+    pub fn fail_leased_request(
+        &self,
+        request_id: String,
+        lease_token: String,
+        status_code: Option<u16>,
+        error: Option<String>,
+        retry_after: Option<String>,
+    ) -> Result<bool, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let failure = RequestFailure {
+            status_code,
+            error,
+            retry_after,
+        };
+        Ok(queue.fail_leased(&request_id, &lease_token, &failure)?)
+    }
+
+    /// Set the retry policy (a `RetryPolicy` as JSON) for a priority
+    pub fn set_retry_policy(
+        &self,
+        priority: String,
+        policy_json: String,
+    ) -> Result<(), NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let policy: RetryPolicy = serde_json::from_str(&policy_json)
+            .map_err(|e| NetworkError::InvalidConfig(format!("Invalid retry policy: {}", e)))?;
+        queue
+            .set_retry_policy(priority.parse()?, policy)
+            .map_err(|e| NetworkError::InvalidConfig(e.to_string()))
+    }
+
+    /// Configure priority aging and weighted round-robin from a
+    /// `SchedulerConfig` JSON object
+    pub fn set_scheduler_config(&self, config_json: String) -> Result<(), NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let config: SchedulerConfig = serde_json::from_str(&config_json)
+            .map_err(|e| NetworkError::InvalidConfig(format!("Invalid scheduler config: {}", e)))?;
+        queue.set_scheduler(config);
+        Ok(())
+    }
+
+    /// Set queue capacity limits from a `QueueLimits` JSON object
+    pub fn set_queue_limits(&self, limits_json: String) -> Result<(), NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let limits: QueueLimits = serde_json::from_str(&limits_json)
+            .map_err(|e| NetworkError::InvalidConfig(format!("Invalid queue limits: {}", e)))?;
+        queue.set_limits(limits);
+        Ok(())
+    }
+
+    /// List dead letters (requests that will not be retried), most recent
+    /// first, as JSON
+    pub fn list_dead_letters(&self, limit: u32) -> Result<String, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let letters = queue.list_dead_letters(limit)?;
+        serde_json::to_string(&letters).map_err(|e| NetworkError::QueueError(e.to_string()))
+    }
+
+    /// Get a dead letter by its original request ID as JSON
+    pub fn get_dead_letter(&self, request_id: String) -> Result<Option<String>, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        match queue.get_dead_letter(&request_id)? {
+            Some(l) => Ok(Some(
+                serde_json::to_string(&l).map_err(|e| NetworkError::QueueError(e.to_string()))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Put a dead letter back in the queue with a fresh retry budget
+    pub fn requeue_dead_letter(&self, request_id: String) -> Result<bool, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(queue.requeue_dead_letter(&request_id)?)
+    }
+
+    /// Delete dead letters older than `older_than_hours` (all if None)
+    pub fn purge_dead_letters(&self, older_than_hours: Option<u32>) -> Result<u64, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(queue.purge_dead_letters(older_than_hours)?)
+    }
+
+    /// Send queued requests through `transport` until nothing eligible is
+    /// left or `max_requests` have been attempted
+    pub fn drain_queue(
+        &self,
+        transport: &dyn Transport,
+        max_requests: u32,
+    ) -> Result<DrainReport, NetworkError> {
+        let queue = self
+            .queue
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let status = self.get_status();
+        let power = self.power.lock().ok().and_then(|p| *p);
+        Ok(transport::drain_queue(
+            queue,
+            transport,
+            &self.bandwidth,
+            &status,
+            power,
+            max_requests,
+        )?)
+    }
+
     /// Cancel a specific request
     pub fn cancel_request(&self, request_id: String) -> Result<bool, NetworkError> {
         let queue = self.queue.as_ref().ok_or(NetworkError::InvalidConfig(
@@ -284,19 +769,135 @@ impl RajeevNetwork {
         Ok(queue.clear()?)
     }
 
+    // ─── Uploads ────────────────────────────────────────────────────
+
+    /// Register a resumable (tus 1.0) upload of a file. Nothing is sent
+    /// until `resume_upload` / `resume_uploads`.
+    pub fn create_upload(
+        &self,
+        file_path: &str,
+        endpoint: &str,
+        options: &UploadOptions,
+    ) -> Result<UploadJob, NetworkError> {
+        let uploads = self
+            .uploads
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(uploads.create(file_path, endpoint, options)?)
+    }
+
+    /// `create_upload` with `UploadOptions` as JSON, for FFI. Returns the
+    /// upload ID.
+    pub fn enqueue_upload(
+        &self,
+        file_path: String,
+        endpoint: String,
+        options_json: String,
+    ) -> Result<String, NetworkError> {
+        let options: UploadOptions = serde_json::from_str(&options_json)
+            .map_err(|e| NetworkError::InvalidConfig(format!("Invalid upload options: {}", e)))?;
+        Ok(self.create_upload(&file_path, &endpoint, &options)?.id)
+    }
+
+    /// Send up to `max_chunks` more chunks of an upload through `transport`,
+    /// from the last byte the server acknowledged. Nothing is sent while
+    /// offline.
+    pub fn resume_upload(
+        &self,
+        upload_id: &str,
+        transport: &dyn Transport,
+        max_chunks: u32,
+    ) -> Result<UploadJob, NetworkError> {
+        let uploads = self
+            .uploads
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let status = self.get_status();
+        if !status.is_online {
+            return uploads.get(upload_id)?.ok_or_else(|| {
+                NetworkError::UploadError(format!("Upload not found: {}", upload_id))
+            });
+        }
+        Ok(uploads.resume(upload_id, transport, status.suggested_timeout(), max_chunks)?)
+    }
+
+    /// Resume every unfinished upload, up to `max_chunks` chunks each.
+    /// Returns the uploads that are now complete.
+    pub fn resume_uploads(
+        &self,
+        transport: &dyn Transport,
+        max_chunks: u32,
+    ) -> Result<Vec<UploadJob>, NetworkError> {
+        let uploads = self
+            .uploads
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        let mut completed = Vec::new();
+        for id in uploads.unfinished()? {
+            // An interrupted upload keeps its offset and resumes next time;
+            // a failed one waits for `retry_upload`
+            if let Ok(job) = self.resume_upload(&id, transport, max_chunks)
+                && job.state == UploadState::Completed
+            {
+                completed.push(job);
+            }
+        }
+        Ok(completed)
+    }
+
+    pub fn get_upload(&self, upload_id: &str) -> Result<Option<UploadJob>, NetworkError> {
+        let uploads = self
+            .uploads
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(uploads.get(upload_id)?)
+    }
+
+    /// All uploads, oldest first
+    pub fn list_uploads(&self) -> Result<Vec<UploadJob>, NetworkError> {
+        let uploads = self
+            .uploads
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(uploads.list()?)
+    }
+
+    /// Put a failed upload back in line for `resume_upload` /
+    /// `resume_uploads`. Returns false if it hadn't failed.
+    pub fn retry_upload(&self, upload_id: &str) -> Result<bool, NetworkError> {
+        let uploads = self
+            .uploads
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(uploads.retry(upload_id)?)
+    }
+
+    /// Stop tracking an upload (the partial upload is left on the server)
+    pub fn cancel_upload(&self, upload_id: &str) -> Result<bool, NetworkError> {
+        let uploads = self
+            .uploads
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Queue not enabled".to_string()))?;
+        Ok(uploads.remove(upload_id)?)
+    }
+
     // ─── Cache ──────────────────────────────────────────────────────
 
     /// Get a cached response
-    pub fn get_cached(
+    pub fn get_cached_response(
         &self,
-        method: String,
-        url: String,
-    ) -> Result<Option<String>, NetworkError> {
+        method: HttpMethod,
+        url: &str,
+    ) -> Result<Option<CachedResponse>, NetworkError> {
         let cache = self.cache.as_ref().ok_or(NetworkError::InvalidConfig(
             "Cache not enabled".to_string(),
         ))?;
+        Ok(cache.get(method.as_str(), url)?)
+    }
 
-        let entry = cache.get(&method, &url)?;
+    /// `get_cached_response` as JSON, for FFI
+    pub fn get_cached(&self, method: String, url: String) -> Result<Option<String>, NetworkError> {
+        let entry = self.get_cached_response(method.parse()?, &url)?;
         match entry {
             Some(e) => Ok(Some(serde_json::to_string(&e).map_err(|e| {
                 NetworkError::CacheError(e.to_string())
@@ -305,6 +906,50 @@ impl RajeevNetwork {
         }
     }
 
+    /// Get the cached variant matching the request's headers (for responses
+    /// stored with `Vary`)
+    pub fn get_cached_for_request(
+        &self,
+        method: String,
+        url: String,
+        request_headers_json: String,
+    ) -> Result<Option<String>, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+
+        let entry = cache.get_with_headers(&method, &url, &request_headers_json)?;
+        match entry {
+            Some(e) => Ok(Some(
+                serde_json::to_string(&e).map_err(|e| NetworkError::CacheError(e.to_string()))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a cached response, including stale entries that may still be
+    /// served, as JSON `{ entry, freshness }`
+    pub fn lookup_cached(
+        &self,
+        method: String,
+        url: String,
+        request_headers_json: String,
+    ) -> Result<Option<String>, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+
+        let status = self.get_status();
+        match cache.lookup(&method, &url, &request_headers_json, &status)? {
+            Some(l) => Ok(Some(
+                serde_json::to_string(&l).map_err(|e| NetworkError::CacheError(e.to_string()))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
     /// Store a response in cache
     pub fn cache_response(
         &self,
@@ -312,7 +957,7 @@ impl RajeevNetwork {
         url: String,
         status_code: u16,
         headers_json: String,
-        body: String,
+        body: Vec<u8>,
         ttl_seconds: u64,
         etag: Option<String>,
         last_modified: Option<String>,
@@ -335,6 +980,80 @@ impl RajeevNetwork {
         Ok(())
     }
 
+    /// Store a response in cache with freshness derived from its headers.
+    /// `tags` are surrogate keys for `invalidate_cache_tag`.
+    /// Returns whether the response was stored.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cache_http_response(
+        &self,
+        method: String,
+        url: String,
+        request_headers_json: String,
+        status_code: u16,
+        headers_json: String,
+        body: Vec<u8>,
+        tags: Vec<String>,
+    ) -> Result<bool, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+
+        let decision = cache.put_response(
+            &method,
+            &url,
+            &request_headers_json,
+            status_code,
+            &headers_json,
+            &body,
+            &tags.iter().map(String::as_str).collect::<Vec<_>>(),
+        )?;
+        Ok(decision.cacheable)
+    }
+
+    /// Get conditional request headers (`If-None-Match` / `If-Modified-Since`)
+    /// for revalidating a cached entry, as JSON
+    pub fn get_revalidation_headers(
+        &self,
+        method: String,
+        url: String,
+        request_headers_json: String,
+    ) -> Result<Option<String>, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+
+        match cache.revalidation_headers(&method, &url, &request_headers_json)? {
+            Some(h) => Ok(Some(
+                serde_json::to_string(&h).map_err(|e| NetworkError::CacheError(e.to_string()))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Handle a `304 Not Modified` response: refresh the cached entry and
+    /// return it (with its stored body) as JSON
+    pub fn handle_not_modified(
+        &self,
+        method: String,
+        url: String,
+        request_headers_json: String,
+        headers_json: String,
+    ) -> Result<Option<String>, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+
+        match cache.refresh_not_modified(&method, &url, &request_headers_json, &headers_json)? {
+            Some(e) => Ok(Some(
+                serde_json::to_string(&e).map_err(|e| NetworkError::CacheError(e.to_string()))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
     /// Invalidate a cache entry
     pub fn invalidate_cache(&self, method: String, url: String) -> Result<bool, NetworkError> {
         let cache = self.cache.as_ref().ok_or(NetworkError::InvalidConfig(
@@ -343,6 +1062,35 @@ impl RajeevNetwork {
         Ok(cache.invalidate(&method, &url)?)
     }
 
+    /// Invalidate every cache entry whose URL (or path, if it starts with
+    /// `/`) starts with `prefix`
+    pub fn invalidate_cache_prefix(&self, prefix: String) -> Result<u64, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+        Ok(cache.invalidate_prefix(&prefix)?)
+    }
+
+    /// Invalidate every cache entry whose URL (or path, if it starts with
+    /// `/`) matches a glob pattern
+    pub fn invalidate_cache_glob(&self, pattern: String) -> Result<u64, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+        Ok(cache.invalidate_glob(&pattern)?)
+    }
+
+    /// Invalidate every cache entry tagged with a surrogate key
+    pub fn invalidate_cache_tag(&self, tag: String) -> Result<u64, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+        Ok(cache.invalidate_tag(&tag)?)
+    }
+
     /// Get cache statistics
     pub fn get_cache_stats(&self) -> Result<String, NetworkError> {
         let cache = self.cache.as_ref().ok_or(NetworkError::InvalidConfig(
@@ -353,6 +1101,34 @@ impl RajeevNetwork {
         serde_json::to_string(&stats).map_err(|e| NetworkError::CacheError(e.to_string()))
     }
 
+    /// Get cache counters by `global`, `host` or `path` prefix over the
+    /// last `window_hours` (all time if None), as JSON
+    pub fn get_cache_stats_breakdown(
+        &self,
+        scope: String,
+        window_hours: Option<u32>,
+    ) -> Result<String, NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+        let scope = StatsScope::parse(&scope).ok_or_else(|| {
+            NetworkError::InvalidConfig(format!("Unknown stats scope: {}", scope))
+        })?;
+
+        let breakdown = cache.stats_breakdown(scope, window_hours)?;
+        serde_json::to_string(&breakdown).map_err(|e| NetworkError::CacheError(e.to_string()))
+    }
+
+    /// Reset cache hit/miss/revalidation/eviction counters
+    pub fn reset_cache_stats(&self) -> Result<(), NetworkError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or(NetworkError::InvalidConfig("Cache not enabled".to_string()))?;
+        Ok(cache.reset_stats()?)
+    }
+
     /// Clear entire cache
     pub fn clear_cache(&self) -> Result<(), NetworkError> {
         let cache = self.cache.as_ref().ok_or(NetworkError::InvalidConfig(
@@ -369,8 +1145,10 @@ impl RajeevNetwork {
             let _ = cache.cleanup_expired();
         }
         if let Some(ref queue) = self.queue {
-            let _ = queue.cleanup_old(24); // Remove non-critical requests older than 24h
+            let _ = queue.cleanup_old(24); // Dead-letter non-critical requests older than 24h
+            let _ = queue.purge_dead_letters(Some(DEAD_LETTER_RETENTION_HOURS));
         }
+        let _ = self.save_bandwidth_state();
         Ok(())
     }
 }
@@ -389,6 +1167,10 @@ mod tests {
             enable_queue: true,
             enable_cache: true,
             auto_compress: true,
+            cache_eviction_policy: EvictionPolicy::Lru,
+            max_cache_entry_bytes: None,
+            cache_host_quota_bytes: None,
+            compress_cache_bodies: true,
         })
         .unwrap()
     }
@@ -400,9 +1182,18 @@ mod tests {
 
         RajeevNetwork {
             queue,
+            uploads: Some(UploadQueue::new(":memory:").unwrap()),
             cache,
             bandwidth: BandwidthEstimator::new(50),
             status: Mutex::new(NetworkStatus::from_connection_type(ConnectionType::WiFi)),
+            power: Mutex::new(None),
+            monitor: ConnectivityMonitor::new(
+                &NetworkStatus::from_connection_type(ConnectionType::WiFi),
+                MonitorConfig::default(),
+            ),
+            reconnect_drain: Mutex::new(None),
+            probe_config: Mutex::new(ProbeConfig::default()),
+            last_probe: Mutex::new(None),
             config: NetworkConfig {
                 app_id: "test".to_string(),
                 db_dir: ":memory:".to_string(),
@@ -410,6 +1201,10 @@ mod tests {
                 enable_queue: true,
                 enable_cache: true,
                 auto_compress: true,
+                cache_eviction_policy: EvictionPolicy::Lru,
+                max_cache_entry_bytes: None,
+                cache_host_quota_bytes: None,
+                compress_cache_bodies: true,
             },
         }
     }
@@ -459,10 +1254,11 @@ mod tests {
                 "POST".to_string(),
                 "https://api.test.com/data".to_string(),
                 "{}".to_string(),
-                Some("{\"key\":\"value\"}".to_string()),
+                Some(b"{\"key\":\"value\"}".to_vec()),
                 "high".to_string(),
                 false,
                 None,
+                None,
             )
             .unwrap();
 
@@ -473,6 +1269,64 @@ mod tests {
         assert!(req_json.is_some());
     }
 
+    #[test]
+    fn test_typed_enqueue_and_dequeue() {
+        let network = create_test_network_inmemory();
+        network.update_status("wifi", 0, 0, false);
+
+        let outcome = network
+            .enqueue(
+                RequestSpec::post("https://api.test.com/events")
+                    .header("Authorization", "Bearer t")
+                    .json(serde_json::json!({ "event": "open" }))
+                    .priority(Priority::High)
+                    .tag("events"),
+            )
+            .unwrap();
+
+        let req = network.dequeue().unwrap().unwrap();
+        assert_eq!(req.id, outcome.id);
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.priority, Priority::High as i32);
+        assert_eq!(req.tag.as_deref(), Some("events"));
+        assert_eq!(
+            req.headers_json,
+            r#"{"Authorization":"Bearer t","Content-Type":"application/json"}"#
+        );
+    }
+
+    #[test]
+    fn test_string_shim_rejects_typos() {
+        let network = create_test_network_inmemory();
+        let enqueue = |method: &str, priority: &str, headers: &str| {
+            network.enqueue_request(
+                method.into(),
+                "https://a.com".into(),
+                headers.into(),
+                None,
+                priority.into(),
+                false,
+                None,
+                None,
+            )
+        };
+
+        assert!(matches!(
+            enqueue("POST", "hihg", "{}"),
+            Err(NetworkError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            enqueue("FETCH", "high", "{}"),
+            Err(NetworkError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            enqueue("POST", "high", "[1]"),
+            Err(NetworkError::InvalidConfig(_))
+        ));
+        assert!(enqueue("head", "Critical", "").is_ok());
+        assert_eq!(network.get_queue_size().unwrap(), 1);
+    }
+
     #[test]
     fn test_cache_and_retrieve() {
         let network = create_test_network_inmemory();
@@ -483,7 +1337,7 @@ mod tests {
                 "https://api.test.com/users".to_string(),
                 200,
                 "{}".to_string(),
-                "{\"users\":[]}".to_string(),
+                b"{\"users\":[]}".to_vec(),
                 300,
                 None,
                 None,
@@ -494,6 +1348,13 @@ mod tests {
             .get_cached("GET".to_string(), "https://api.test.com/users".to_string())
             .unwrap();
         assert!(cached.is_some());
+
+        let entry = network
+            .get_cached_response(HttpMethod::GET, "https://api.test.com/users")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.status_code, 200);
+        assert_eq!(entry.body, b"{\"users\":[]}");
     }
 
     #[test]
@@ -505,6 +1366,277 @@ mod tests {
         assert!(estimate > 0);
     }
 
+    #[test]
+    fn test_measurements_blend_into_status() {
+        let network = create_test_network_inmemory();
+        network.update_status("4g", 0, 0, false);
+        assert_eq!(network.get_status().quality_score, 70);
+
+        // Ten transfers at 2G speeds pull the 4G default down
+        for _ in 0..10 {
+            network.record_transfer(10_000, 640); // 125 Kbps
+        }
+        network.record_rtt(900);
+        let status = network.get_status();
+        assert!((6_900..7_100).contains(&status.downlink_kbps)); // 75% of the way from 27500 to 125
+        assert_eq!(status.rtt_ms, 900);
+        assert!(status.quality_score < 30);
+
+        let stats: BandwidthSnapshot =
+            serde_json::from_str(&network.get_bandwidth_stats()).unwrap();
+        assert_eq!(stats.p50_kbps, Some(125));
+        assert_eq!(stats.samples, 10);
+
+        // Switching networks starts the measurements over
+        network.update_status("wifi", 0, 0, false);
+        assert_eq!(network.get_status().quality_score, 80);
+    }
+
+    #[test]
+    fn test_bandwidth_state_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = || NetworkConfig {
+            app_id: "test-app".to_string(),
+            db_dir: dir.path().to_string_lossy().to_string(),
+            max_cache_bytes: 10 * 1024 * 1024,
+            enable_queue: false,
+            enable_cache: false,
+            auto_compress: true,
+            cache_eviction_policy: EvictionPolicy::Lru,
+            max_cache_entry_bytes: None,
+            cache_host_quota_bytes: None,
+            compress_cache_bodies: true,
+        };
+
+        let network = RajeevNetwork::new(config()).unwrap();
+        network.record_transfer(100_000, 100);
+        network.cleanup().unwrap();
+
+        let relaunched = RajeevNetwork::new(config()).unwrap();
+        assert_eq!(relaunched.get_estimated_bandwidth_kbps(), 8000);
+    }
+
+    #[test]
+    fn test_enqueue_idempotency_key() {
+        let network = create_test_network_inmemory();
+
+        let enqueue = || {
+            network
+                .enqueue_request(
+                    "POST".to_string(),
+                    "https://pay.com/charge".to_string(),
+                    "{}".to_string(),
+                    Some(b"{\"amount\":100}".to_vec()),
+                    "critical".to_string(),
+                    false,
+                    None,
+                    Some("order-42".to_string()),
+                )
+                .unwrap()
+        };
+
+        let first = enqueue();
+        let second = enqueue();
+        assert_eq!(first, second);
+        assert_eq!(network.get_queue_size().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_enqueue_with_options_merges() {
+        let network = create_test_network_inmemory();
+        let options = r#"{"priority":"High","coalesce":"MergeJson"}"#.to_string();
+
+        for body in [r#"{"name":"A"}"#, r#"{"city":"Pune"}"#] {
+            network
+                .enqueue_request_with_options(
+                    "PATCH".to_string(),
+                    "https://api.com/me".to_string(),
+                    "{}".to_string(),
+                    Some(body.as_bytes().to_vec()),
+                    options.clone(),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(network.get_queue_size().unwrap(), 1);
+        let req: QueuedRequest =
+            serde_json::from_str(&network.dequeue_request().unwrap().unwrap()).unwrap();
+        assert_eq!(req.body_text(), Some(r#"{"city":"Pune","name":"A"}"#));
+        assert!(
+            network
+                .enqueue_request_with_options(
+                    "GET".into(),
+                    "https://a.com".into(),
+                    "{}".into(),
+                    None,
+                    "{".into()
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_set_scheduler_config() {
+        let network = create_test_network_inmemory();
+        network
+            .set_scheduler_config(
+                r#"{"aging_interval_seconds":600,"weights":{"Normal":3,"Low":1}}"#.to_string(),
+            )
+            .unwrap();
+        assert!(
+            network
+                .set_scheduler_config(r#"{"weights":{"Urgent":1}}"#.to_string())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_queue_limits() {
+        let network = create_test_network_inmemory();
+        network
+            .set_queue_limits(r#"{"max_requests":1}"#.to_string())
+            .unwrap();
+        let first = network
+            .enqueue_request(
+                "POST".into(),
+                "https://a.com/1".into(),
+                "{}".into(),
+                None,
+                "low".into(),
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+        let outcome: EnqueueOutcome = serde_json::from_str(
+            &network
+                .enqueue_request_with_options(
+                    "POST".into(),
+                    "https://a.com/2".into(),
+                    "{}".into(),
+                    None,
+                    "{}".into(),
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(outcome.shed, vec![first]);
+        assert!(
+            network
+                .set_queue_limits(r#"{"max_requests":-1}"#.to_string())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_power_constraint() {
+        let network = create_test_network_inmemory();
+        let options = r#"{"constraints":{"requires_charging":true}}"#.to_string();
+        network
+            .enqueue_request_with_options(
+                "PUT".into(),
+                "https://a.com/backup".into(),
+                "{}".into(),
+                None,
+                options,
+            )
+            .unwrap();
+
+        assert!(network.dequeue_request().unwrap().is_none());
+        network.update_power_state(false, Some(80));
+        assert!(network.dequeue_request().unwrap().is_none());
+        network.update_power_state(true, Some(80));
+        assert!(network.dequeue_request().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_dead_letter_roundtrip() {
+        let network = create_test_network_inmemory();
+        let id = network
+            .enqueue_request(
+                "POST".to_string(),
+                "https://a.com/form".to_string(),
+                "{}".to_string(),
+                None,
+                "low".to_string(),
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+
+        assert!(
+            !network
+                .fail_request_with(id.clone(), Some(500), None, None)
+                .unwrap()
+        );
+        let letters: Vec<DeadLetter> =
+            serde_json::from_str(&network.list_dead_letters(10).unwrap()).unwrap();
+        assert_eq!(letters[0].last_status_code, Some(500));
+
+        assert!(network.requeue_dead_letter(id.clone()).unwrap());
+        assert_eq!(network.get_queue_size().unwrap(), 1);
+        assert!(network.get_dead_letter(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_leased_request_roundtrip() {
+        let network = create_test_network_inmemory();
+        let id = network
+            .enqueue_request(
+                "POST".to_string(),
+                "https://a.com".to_string(),
+                "{}".to_string(),
+                None,
+                "high".to_string(),
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let req: QueuedRequest =
+            serde_json::from_str(&network.dequeue_request().unwrap().unwrap()).unwrap();
+        assert!(network.dequeue_request().unwrap().is_none());
+        assert!(
+            network
+                .complete_leased_request(id.clone(), "wrong".to_string())
+                .is_err()
+        );
+        network
+            .complete_leased_request(id, req.lease_token.unwrap())
+            .unwrap();
+        assert_eq!(network.get_queue_size().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_dequeue_batch() {
+        let network = create_test_network_inmemory();
+        network.update_status("wifi", 10000, 20, false);
+        for _ in 0..3 {
+            network
+                .enqueue_request(
+                    "POST".to_string(),
+                    "https://a.com/e".to_string(),
+                    "{}".to_string(),
+                    Some(vec![b'e'; 100]),
+                    "low".to_string(),
+                    false,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        let batch: Vec<QueuedRequest> =
+            serde_json::from_str(&network.dequeue_batch(2).unwrap()).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(batch.iter().all(|r| r.lease_token.is_some()));
+        let rest: Vec<QueuedRequest> =
+            serde_json::from_str(&network.dequeue_batch(10).unwrap()).unwrap();
+        assert_eq!(rest.len(), 1);
+    }
+
     #[test]
     fn test_complete_request() {
         let network = create_test_network_inmemory();
@@ -518,6 +1650,7 @@ mod tests {
                 "normal".to_string(),
                 false,
                 None,
+                None,
             )
             .unwrap();
 
@@ -530,13 +1663,265 @@ mod tests {
         let network = create_test_network_inmemory();
 
         network
-            .enqueue_request("GET".to_string(), "https://a.com".to_string(), "{}".to_string(), None, "normal".to_string(), false, Some("batch".to_string()))
+            .enqueue_request(
+                "GET".to_string(),
+                "https://a.com".to_string(),
+                "{}".to_string(),
+                None,
+                "normal".to_string(),
+                false,
+                Some("batch".to_string()),
+                None,
+            )
             .unwrap();
         network
-            .enqueue_request("GET".to_string(), "https://b.com".to_string(), "{}".to_string(), None, "normal".to_string(), false, Some("batch".to_string()))
+            .enqueue_request(
+                "GET".to_string(),
+                "https://b.com".to_string(),
+                "{}".to_string(),
+                None,
+                "normal".to_string(),
+                false,
+                Some("batch".to_string()),
+                None,
+            )
             .unwrap();
 
         let cancelled = network.cancel_by_tag("batch".to_string()).unwrap();
         assert_eq!(cancelled, 2);
     }
+
+    #[test]
+    fn test_enqueue_compresses_large_body() {
+        let network = create_test_network_inmemory();
+        let body = "{\"event\":\"view\"}".repeat(200).into_bytes();
+
+        network
+            .enqueue_request(
+                "POST".to_string(),
+                "https://a.com".to_string(),
+                "{}".to_string(),
+                Some(body.clone()),
+                "normal".to_string(),
+                true,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let queue = network.queue.as_ref().unwrap();
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert!(req.compress);
+        assert_eq!(
+            optimization::decompress(req.body.as_ref().unwrap()).unwrap(),
+            body
+        );
+    }
+
+    struct OkTransport;
+
+    impl Transport for OkTransport {
+        fn send(
+            &self,
+            _request: &QueuedRequest,
+            _timeout: std::time::Duration,
+        ) -> Result<TransportResponse, TransportError> {
+            Ok(TransportResponse {
+                status_code: 204,
+                headers_json: "{}".to_string(),
+                body: Vec::new(),
+                duration_ms: 20,
+            })
+        }
+    }
+
+    #[test]
+    fn test_drain_queue() {
+        let network = create_test_network_inmemory();
+
+        network
+            .enqueue_request(
+                "POST".to_string(),
+                "https://a.com".to_string(),
+                "{}".to_string(),
+                Some(b"{}".to_vec()),
+                "high".to_string(),
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let report = network.drain_queue(&OkTransport, 10).unwrap();
+        assert_eq!(report.succeeded, 1);
+        assert_eq!(network.get_queue_size().unwrap(), 0);
+    }
+
+    struct RedirectTransport;
+
+    impl Transport for RedirectTransport {
+        fn send(
+            &self,
+            _request: &QueuedRequest,
+            _timeout: std::time::Duration,
+        ) -> Result<TransportResponse, TransportError> {
+            Ok(TransportResponse {
+                status_code: 302,
+                headers_json: r#"{"Location":"http://login.hotel.example/"}"#.to_string(),
+                body: Vec::new(),
+                duration_ms: 5,
+            })
+        }
+    }
+
+    #[test]
+    fn test_captive_portal_goes_offline() {
+        let network = create_test_network_inmemory();
+        network.update_status("wifi", 0, 0, false);
+
+        let result: ProbeResult =
+            serde_json::from_str(&network.check_reachability(&RedirectTransport)).unwrap();
+        assert_eq!(result.reachability, Reachability::CaptivePortal);
+        let status = network.get_status();
+        assert!(!status.is_online);
+        assert_eq!(status.quality_score, 0);
+
+        // Still the same Wi-Fi: the portal verdict sticks
+        network.update_status("wifi", 0, 0, false);
+        assert!(!network.get_status().is_online);
+
+        // Signed in: a 204 brings it back
+        network
+            .set_reachability_probe(r#"{"url":"https://probe.example/204"}"#.to_string())
+            .unwrap();
+        network.check_reachability(&OkTransport);
+        let status = network.get_status();
+        assert!(status.is_online);
+        assert_eq!(status.reachability, Reachability::Reachable);
+        assert_eq!(status.rtt_ms, 20);
+
+        // A different network starts unprobed
+        network.update_status("4g", 0, 0, false);
+        assert_eq!(network.get_status().reachability, Reachability::Unknown);
+    }
+
+    #[test]
+    fn test_drain_on_reconnect() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let network = create_test_network_inmemory();
+        network
+            .set_connectivity_config(r#"{"debounce_ms":0}"#.to_string())
+            .unwrap();
+        let scheduled = Arc::new(AtomicUsize::new(0));
+        let counter = scheduled.clone();
+        network.set_drain_on_reconnect(Some(ReconnectDrain {
+            transport: Arc::new(OkTransport),
+            executor: Arc::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+            max_requests: 10,
+            max_upload_chunks: 4,
+        }));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        network.add_connectivity_listener(Arc::new(
+            move |event: &ConnectivityEvent, _: &NetworkStatus| {
+                sink.lock().unwrap().push(event.clone());
+            },
+        ));
+
+        network.update_status("offline", 0, 0, false);
+        network
+            .enqueue_request(
+                "POST".to_string(),
+                "https://a.com".to_string(),
+                "{}".to_string(),
+                None,
+                "high".to_string(),
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(network.get_queue_size().unwrap(), 1);
+
+        // Coming online only schedules the drain; nothing is sent inline
+        network.update_status("4g", 0, 0, false);
+        assert_eq!(scheduled.load(Ordering::SeqCst), 1);
+        assert_eq!(network.get_queue_size().unwrap(), 1);
+        let report = network.drain_on_reconnect().unwrap().unwrap();
+        assert_eq!(report.succeeded, 1);
+        assert_eq!(network.get_queue_size().unwrap(), 0);
+
+        network.set_drain_on_reconnect(None);
+        assert!(network.drain_on_reconnect().unwrap().is_none());
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], ConnectivityEvent::WentOffline);
+        assert!(events.contains(&ConnectivityEvent::WentOnline {
+            connection_type: ConnectionType::Cellular4G
+        }));
+        assert_eq!(network.poll_connectivity(), "[]");
+    }
+
+    #[test]
+    fn test_uploads() {
+        let network = create_test_network_inmemory();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        std::fs::write(&path, vec![7u8; 10_000]).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let id = network
+            .enqueue_upload(
+                path.clone(),
+                "https://up.test/files".into(),
+                r#"{"chunk_size":4096}"#.into(),
+            )
+            .unwrap();
+        let job = network.get_upload(&id).unwrap().unwrap();
+        assert_eq!(job.file_size, 10_000);
+        assert_eq!(job.chunk_size, 4096);
+        assert!(
+            network
+                .enqueue_upload(
+                    "/no/such/file".into(),
+                    "https://up.test".into(),
+                    "{}".into()
+                )
+                .is_err()
+        );
+        assert!(
+            network
+                .enqueue_upload(path, "https://up.test".into(), "not json".into())
+                .is_err()
+        );
+
+        // Nothing goes out while offline
+        network.update_status("offline", 0, 0, false);
+        let job = network.resume_upload(&id, &OkTransport, 10).unwrap();
+        assert_eq!(job.state, UploadState::Pending);
+
+        // A server that doesn't create the upload (no 201) fails it
+        network.update_status("wifi", 0, 0, false);
+        assert!(matches!(
+            network.resume_upload(&id, &OkTransport, 10),
+            Err(NetworkError::UploadError(_))
+        ));
+        assert_eq!(
+            network.get_upload(&id).unwrap().unwrap().state,
+            UploadState::Failed
+        );
+        assert!(network.resume_uploads(&OkTransport, 10).unwrap().is_empty());
+
+        assert!(network.retry_upload(&id).unwrap());
+        assert!(!network.retry_upload(&id).unwrap());
+        assert_eq!(
+            network.get_upload(&id).unwrap().unwrap().state,
+            UploadState::Pending
+        );
+
+        assert!(network.cancel_upload(&id).unwrap());
+        assert!(network.list_uploads().unwrap().is_empty());
+    }
 }
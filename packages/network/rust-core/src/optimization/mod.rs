@@ -48,17 +48,67 @@ pub fn decompress_string(input: &str) -> Result<String, CompressionError> {
         .map_err(|e| CompressionError::DecompressFailed(e.to_string()))
 }
 
+/// Serde adapter that writes byte bodies as base64 strings, so JSON handed to
+/// platform layers stays a string field instead of an array of numbers
+pub mod base64_body {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            bytes,
+        ))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encoded)
+            .map_err(serde::de::Error::custom)
+    }
+
+    /// Same as the parent module, for optional bodies
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            bytes: &Option<Vec<u8>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match bytes {
+                Some(b) => super::serialize(b, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<u8>>, D::Error> {
+            let encoded = Option::<String>::deserialize(deserializer)?;
+            encoded
+                .map(|e| {
+                    base64::Engine::decode(&base64::engine::general_purpose::STANDARD, e)
+                        .map_err(serde::de::Error::custom)
+                })
+                .transpose()
+        }
+    }
+}
+
 /// Check if compression would be beneficial (data > 1KB and compresses well)
 pub fn should_compress(data: &[u8]) -> bool {
+    compress_if_beneficial(data).is_some()
+}
+
+/// Compress data if `should_compress` would say yes, returning the
+/// compressed bytes so callers don't compress twice
+pub fn compress_if_beneficial(data: &[u8]) -> Option<Vec<u8>> {
     if data.len() < 1024 {
-        return false; // Too small to benefit
-    }
-    // Quick check: try compressing and see if it's at least 20% smaller
-    if let Ok(compressed) = compress(data) {
-        compressed.len() < (data.len() * 80 / 100)
-    } else {
-        false
+        return None; // Too small to benefit
     }
+    // Only worth it if it's at least 20% smaller
+    compress(data)
+        .ok()
+        .filter(|compressed| compressed.len() < (data.len() * 80 / 100))
 }
 
 /// Get compression ratio (0.0 to 1.0, lower = better compression)
@@ -110,6 +160,14 @@ mod tests {
         assert!(should_compress(large.as_bytes()));
     }
 
+    #[test]
+    fn test_compress_if_beneficial() {
+        assert!(compress_if_beneficial(b"tiny").is_none());
+        let data = "repeated json field ".repeat(200);
+        let compressed = compress_if_beneficial(data.as_bytes()).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), data.as_bytes());
+    }
+
     #[test]
     fn test_compression_ratio() {
         let original = b"test data for ratio calculation";
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Priority, QueuedRequest};
+use crate::connectivity::{ConnectionType, NetworkStatus, PowerState};
+
+/// Conditions a request waits for beyond its priority's quality threshold.
+/// Anything the queue can't confirm (e.g. an unknown connection type or
+/// power state) counts as not met.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestConstraints {
+    /// Hold while the connection is metered (e.g. wait for Wi-Fi)
+    pub unmetered: bool,
+    /// Hold while the user has data saver on
+    pub respect_save_data: bool,
+    /// Slowest connection type to send on, compared by quality score
+    pub min_connection: Option<ConnectionType>,
+    /// Hold until the device is charging
+    pub requires_charging: bool,
+    /// Hold while the battery is below this level and not charging
+    pub min_battery_percent: Option<u8>,
+}
+
+impl RequestConstraints {
+    /// Whether every constraint holds under `conditions`
+    pub fn allows(&self, conditions: &SendConditions) -> bool {
+        if self.unmetered && conditions.is_metered {
+            return false;
+        }
+        if self.respect_save_data && conditions.save_data {
+            return false;
+        }
+        if let Some(min) = self.min_connection {
+            let known = !matches!(
+                conditions.connection_type,
+                ConnectionType::Offline | ConnectionType::Unknown
+            );
+            if !known || conditions.connection_type.quality_score() < min.quality_score() {
+                return false;
+            }
+        }
+        if self.requires_charging && !conditions.power.is_some_and(|p| p.is_charging) {
+            return false;
+        }
+        if let Some(min) = self.min_battery_percent {
+            let ok = conditions
+                .power
+                .is_some_and(|p| p.is_charging || p.battery_percent.is_some_and(|b| b >= min));
+            if !ok {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The network and device state a dequeue is judged against
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendConditions {
+    pub quality_score: u8,
+    pub connection_type: ConnectionType,
+    pub is_metered: bool,
+    pub save_data: bool,
+    /// None if the platform hasn't reported it
+    pub power: Option<PowerState>,
+}
+
+impl SendConditions {
+    pub fn new(status: &NetworkStatus, power: Option<PowerState>) -> Self {
+        SendConditions {
+            quality_score: status.quality_score,
+            connection_type: status.connection_type,
+            is_metered: status.is_metered,
+            save_data: status.save_data,
+            power,
+        }
+    }
+
+    /// Only the quality score is known, so constrained requests are held
+    pub fn with_quality(quality_score: u8) -> Self {
+        SendConditions {
+            quality_score,
+            connection_type: ConnectionType::Unknown,
+            is_metered: true,
+            save_data: false,
+            power: None,
+        }
+    }
+
+    /// Whether `request` may be sent now: its priority's quality threshold
+    /// is met and so are its constraints
+    pub fn allows(&self, request: &QueuedRequest) -> bool {
+        self.admits(
+            Priority::from_i32(request.priority),
+            request.constraints.as_ref(),
+        )
+    }
+
+    pub(super) fn admits(
+        &self,
+        priority: Priority,
+        constraints: Option<&RequestConstraints>,
+    ) -> bool {
+        self.quality_score >= priority.min_quality_score()
+            && constraints.is_none_or(|c| c.allows(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions(conn_type: ConnectionType, power: Option<PowerState>) -> SendConditions {
+        SendConditions::new(&NetworkStatus::from_connection_type(conn_type), power)
+    }
+
+    #[test]
+    fn test_unmetered_and_connection_type() {
+        let wifi_only = RequestConstraints {
+            unmetered: true,
+            ..RequestConstraints::default()
+        };
+        assert!(wifi_only.allows(&conditions(ConnectionType::WiFi, None)));
+        assert!(!wifi_only.allows(&conditions(ConnectionType::Cellular5G, None)));
+        assert!(!wifi_only.allows(&SendConditions::with_quality(100)));
+
+        let fast = RequestConstraints {
+            min_connection: Some(ConnectionType::Cellular4G),
+            ..RequestConstraints::default()
+        };
+        assert!(fast.allows(&conditions(ConnectionType::WiFi, None)));
+        assert!(!fast.allows(&conditions(ConnectionType::Cellular3G, None)));
+        assert!(!fast.allows(&conditions(ConnectionType::Unknown, None)));
+    }
+
+    #[test]
+    fn test_power_constraints() {
+        let charging = RequestConstraints {
+            requires_charging: true,
+            ..RequestConstraints::default()
+        };
+        let plugged = PowerState {
+            is_charging: true,
+            battery_percent: Some(10),
+        };
+        let battery = PowerState {
+            is_charging: false,
+            battery_percent: Some(40),
+        };
+        assert!(charging.allows(&conditions(ConnectionType::WiFi, Some(plugged))));
+        assert!(!charging.allows(&conditions(ConnectionType::WiFi, Some(battery))));
+        assert!(!charging.allows(&conditions(ConnectionType::WiFi, None)));
+
+        let min_battery = RequestConstraints {
+            min_battery_percent: Some(50),
+            ..RequestConstraints::default()
+        };
+        assert!(!min_battery.allows(&conditions(ConnectionType::WiFi, Some(battery))));
+        assert!(min_battery.allows(&conditions(ConnectionType::WiFi, Some(plugged))));
+    }
+}
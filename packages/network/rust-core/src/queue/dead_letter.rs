@@ -0,0 +1,628 @@
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, ToSql, params};
+use serde::{Deserialize, Serialize};
+
+use super::{QueueError, QueuedRequest, REQUEST_COLUMNS, RequestQueue};
+use crate::schema::add_column_if_missing;
+
+/// Why a request ended up in the dead-letter table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeadLetterReason {
+    /// Failed on every attempt its priority allows
+    RetriesExhausted,
+    /// Still queued when `cleanup_old` ran
+    Expired,
+    /// The server rejected it in a way retrying can't fix (most 4xx)
+    PermanentFailure,
+    /// The request it depended on was dead-lettered
+    DependencyFailed,
+    /// Its `deadline_at` passed before it could be sent
+    DeadlineExceeded,
+    /// Dropped to keep the queue within its `QueueLimits`
+    Shed,
+}
+
+impl DeadLetterReason {
+    pub(super) fn as_str(&self) -> &'static str {
+        match self {
+            DeadLetterReason::RetriesExhausted => "retries_exhausted",
+            DeadLetterReason::Expired => "expired",
+            DeadLetterReason::PermanentFailure => "permanent_failure",
+            DeadLetterReason::DependencyFailed => "dependency_failed",
+            DeadLetterReason::DeadlineExceeded => "deadline_exceeded",
+            DeadLetterReason::Shed => "shed",
+        }
+    }
+
+    fn from_db(s: &str) -> Self {
+        match s {
+            "expired" => DeadLetterReason::Expired,
+            "permanent_failure" => DeadLetterReason::PermanentFailure,
+            "dependency_failed" => DeadLetterReason::DependencyFailed,
+            "deadline_exceeded" => DeadLetterReason::DeadlineExceeded,
+            "shed" => DeadLetterReason::Shed,
+            _ => DeadLetterReason::RetriesExhausted,
+        }
+    }
+}
+
+/// What went wrong on a single send attempt
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestFailure {
+    /// HTTP status, if the server answered
+    pub status_code: Option<u16>,
+    /// Transport or application error message
+    pub error: Option<String>,
+    /// The response's `Retry-After` header, honored for 429 and 503
+    pub retry_after: Option<String>,
+}
+
+/// One failed attempt in a request's history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttemptRecord {
+    pub at: String,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// A request that will not be retried, kept so it can be inspected or requeued
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The request as it was last queued
+    pub request: QueuedRequest,
+    pub reason: DeadLetterReason,
+    /// When it was moved out of the queue
+    pub dead_at: String,
+    /// Status code of the last attempt, if any
+    pub last_status_code: Option<u16>,
+    /// Error of the last attempt, if any
+    pub last_error: Option<String>,
+    /// Every failed attempt, oldest first
+    pub attempt_history: Vec<AttemptRecord>,
+}
+
+pub(super) fn initialize(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS dead_letters (
+            id TEXT PRIMARY KEY,
+            method TEXT NOT NULL,
+            url TEXT NOT NULL,
+            headers_json TEXT NOT NULL DEFAULT '{}',
+            body BLOB,
+            priority INTEGER NOT NULL,
+            retry_count INTEGER NOT NULL,
+            max_retries INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            next_attempt_at TEXT NOT NULL,
+            compress INTEGER NOT NULL DEFAULT 0,
+            tag TEXT,
+            idempotency_key TEXT,
+            coalesce_key TEXT,
+            reason TEXT NOT NULL,
+            dead_at TEXT NOT NULL,
+            last_status_code INTEGER,
+            last_error TEXT,
+            attempt_history TEXT NOT NULL DEFAULT '[]',
+            retry_policy TEXT,
+            lease_token TEXT,
+            lease_expires_at TEXT,
+            ordering_group TEXT,
+            depends_on TEXT,
+            deadline_at TEXT,
+            constraints TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_dead_letters_dead_at ON dead_letters(dead_at);
+        ",
+    )?;
+    add_column_if_missing(conn, "dead_letters", "retry_policy", "TEXT")?;
+    // Always NULL; present so dead letters can be read with `REQUEST_COLUMNS`
+    add_column_if_missing(conn, "dead_letters", "lease_token", "TEXT")?;
+    add_column_if_missing(conn, "dead_letters", "lease_expires_at", "TEXT")?;
+    add_column_if_missing(conn, "dead_letters", "ordering_group", "TEXT")?;
+    add_column_if_missing(conn, "dead_letters", "depends_on", "TEXT")?;
+    add_column_if_missing(conn, "dead_letters", "deadline_at", "TEXT")?;
+    add_column_if_missing(conn, "dead_letters", "constraints", "TEXT")?;
+    Ok(())
+}
+
+/// Append a failed attempt to a queued request's history
+pub(super) fn record_attempt(
+    conn: &Connection,
+    request_id: &str,
+    failure: &RequestFailure,
+) -> Result<(), QueueError> {
+    let history: String = conn.query_row(
+        "SELECT attempt_history FROM request_queue WHERE id = ?1",
+        params![request_id],
+        |row| row.get(0),
+    )?;
+    let mut history: Vec<AttemptRecord> = serde_json::from_str(&history).unwrap_or_default();
+    history.push(AttemptRecord {
+        at: Utc::now().to_rfc3339(),
+        status_code: failure.status_code,
+        error: failure.error.clone(),
+    });
+    let history = serde_json::to_string(&history)
+        .map_err(|e| QueueError::SerializationError(e.to_string()))?;
+    conn.execute(
+        "UPDATE request_queue SET attempt_history = ?1 WHERE id = ?2",
+        params![history, request_id],
+    )?;
+    Ok(())
+}
+
+/// Move the queued requests matching `condition` into the dead-letter table.
+/// `condition` may use `?1`, `?2`… for `args`; `last_status_code` and
+/// `last_error` come from `failure`. Requests depending on a moved request
+/// follow it, transitively, as `DependencyFailed`. Returns the number of
+/// rows moved, not counting dependents.
+pub(super) fn bury(
+    conn: &Connection,
+    condition: &str,
+    args: &[&dyn ToSql],
+    reason: DeadLetterReason,
+    failure: &RequestFailure,
+) -> Result<u64, QueueError> {
+    let n = args.len();
+    let mut all_args: Vec<&dyn ToSql> = args.to_vec();
+    let reason = reason.as_str();
+    let now = Utc::now().to_rfc3339();
+    all_args.push(&reason);
+    all_args.push(&now);
+    all_args.push(&failure.status_code);
+    all_args.push(&failure.error);
+
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO dead_letters
+             (id, method, url, headers_json, body, priority, retry_count, max_retries, created_at,
+              next_attempt_at, compress, tag, idempotency_key, coalesce_key, attempt_history, retry_policy,
+              ordering_group, depends_on, deadline_at, constraints, reason, dead_at, last_status_code, last_error)
+             SELECT id, method, url, headers_json, body, priority, retry_count, max_retries, created_at,
+                    next_attempt_at, compress, tag, idempotency_key, coalesce_key, attempt_history, retry_policy,
+                    ordering_group, depends_on, deadline_at, constraints, ?{}, ?{}, ?{}, ?{}
+             FROM request_queue WHERE {}",
+            n + 1,
+            n + 2,
+            n + 3,
+            n + 4,
+            condition
+        ),
+        all_args.as_slice(),
+    )?;
+    let rows = conn.execute(
+        &format!("DELETE FROM request_queue WHERE {}", condition),
+        args,
+    )?;
+
+    if rows > 0 {
+        // Collected up front: each bury adds to `dead_letters`
+        let mut stmt = conn.prepare(
+            "SELECT id FROM request_queue WHERE depends_on IN (SELECT id FROM dead_letters)",
+        )?;
+        let dependents = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let failure = RequestFailure {
+            error: Some("A request this one depends on was dead-lettered".to_string()),
+            ..RequestFailure::default()
+        };
+        for id in dependents {
+            bury(
+                conn,
+                "id = ?1",
+                &[&id],
+                DeadLetterReason::DependencyFailed,
+                &failure,
+            )?;
+        }
+    }
+    Ok(rows as u64)
+}
+
+/// Dead-letter the requests waiting on `parent`, which left the queue
+/// without completing
+pub(super) fn bury_dependents(
+    conn: &Connection,
+    parent: &str,
+    error: &str,
+) -> Result<u64, QueueError> {
+    let failure = RequestFailure {
+        error: Some(error.to_string()),
+        ..RequestFailure::default()
+    };
+    bury(
+        conn,
+        "depends_on = ?1",
+        &[&parent],
+        DeadLetterReason::DependencyFailed,
+        &failure,
+    )
+}
+
+/// Columns read by `row_to_dead_letter`: the request columns, then the
+/// dead-letter ones
+fn dead_letter_columns() -> String {
+    format!(
+        "{}, reason, dead_at, last_status_code, last_error, attempt_history",
+        REQUEST_COLUMNS
+    )
+}
+
+fn row_to_dead_letter(row: &rusqlite::Row) -> rusqlite::Result<DeadLetter> {
+    let request = super::row_to_request(row)?;
+    let offset = REQUEST_COLUMNS.split(',').count();
+    let reason: String = row.get(offset)?;
+    let history: String = row.get(offset + 4)?;
+    Ok(DeadLetter {
+        request,
+        reason: DeadLetterReason::from_db(&reason),
+        dead_at: row.get(offset + 1)?,
+        last_status_code: row.get(offset + 2)?,
+        last_error: row.get(offset + 3)?,
+        attempt_history: serde_json::from_str(&history).unwrap_or_default(),
+    })
+}
+
+impl RequestQueue {
+    /// Dead letters, most recent first
+    pub fn list_dead_letters(&self, limit: u32) -> Result<Vec<DeadLetter>, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM dead_letters ORDER BY dead_at DESC LIMIT ?1",
+            dead_letter_columns()
+        ))?;
+        let letters = stmt
+            .query_map(params![limit], row_to_dead_letter)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(letters)
+    }
+
+    /// A single dead letter by its original request ID
+    pub fn get_dead_letter(&self, request_id: &str) -> Result<Option<DeadLetter>, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let letter = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM dead_letters WHERE id = ?1",
+                    dead_letter_columns()
+                ),
+                params![request_id],
+                row_to_dead_letter,
+            )
+            .optional()?;
+        Ok(letter)
+    }
+
+    /// Number of dead letters
+    pub fn dead_letter_count(&self) -> Result<u64, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let count = conn.query_row("SELECT COUNT(*) FROM dead_letters", [], |row| {
+            row.get::<_, i64>(0).map(|v| v as u64)
+        })?;
+        Ok(count)
+    }
+
+    /// Put a dead letter back in the queue under its original ID with a
+    /// fresh retry budget and empty attempt history; its deadline still
+    /// applies. Returns false (and keeps the dead letter) if a request with
+    /// the same idempotency key is already queued.
+    ///
+    /// Fails with `DependencyFailed` if the request it depends on is neither
+    /// queued nor completed, e.g. still dead-lettered; requeue that one first.
+    pub fn requeue_dead_letter(&self, request_id: &str) -> Result<bool, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let found: Option<(Option<String>, Option<String>)> = conn
+            .query_row(
+                "SELECT idempotency_key, depends_on FROM dead_letters WHERE id = ?1",
+                params![request_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let (key, depends_on) = match found {
+            Some(f) => f,
+            None => return Err(QueueError::NotFound(request_id.to_string())),
+        };
+        if let Some(ref parent) = depends_on
+            && !Self::can_depend_on(&conn, parent)?
+        {
+            return Err(QueueError::DependencyFailed(parent.clone()));
+        }
+        if let Some(ref key) = key {
+            let queued: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM request_queue WHERE idempotency_key = ?1",
+                params![key],
+                |row| row.get(0),
+            )?;
+            if queued > 0 {
+                return Ok(false);
+            }
+        }
+
+        let now = Utc::now().to_rfc3339();
+        conn.execute(
+            "INSERT OR REPLACE INTO request_queue
+             (id, method, url, headers_json, body, priority, retry_count, max_retries, created_at,
+              next_attempt_at, compress, tag, idempotency_key, coalesce_key, attempt_history, retry_policy,
+              ordering_group, depends_on, deadline_at, constraints)
+             SELECT id, method, url, headers_json, body, priority, 0, max_retries, created_at,
+                    ?2, compress, tag, idempotency_key, coalesce_key, '[]', retry_policy,
+                    ordering_group, depends_on, deadline_at, constraints
+             FROM dead_letters WHERE id = ?1",
+            params![request_id, now],
+        )?;
+        conn.execute(
+            "DELETE FROM dead_letters WHERE id = ?1",
+            params![request_id],
+        )?;
+        Ok(true)
+    }
+
+    /// Delete dead letters older than `older_than_hours` (all if None)
+    pub fn purge_dead_letters(&self, older_than_hours: Option<u32>) -> Result<u64, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let rows = match older_than_hours {
+            Some(h) => {
+                let cutoff = Utc::now() - chrono::Duration::hours(h as i64);
+                conn.execute(
+                    "DELETE FROM dead_letters WHERE dead_at < ?1",
+                    params![cutoff.to_rfc3339()],
+                )?
+            }
+            None => conn.execute("DELETE FROM dead_letters", [])?,
+        };
+        Ok(rows as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{EnqueueOptions, Priority};
+    use super::*;
+
+    #[test]
+    fn test_exhausted_request_becomes_dead_letter() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let id = queue
+            .enqueue(
+                "POST",
+                "https://a.com/form",
+                "{}",
+                Some(b"{\"x\":1}".as_slice()),
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let failure = RequestFailure {
+            status_code: Some(503),
+            error: Some("Service Unavailable".to_string()),
+            retry_after: None,
+        };
+        assert!(!queue.fail_with(&id, &failure).unwrap());
+        assert_eq!(queue.size().unwrap(), 0);
+
+        let letter = queue.get_dead_letter(&id).unwrap().unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::RetriesExhausted);
+        assert_eq!(letter.last_status_code, Some(503));
+        assert_eq!(letter.attempt_history.len(), 1);
+        assert_eq!(letter.request.body_text(), Some("{\"x\":1}"));
+    }
+
+    #[test]
+    fn test_attempt_history_accumulates() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let id = queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        for code in [500, 502, 504] {
+            queue
+                .fail_with(
+                    &id,
+                    &RequestFailure {
+                        status_code: Some(code),
+                        ..RequestFailure::default()
+                    },
+                )
+                .unwrap();
+        }
+
+        let letter = queue.get_dead_letter(&id).unwrap().unwrap();
+        let codes: Vec<_> = letter
+            .attempt_history
+            .iter()
+            .map(|a| a.status_code)
+            .collect();
+        assert_eq!(codes, vec![Some(500), Some(502), Some(504)]);
+    }
+
+    #[test]
+    fn test_requeue_and_purge() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let id = queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        queue.fail(&id).unwrap();
+        assert_eq!(queue.dead_letter_count().unwrap(), 1);
+
+        assert!(queue.requeue_dead_letter(&id).unwrap());
+        assert_eq!(queue.dead_letter_count().unwrap(), 0);
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(req.id, id);
+        assert_eq!(req.retry_count, 0);
+
+        queue.fail(&id).unwrap();
+        assert_eq!(queue.purge_dead_letters(Some(1)).unwrap(), 0);
+        assert_eq!(queue.purge_dead_letters(None).unwrap(), 1);
+        assert!(matches!(
+            queue.requeue_dead_letter(&id),
+            Err(QueueError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_dependents_follow_parent() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let parent = queue
+            .enqueue(
+                "POST",
+                "https://a.com/orders",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        let child_options = EnqueueOptions {
+            depends_on: Some(parent.clone()),
+            ..EnqueueOptions::default()
+        };
+        let child = queue
+            .enqueue_with_options("POST", "https://a.com/photos", "{}", None, &child_options)
+            .unwrap()
+            .id;
+        let grandchild_options = EnqueueOptions {
+            depends_on: Some(child.clone()),
+            ..EnqueueOptions::default()
+        };
+        let grandchild = queue
+            .enqueue_with_options(
+                "POST",
+                "https://a.com/notify",
+                "{}",
+                None,
+                &grandchild_options,
+            )
+            .unwrap()
+            .id;
+
+        let failure = RequestFailure {
+            status_code: Some(400),
+            ..RequestFailure::default()
+        };
+        queue.fail_with(&parent, &failure).unwrap();
+        assert_eq!(queue.size().unwrap(), 0);
+        for id in [&child, &grandchild] {
+            let letter = queue.get_dead_letter(id).unwrap().unwrap();
+            assert_eq!(letter.reason, DeadLetterReason::DependencyFailed);
+        }
+
+        // Can't hang new work off a dead request
+        let late = EnqueueOptions {
+            depends_on: Some(parent.clone()),
+            ..EnqueueOptions::default()
+        };
+        let result = queue.enqueue_with_options("POST", "https://a.com/late", "{}", None, &late);
+        assert!(matches!(result, Err(QueueError::DependencyFailed(_))));
+
+        // ...or bring a dependent back before its parent
+        assert!(matches!(
+            queue.requeue_dead_letter(&child),
+            Err(QueueError::DependencyFailed(_))
+        ));
+        assert!(queue.requeue_dead_letter(&parent).unwrap());
+        assert!(queue.requeue_dead_letter(&child).unwrap());
+        assert_eq!(queue.dequeue(100).unwrap().unwrap().id, parent);
+    }
+
+    #[test]
+    fn test_requeue_keeps_deadline() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let options = EnqueueOptions {
+            deadline_at: Some("2999-01-01T00:00:00+00:00".to_string()),
+            ..EnqueueOptions::default()
+        };
+        let id = queue
+            .enqueue_with_options("POST", "https://a.com", "{}", None, &options)
+            .unwrap()
+            .id;
+        queue
+            .fail_with(
+                &id,
+                &RequestFailure {
+                    status_code: Some(400),
+                    ..RequestFailure::default()
+                },
+            )
+            .unwrap();
+
+        assert!(queue.requeue_dead_letter(&id).unwrap());
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(
+            req.deadline_at.as_deref(),
+            Some("2999-01-01T00:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn test_cleanup_old_moves_to_dead_letters() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "POST",
+                "https://pay.com",
+                "{}",
+                None,
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+
+        // Everything is "older" than a cutoff of 0 hours from now
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(queue.cleanup_old(0).unwrap(), 1);
+        assert_eq!(queue.size().unwrap(), 1);
+
+        let letters = queue.list_dead_letters(10).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].reason, DeadLetterReason::Expired);
+    }
+}
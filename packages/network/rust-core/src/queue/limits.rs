@@ -0,0 +1,296 @@
+use chrono::Utc;
+use rusqlite::{Connection, ToSql, params};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::dead_letter::{self, DeadLetterReason, RequestFailure};
+use super::{Priority, QueueError};
+
+/// Bytes a queued row takes on the wire, matching `QueuedRequest::wire_size`
+const ROW_BYTES: &str = "LENGTH(url) + LENGTH(headers_json) + COALESCE(LENGTH(body), 0)";
+
+/// A cap on request count and/or total bytes (None = unbounded)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CapacityLimit {
+    pub max_requests: Option<u64>,
+    /// Sum of URL, header and body bytes
+    pub max_bytes: Option<u64>,
+}
+
+/// Queue capacity, overall and per tag. When an enqueue goes over, the
+/// oldest Low requests are dropped first, then Normal, then High, into the
+/// dead-letter table. Critical requests and requests in flight under a live
+/// lease are never dropped, and neither is anything they depend on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueLimits {
+    #[serde(flatten)]
+    pub overall: CapacityLimit,
+    /// Limits for requests carrying a given tag
+    pub per_tag: HashMap<String, CapacityLimit>,
+}
+
+/// Count and bytes of the queued requests matching `scope`
+fn usage(conn: &Connection, scope: &str, args: &[&dyn ToSql]) -> Result<(u64, u64), QueueError> {
+    let usage = conn.query_row(
+        &format!(
+            "SELECT COUNT(*), COALESCE(SUM({}), 0) FROM request_queue WHERE {}",
+            ROW_BYTES, scope
+        ),
+        args,
+        |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+    )?;
+    Ok(usage)
+}
+
+/// `victim` and every request depending on it, transitively, unless one of
+/// them is Critical or in flight under a live lease (then None)
+fn sheddable(
+    conn: &Connection,
+    victim: &str,
+    now: &str,
+) -> Result<Option<Vec<String>>, QueueError> {
+    let mut stmt = conn.prepare(
+        "WITH RECURSIVE doomed(id) AS (
+             SELECT ?1
+             UNION SELECT r.id FROM request_queue r JOIN doomed d ON r.depends_on = d.id
+         )
+         SELECT q.id, q.priority >= ?2 OR COALESCE(q.lease_expires_at > ?3, 0)
+         FROM request_queue q JOIN doomed d ON q.id = d.id",
+    )?;
+    let doomed = stmt
+        .query_map(params![victim, Priority::Critical as i32, now], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    if doomed.iter().any(|(_, protected)| *protected) {
+        return Ok(None);
+    }
+    Ok(Some(doomed.into_iter().map(|(id, _)| id).collect()))
+}
+
+/// Dead-letter requests matching `scope` until it fits `limit`, returning
+/// the IDs dropped. Requests depending on a dropped request follow it; a
+/// request can't be dropped if that would take a protected one with it.
+fn shed_scope(
+    conn: &Connection,
+    limit: &CapacityLimit,
+    scope: &str,
+    args: &[&dyn ToSql],
+) -> Result<Vec<String>, QueueError> {
+    let mut shed = Vec::new();
+    if limit.max_requests.is_none() && limit.max_bytes.is_none() {
+        return Ok(shed);
+    }
+
+    let now = Utc::now().to_rfc3339();
+    let n = args.len();
+    let mut victim_args: Vec<&dyn ToSql> = args.to_vec();
+    let critical = Priority::Critical as i32;
+    victim_args.push(&critical);
+    victim_args.push(&now);
+    let victim_sql = format!(
+        "SELECT id FROM request_queue
+         WHERE {} AND priority < ?{} AND (lease_expires_at IS NULL OR lease_expires_at <= ?{})
+         ORDER BY priority ASC, created_at ASC",
+        scope,
+        n + 1,
+        n + 2
+    );
+    let failure = RequestFailure {
+        error: Some("Dropped to stay within the queue limits".to_string()),
+        ..RequestFailure::default()
+    };
+    // Victims a protected request depends on stay that way while shedding
+    let mut protected = HashSet::new();
+
+    loop {
+        let (count, bytes) = usage(conn, scope, args)?;
+        let over = limit.max_requests.is_some_and(|m| count > m)
+            || limit.max_bytes.is_some_and(|m| bytes > m);
+        if !over {
+            return Ok(shed);
+        }
+
+        let mut doomed = None;
+        let mut stmt = conn.prepare(&victim_sql)?;
+        let mut rows = stmt.query(victim_args.as_slice())?;
+        while let Some(row) = rows.next()? {
+            let victim: String = row.get(0)?;
+            if protected.contains(&victim) {
+                continue;
+            }
+            match sheddable(conn, &victim, &now)? {
+                Some(ids) => {
+                    doomed = Some((victim, ids));
+                    break;
+                }
+                None => {
+                    protected.insert(victim);
+                }
+            }
+        }
+        let (victim, ids) = match doomed {
+            Some(d) => d,
+            None => return Ok(shed), // Only protected requests left
+        };
+        dead_letter::bury(
+            conn,
+            "id = ?1",
+            &[&victim],
+            DeadLetterReason::Shed,
+            &failure,
+        )?;
+        shed.extend(ids);
+    }
+}
+
+/// Bring the queue back within `limits` after a request tagged `tag` was
+/// added, tag limit first
+pub(super) fn enforce(
+    conn: &Connection,
+    limits: &QueueLimits,
+    tag: Option<&str>,
+) -> Result<Vec<String>, QueueError> {
+    let mut shed = Vec::new();
+    if let Some(tag) = tag
+        && let Some(limit) = limits.per_tag.get(tag)
+    {
+        shed.extend(shed_scope(conn, limit, "tag = ?1", &[&tag])?);
+    }
+    shed.extend(shed_scope(conn, &limits.overall, "1 = 1", &[])?);
+    Ok(shed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{EnqueueOptions, RequestQueue};
+    use super::*;
+
+    #[test]
+    fn test_limits_json() {
+        let limits: QueueLimits = serde_json::from_str(
+            r#"{"max_requests":100,"per_tag":{"analytics":{"max_bytes":1024}}}"#,
+        )
+        .unwrap();
+        assert_eq!(limits.overall.max_requests, Some(100));
+        assert_eq!(limits.overall.max_bytes, None);
+        assert_eq!(limits.per_tag["analytics"].max_bytes, Some(1024));
+    }
+
+    #[test]
+    fn test_shed_order_and_dependents() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let low = queue
+            .enqueue(
+                "POST",
+                "https://a.com/t",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        let options = EnqueueOptions {
+            priority: Priority::High,
+            depends_on: Some(low.clone()),
+            ..EnqueueOptions::default()
+        };
+        let child = queue
+            .enqueue_with_options("POST", "https://a.com/t2", "{}", None, &options)
+            .unwrap()
+            .id;
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com/n",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let conn = queue.conn.lock().unwrap();
+        let limits = QueueLimits {
+            overall: CapacityLimit {
+                max_requests: Some(2),
+                max_bytes: None,
+            },
+            ..QueueLimits::default()
+        };
+        let shed = enforce(&conn, &limits, None).unwrap();
+        assert_eq!(shed, vec![low.clone(), child.clone()]);
+        assert_eq!(usage(&conn, "1 = 1", &[]).unwrap().0, 1);
+        drop(conn);
+
+        // Shed requests can be recovered
+        assert_eq!(
+            queue.get_dead_letter(&low).unwrap().unwrap().reason,
+            DeadLetterReason::Shed
+        );
+        assert_eq!(
+            queue.get_dead_letter(&child).unwrap().unwrap().reason,
+            DeadLetterReason::DependencyFailed
+        );
+    }
+
+    #[test]
+    fn test_shed_skips_victims_with_protected_dependents() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let parent = queue
+            .enqueue(
+                "POST",
+                "https://a.com/orders",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        let options = EnqueueOptions {
+            priority: Priority::Critical,
+            depends_on: Some(parent.clone()),
+            ..EnqueueOptions::default()
+        };
+        queue
+            .enqueue_with_options("POST", "https://a.com/pay", "{}", None, &options)
+            .unwrap();
+        let normal = queue
+            .enqueue(
+                "GET",
+                "https://a.com/n",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let conn = queue.conn.lock().unwrap();
+        let limits = QueueLimits {
+            overall: CapacityLimit {
+                max_requests: Some(2),
+                max_bytes: None,
+            },
+            ..QueueLimits::default()
+        };
+        // The Low parent would take the Critical payment down with it
+        assert_eq!(enforce(&conn, &limits, None).unwrap(), vec![normal]);
+
+        let limits = QueueLimits {
+            overall: CapacityLimit {
+                max_requests: Some(1),
+                max_bytes: None,
+            },
+            ..QueueLimits::default()
+        };
+        assert!(enforce(&conn, &limits, None).unwrap().is_empty());
+        assert_eq!(usage(&conn, "1 = 1", &[]).unwrap().0, 2);
+    }
+}
@@ -1,11 +1,30 @@
 use chrono::Utc;
-use rusqlite::{params, Connection};
+use rusqlite::{Connection, OptionalExtension, params};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Mutex;
+use std::time::Duration;
 use uuid::Uuid;
 
+use crate::optimization;
+use crate::schema::{add_column_if_missing, read_body};
+
+mod constraints;
+mod dead_letter;
+mod limits;
+mod retry;
+mod scheduler;
+
+pub use crate::request::HttpMethod;
+pub use constraints::{RequestConstraints, SendConditions};
+pub use dead_letter::{AttemptRecord, DeadLetter, DeadLetterReason, RequestFailure};
+pub use limits::{CapacityLimit, QueueLimits};
+pub use retry::{BackoffStrategy, RetryPolicy, is_permanent_status, parse_retry_after};
+pub use scheduler::SchedulerConfig;
+use scheduler::{Candidate, Scheduler};
+
 /// Request priority levels
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Priority {
     /// Send only on good connections, can be dropped (analytics, telemetry)
     Low = 0,
@@ -49,39 +68,6 @@ impl Priority {
     }
 }
 
-/// HTTP method
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum HttpMethod {
-    GET,
-    POST,
-    PUT,
-    PATCH,
-    DELETE,
-}
-
-impl HttpMethod {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            HttpMethod::GET => "GET",
-            HttpMethod::POST => "POST",
-            HttpMethod::PUT => "PUT",
-            HttpMethod::PATCH => "PATCH",
-            HttpMethod::DELETE => "DELETE",
-        }
-    }
-
-    pub fn from_str(s: &str) -> Self {
-        match s.to_uppercase().as_str() {
-            "GET" => HttpMethod::GET,
-            "POST" => HttpMethod::POST,
-            "PUT" => HttpMethod::PUT,
-            "PATCH" => HttpMethod::PATCH,
-            "DELETE" => HttpMethod::DELETE,
-            _ => HttpMethod::GET,
-        }
-    }
-}
-
 /// A queued network request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueuedRequest {
@@ -93,8 +79,9 @@ pub struct QueuedRequest {
     pub url: String,
     /// Request headers as JSON string
     pub headers_json: String,
-    /// Request body (if any)
-    pub body: Option<String>,
+    /// Request body (if any; base64 in JSON)
+    #[serde(with = "crate::optimization::base64_body::option")]
+    pub body: Option<Vec<u8>>,
     /// Priority level
     pub priority: i32,
     /// Number of retry attempts so far
@@ -105,10 +92,244 @@ pub struct QueuedRequest {
     pub created_at: String,
     /// When to next attempt (for backoff)
     pub next_attempt_at: String,
-    /// Whether to compress the request body
+    /// Whether the body is gzip-compressed (send with `Content-Encoding: gzip`)
     pub compress: bool,
     /// Optional tag for grouping/cancellation
     pub tag: Option<String>,
+    /// Key that suppresses duplicate enqueues; also sent as `Idempotency-Key`
+    pub idempotency_key: Option<String>,
+    /// Token proving the current lease, set while the request is in flight
+    pub lease_token: Option<String>,
+    /// When the lease lapses and the request becomes eligible again
+    pub lease_expires_at: Option<String>,
+    /// FIFO lane: requests in a group go out one at a time, in enqueue order
+    pub ordering_group: Option<String>,
+    /// Request that must leave the queue before this one is sent
+    pub depends_on: Option<String>,
+    /// When the request expires if it hasn't been sent (RFC 3339, UTC)
+    pub deadline_at: Option<String>,
+    /// Network and power conditions required to send
+    pub constraints: Option<RequestConstraints>,
+}
+
+/// How a new request treats older pending requests to the same resource
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoalescePolicy {
+    /// Keep every request
+    #[default]
+    None,
+    /// Drop older pending requests with the same coalesce key
+    Replace,
+    /// Fold this request's JSON body into the oldest pending request with the
+    /// same coalesce key (JSON Merge Patch, RFC 7386)
+    MergeJson,
+}
+
+impl CoalescePolicy {
+    /// Parse `none`, `replace` or `merge-json`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Some(CoalescePolicy::None),
+            "replace" => Some(CoalescePolicy::Replace),
+            "merge-json" | "merge_json" | "mergejson" => Some(CoalescePolicy::MergeJson),
+            _ => None,
+        }
+    }
+}
+
+/// Optional settings for `RequestQueue::enqueue_with_options`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnqueueOptions {
+    pub priority: Priority,
+    /// Whether the body is already gzip-compressed
+    pub compress: bool,
+    pub tag: Option<String>,
+    /// While a request with this key is queued, further enqueues with the
+    /// same key return the existing request instead of adding another
+    pub idempotency_key: Option<String>,
+    pub coalesce: CoalescePolicy,
+    /// Resource identity for coalescing (defaults to method + URL)
+    pub coalesce_key: Option<String>,
+    /// Overrides the priority's retry policy for this request
+    pub retry_policy: Option<RetryPolicy>,
+    /// Send after every earlier request in this group has left the queue
+    pub ordering_group: Option<String>,
+    /// Hold until this request completes; dead-lettered with it if it fails
+    pub depends_on: Option<String>,
+    /// RFC 3339 time after which the request is dead-lettered instead of sent.
+    /// Requests with a deadline are exempt from `cleanup_old`.
+    pub deadline_at: Option<String>,
+    pub constraints: Option<RequestConstraints>,
+}
+
+impl Default for EnqueueOptions {
+    fn default() -> Self {
+        EnqueueOptions {
+            priority: Priority::Normal,
+            compress: false,
+            tag: None,
+            idempotency_key: None,
+            coalesce: CoalescePolicy::None,
+            coalesce_key: None,
+            retry_policy: None,
+            ordering_group: None,
+            depends_on: None,
+            deadline_at: None,
+            constraints: None,
+        }
+    }
+}
+
+/// What `enqueue_with_options` did
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnqueueOutcome {
+    /// ID of the queued request (the existing one if deduplicated)
+    pub id: String,
+    /// True if an already-queued request with the same idempotency key was found
+    pub deduplicated: bool,
+    /// True if the body was merged into an existing request (`MergeJson`)
+    pub merged: bool,
+    /// IDs of older requests removed by coalescing
+    pub superseded: Vec<String>,
+    /// IDs dropped to stay within `QueueLimits`, lowest priority and oldest
+    /// first. Contains `id` itself if the new request didn't fit.
+    pub shed: Vec<String>,
+}
+
+/// Columns read by `row_to_request`, in order
+const REQUEST_COLUMNS: &str = "id, method, url, headers_json, body, priority, retry_count, max_retries, \
+     created_at, next_attempt_at, compress, tag, idempotency_key, lease_token, lease_expires_at, \
+     ordering_group, depends_on, deadline_at, constraints";
+
+/// Holds back a request while an earlier request in its ordering group is
+/// still queued, and until the request it depends on has completed
+const ORDERING_CONDITION: &str = "(ordering_group IS NULL OR NOT EXISTS (
+         SELECT 1 FROM request_queue ahead
+         WHERE ahead.ordering_group = request_queue.ordering_group
+           AND (ahead.created_at < request_queue.created_at
+                OR (ahead.created_at = request_queue.created_at AND ahead.rowid < request_queue.rowid))))
+     AND (depends_on IS NULL OR EXISTS (
+         SELECT 1 FROM completed_requests done WHERE done.id = request_queue.depends_on))";
+
+/// Default visibility timeout for `dequeue`
+pub const DEFAULT_LEASE_SECONDS: u64 = 120;
+
+fn row_to_request(row: &rusqlite::Row) -> rusqlite::Result<QueuedRequest> {
+    Ok(QueuedRequest {
+        id: row.get(0)?,
+        method: row.get(1)?,
+        url: row.get(2)?,
+        headers_json: row.get(3)?,
+        body: read_body(row, 4)?,
+        priority: row.get(5)?,
+        retry_count: row.get::<_, u32>(6)?,
+        max_retries: row.get::<_, u32>(7)?,
+        created_at: row.get(8)?,
+        next_attempt_at: row.get(9)?,
+        compress: row.get::<_, i32>(10)? != 0,
+        tag: row.get(11)?,
+        idempotency_key: row.get(12)?,
+        lease_token: row.get(13)?,
+        lease_expires_at: row.get(14)?,
+        ordering_group: row.get(15)?,
+        depends_on: row.get(16)?,
+        deadline_at: row.get(17)?,
+        constraints: row
+            .get::<_, Option<String>>(18)?
+            .and_then(|c| serde_json::from_str(&c).ok()),
+    })
+}
+
+/// Add `name: value` to a JSON header object unless a header with that name
+/// (in any case) is already present
+fn with_header(headers_json: &str, name: &str, value: &str) -> Result<String, QueueError> {
+    let mut headers: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(headers_json)
+            .map_err(|e| QueueError::SerializationError(e.to_string()))?;
+    if !headers.keys().any(|k| k.eq_ignore_ascii_case(name)) {
+        headers.insert(
+            name.to_string(),
+            serde_json::Value::String(value.to_string()),
+        );
+    }
+    serde_json::to_string(&headers).map_err(|e| QueueError::SerializationError(e.to_string()))
+}
+
+/// `headers_json` with its `name` header (any case) taken from `source_json`,
+/// or dropped if `source_json` has none
+fn with_header_from(
+    headers_json: &str,
+    source_json: &str,
+    name: &str,
+) -> Result<String, QueueError> {
+    let parse = |json: &str| {
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(json)
+            .map_err(|e| QueueError::SerializationError(e.to_string()))
+    };
+    let mut headers = parse(headers_json)?;
+    headers.retain(|k, _| !k.eq_ignore_ascii_case(name));
+    if let Some((k, v)) = parse(source_json)?
+        .into_iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+    {
+        headers.insert(k, v);
+    }
+    serde_json::to_string(&headers).map_err(|e| QueueError::SerializationError(e.to_string()))
+}
+
+/// Apply a JSON Merge Patch (RFC 7386) to `target`
+fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let patch_map = match patch.as_object() {
+        Some(m) => m,
+        None => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let Some(target_map) = target.as_object_mut() {
+        for (key, value) in patch_map {
+            if value.is_null() {
+                target_map.remove(key);
+            } else {
+                merge_patch(
+                    target_map
+                        .entry(key.clone())
+                        .or_insert(serde_json::Value::Null),
+                    value,
+                );
+            }
+        }
+    }
+}
+
+/// Parse a stored body as JSON, gunzipping it first if needed
+fn body_json(body: Option<&[u8]>, compressed: bool) -> Option<serde_json::Value> {
+    let body = body?;
+    if compressed {
+        serde_json::from_slice(&optimization::decompress(body).ok()?).ok()
+    } else {
+        serde_json::from_slice(body).ok()
+    }
+}
+
+impl QueuedRequest {
+    /// Body as UTF-8 text, if present and valid UTF-8
+    pub fn body_text(&self) -> Option<&str> {
+        self.body
+            .as_deref()
+            .and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    /// Approximate bytes on the wire: URL, headers and body
+    pub fn wire_size(&self) -> u64 {
+        (self.url.len()
+            + self.headers_json.len()
+            + self.body.as_ref().map(|b| b.len()).unwrap_or(0)) as u64
+    }
 }
 
 /// Result of processing a queued request
@@ -132,6 +353,12 @@ pub enum QueueError {
     Empty,
     #[error("Serialization error: {0}")]
     SerializationError(String),
+    #[error("Lease for request {0} is not held by this token")]
+    LeaseMismatch(String),
+    #[error("Request {0} was dead-lettered")]
+    DependencyFailed(String),
+    #[error("Invalid retry policy: {0}")]
+    InvalidRetryPolicy(String),
 }
 
 impl From<rusqlite::Error> for QueueError {
@@ -143,6 +370,9 @@ impl From<rusqlite::Error> for QueueError {
 /// Persistent request queue backed by SQLite
 pub struct RequestQueue {
     conn: Mutex<Connection>,
+    retry_policies: Mutex<HashMap<Priority, RetryPolicy>>,
+    scheduler: Mutex<Scheduler>,
+    limits: Mutex<QueueLimits>,
 }
 
 impl RequestQueue {
@@ -156,6 +386,9 @@ impl RequestQueue {
 
         let queue = RequestQueue {
             conn: Mutex::new(conn),
+            retry_policies: Mutex::new(HashMap::new()),
+            scheduler: Mutex::new(Scheduler::default()),
+            limits: Mutex::new(QueueLimits::default()),
         };
         queue.initialize_db()?;
         Ok(queue)
@@ -170,43 +403,293 @@ impl RequestQueue {
                 method TEXT NOT NULL,
                 url TEXT NOT NULL,
                 headers_json TEXT NOT NULL DEFAULT '{}',
-                body TEXT,
+                body BLOB,
                 priority INTEGER NOT NULL DEFAULT 1,
                 retry_count INTEGER NOT NULL DEFAULT 0,
                 max_retries INTEGER NOT NULL DEFAULT 3,
                 created_at TEXT NOT NULL,
                 next_attempt_at TEXT NOT NULL,
                 compress INTEGER NOT NULL DEFAULT 0,
-                tag TEXT
+                tag TEXT,
+                idempotency_key TEXT,
+                coalesce_key TEXT,
+                attempt_history TEXT NOT NULL DEFAULT '[]',
+                retry_policy TEXT,
+                last_backoff_ms INTEGER NOT NULL DEFAULT 0,
+                lease_token TEXT,
+                lease_expires_at TEXT,
+                ordering_group TEXT,
+                depends_on TEXT,
+                deadline_at TEXT,
+                constraints TEXT
             );
 
             CREATE INDEX IF NOT EXISTS idx_queue_priority ON request_queue(priority DESC, created_at ASC);
             CREATE INDEX IF NOT EXISTS idx_queue_next_attempt ON request_queue(next_attempt_at);
             CREATE INDEX IF NOT EXISTS idx_queue_tag ON request_queue(tag);
+
+            CREATE TABLE IF NOT EXISTS completed_requests (
+                id TEXT PRIMARY KEY,
+                completed_at TEXT NOT NULL
+            );
+            ",
+        )?;
+
+        // Columns added after the initial schema
+        add_column_if_missing(&conn, "request_queue", "idempotency_key", "TEXT")?;
+        add_column_if_missing(&conn, "request_queue", "coalesce_key", "TEXT")?;
+        add_column_if_missing(
+            &conn,
+            "request_queue",
+            "attempt_history",
+            "TEXT NOT NULL DEFAULT '[]'",
+        )?;
+        add_column_if_missing(&conn, "request_queue", "retry_policy", "TEXT")?;
+        add_column_if_missing(
+            &conn,
+            "request_queue",
+            "last_backoff_ms",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(&conn, "request_queue", "lease_token", "TEXT")?;
+        add_column_if_missing(&conn, "request_queue", "lease_expires_at", "TEXT")?;
+        add_column_if_missing(&conn, "request_queue", "ordering_group", "TEXT")?;
+        add_column_if_missing(&conn, "request_queue", "depends_on", "TEXT")?;
+        add_column_if_missing(&conn, "request_queue", "deadline_at", "TEXT")?;
+        add_column_if_missing(&conn, "request_queue", "constraints", "TEXT")?;
+        conn.execute_batch(
+            "
+            UPDATE request_queue SET coalesce_key = method || ' ' || url WHERE coalesce_key IS NULL;
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_queue_idempotency
+                ON request_queue(idempotency_key) WHERE idempotency_key IS NOT NULL;
+            CREATE INDEX IF NOT EXISTS idx_queue_coalesce ON request_queue(coalesce_key);
+            CREATE INDEX IF NOT EXISTS idx_queue_ordering_group ON request_queue(ordering_group, created_at);
+            CREATE INDEX IF NOT EXISTS idx_queue_depends_on ON request_queue(depends_on);
+            CREATE INDEX IF NOT EXISTS idx_queue_deadline ON request_queue(deadline_at);
             ",
         )?;
+        dead_letter::initialize(&conn)?;
+
+        // Parents that left the queue before completions were recorded
+        // released their dependents, so count them as completed
+        conn.execute(
+            "INSERT OR IGNORE INTO completed_requests (id, completed_at)
+             SELECT DISTINCT depends_on, ?1 FROM request_queue
+             WHERE depends_on IS NOT NULL
+               AND depends_on NOT IN (SELECT id FROM request_queue)
+               AND depends_on NOT IN (SELECT id FROM dead_letters)",
+            params![Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Set the retry policy for requests of `priority` that don't carry
+    /// their own. Applies to requests enqueued and failures recorded from now on.
+    pub fn set_retry_policy(
+        &self,
+        priority: Priority,
+        policy: RetryPolicy,
+    ) -> Result<(), QueueError> {
+        policy.validate().map_err(QueueError::InvalidRetryPolicy)?;
+        if let Ok(mut policies) = self.retry_policies.lock() {
+            policies.insert(priority, policy);
+        }
         Ok(())
     }
 
+    /// Retry policy used for requests of `priority` without their own
+    pub fn retry_policy(&self, priority: Priority) -> RetryPolicy {
+        self.retry_policies
+            .lock()
+            .ok()
+            .and_then(|p| p.get(&priority).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Change how eligible requests of different priorities are ordered
+    pub fn set_scheduler(&self, config: SchedulerConfig) {
+        if let Ok(mut scheduler) = self.scheduler.lock() {
+            *scheduler = Scheduler::new(config);
+        }
+    }
+
+    /// Current scheduler settings
+    pub fn scheduler(&self) -> SchedulerConfig {
+        self.scheduler
+            .lock()
+            .map(|s| s.config.clone())
+            .unwrap_or_default()
+    }
+
+    /// Set capacity limits, enforced on every enqueue from now on
+    pub fn set_limits(&self, limits: QueueLimits) {
+        if let Ok(mut current) = self.limits.lock() {
+            *current = limits;
+        }
+    }
+
+    /// Current capacity limits
+    pub fn limits(&self) -> QueueLimits {
+        self.limits.lock().map(|l| l.clone()).unwrap_or_default()
+    }
+
+    /// Attempts allowed under `policy` (or the priority's policy), falling
+    /// back to the priority's default
+    fn max_attempts(&self, priority: Priority, policy: Option<&RetryPolicy>) -> u32 {
+        policy
+            .and_then(|p| p.max_attempts)
+            .or_else(|| self.retry_policy(priority).max_attempts)
+            .unwrap_or_else(|| priority.max_retries())
+    }
+
     /// Add a request to the queue
     pub fn enqueue(
         &self,
         method: &str,
         url: &str,
         headers_json: &str,
-        body: Option<&str>,
+        body: Option<&[u8]>,
         priority: Priority,
         compress: bool,
         tag: Option<&str>,
     ) -> Result<String, QueueError> {
+        let options = EnqueueOptions {
+            priority,
+            compress,
+            tag: tag.map(str::to_string),
+            ..EnqueueOptions::default()
+        };
+        Ok(self
+            .enqueue_with_options(method, url, headers_json, body, &options)?
+            .id)
+    }
+
+    /// Add a request to the queue, deduplicating on `options.idempotency_key`
+    /// and coalescing with older requests per `options.coalesce`.
+    ///
+    /// A keyed request gets an `Idempotency-Key` header (unless `headers_json`
+    /// already has one). Once the request completes or is dropped the key can
+    /// be used again.
+    ///
+    /// `MergeJson` falls back to `Replace` when either body isn't JSON.
+    ///
+    /// If the queue is then over its `QueueLimits`, lower-priority requests
+    /// are shed (see `EnqueueOutcome::shed`).
+    ///
+    /// Fails with `DependencyFailed` if `options.depends_on` names a request
+    /// that is neither queued nor completed (it was dead-lettered, cancelled
+    /// or never existed), and with
+    /// `SerializationError` if `options.deadline_at` isn't RFC 3339.
+    pub fn enqueue_with_options(
+        &self,
+        method: &str,
+        url: &str,
+        headers_json: &str,
+        body: Option<&[u8]>,
+        options: &EnqueueOptions,
+    ) -> Result<EnqueueOutcome, QueueError> {
         let conn = self.conn.lock().map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let mut outcome = self.enqueue_locked(&conn, method, url, headers_json, body, options)?;
+        if !outcome.deduplicated {
+            outcome.shed = limits::enforce(&conn, &self.limits(), options.tag.as_deref())?;
+        }
+        Ok(outcome)
+    }
+
+    fn enqueue_locked(
+        &self,
+        conn: &Connection,
+        method: &str,
+        url: &str,
+        headers_json: &str,
+        body: Option<&[u8]>,
+        options: &EnqueueOptions,
+    ) -> Result<EnqueueOutcome, QueueError> {
+        let headers_json = match options.idempotency_key {
+            Some(ref key) => {
+                let existing: Option<String> = conn
+                    .query_row(
+                        "SELECT id FROM request_queue WHERE idempotency_key = ?1",
+                        params![key],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                if let Some(id) = existing {
+                    return Ok(EnqueueOutcome {
+                        id,
+                        deduplicated: true,
+                        merged: false,
+                        superseded: Vec::new(),
+                        shed: Vec::new(),
+                    });
+                }
+                with_header(headers_json, "Idempotency-Key", key)?
+            }
+            None => headers_json.to_string(),
+        };
+
+        if let Some(ref parent) = options.depends_on
+            && !Self::can_depend_on(conn, parent)?
+        {
+            return Err(QueueError::DependencyFailed(parent.clone()));
+        }
+
+        let deadline_at = match options.deadline_at {
+            Some(ref d) => Some(
+                chrono::DateTime::parse_from_rfc3339(d)
+                    .map_err(|e| QueueError::SerializationError(format!("deadline_at: {}", e)))?
+                    .with_timezone(&Utc)
+                    .to_rfc3339(),
+            ),
+            None => None,
+        };
+        let constraints = match options.constraints {
+            Some(ref c) => Some(
+                serde_json::to_string(c)
+                    .map_err(|e| QueueError::SerializationError(e.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let coalesce_key = options
+            .coalesce_key
+            .clone()
+            .unwrap_or_else(|| format!("{} {}", method.to_uppercase(), url));
+
+        let older = match options.coalesce {
+            CoalescePolicy::None => Vec::new(),
+            CoalescePolicy::Replace | CoalescePolicy::MergeJson => {
+                Self::pending_for_key(conn, &coalesce_key)?
+            }
+        };
+
+        if options.coalesce == CoalescePolicy::MergeJson
+            && let Some(outcome) = self.merge_into(conn, &older, &headers_json, body, options)?
+        {
+            return Ok(outcome);
+        }
+
+        let superseded: Vec<String> = older.into_iter().map(|r| r.id).collect();
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().to_rfc3339();
+        let priority = options.priority;
+        let retry_policy = match options.retry_policy {
+            Some(ref p) => {
+                p.validate().map_err(QueueError::InvalidRetryPolicy)?;
+                Some(
+                    serde_json::to_string(p)
+                        .map_err(|e| QueueError::SerializationError(e.to_string()))?,
+                )
+            }
+            None => None,
+        };
 
         conn.execute(
             "INSERT INTO request_queue 
-             (id, method, url, headers_json, body, priority, retry_count, max_retries, created_at, next_attempt_at, compress, tag)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0, ?7, ?8, ?8, ?9, ?10)",
+             (id, method, url, headers_json, body, priority, retry_count, max_retries, created_at, next_attempt_at,
+              compress, tag, idempotency_key, coalesce_key, retry_policy, ordering_group, depends_on,
+              deadline_at, constraints)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0, ?7, ?8, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
             params![
                 id,
                 method,
@@ -214,60 +697,446 @@ impl RequestQueue {
                 headers_json,
                 body,
                 priority as i32,
-                priority.max_retries(),
+                self.max_attempts(priority, options.retry_policy.as_ref()),
                 now,
-                compress as i32,
-                tag,
+                options.compress as i32,
+                options.tag,
+                options.idempotency_key,
+                coalesce_key,
+                retry_policy,
+                options.ordering_group,
+                options.depends_on,
+                deadline_at,
+                constraints,
+            ],
+        )?;
+        Self::supersede(conn, &superseded, &id)?;
+
+        Ok(EnqueueOutcome {
+            id,
+            deduplicated: false,
+            merged: false,
+            superseded,
+            shed: Vec::new(),
+        })
+    }
+
+    /// Whether a new or requeued request may wait on `parent`: it must be
+    /// queued or already completed
+    fn can_depend_on(conn: &Connection, parent: &str) -> Result<bool, QueueError> {
+        let known: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM request_queue WHERE id = ?1)
+                 OR EXISTS (SELECT 1 FROM completed_requests WHERE id = ?1)",
+            params![parent],
+            |row| row.get(0),
+        )?;
+        Ok(known)
+    }
+
+    /// Remove requests coalesced into `replacement`; anything that depended
+    /// on them now waits for `replacement` instead
+    fn supersede(
+        conn: &Connection,
+        superseded: &[String],
+        replacement: &str,
+    ) -> Result<(), QueueError> {
+        for old_id in superseded {
+            conn.execute("DELETE FROM request_queue WHERE id = ?1", params![old_id])?;
+            conn.execute(
+                "UPDATE request_queue SET depends_on = ?1 WHERE depends_on = ?2",
+                params![replacement, old_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Remember that `request_id` completed, releasing its dependents
+    fn record_completion(conn: &Connection, request_id: &str) -> Result<(), QueueError> {
+        conn.execute(
+            "INSERT OR REPLACE INTO completed_requests (id, completed_at) VALUES (?1, ?2)",
+            params![request_id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Pending requests with a coalesce key, oldest first. Requests that are
+    /// in flight under a live lease are left alone.
+    fn pending_for_key(
+        conn: &Connection,
+        coalesce_key: &str,
+    ) -> Result<Vec<QueuedRequest>, QueueError> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM request_queue
+             WHERE coalesce_key = ?1 AND (lease_expires_at IS NULL OR lease_expires_at <= ?2)
+             ORDER BY created_at ASC",
+            REQUEST_COLUMNS
+        ))?;
+        let requests = stmt
+            .query_map(
+                params![coalesce_key, Utc::now().to_rfc3339()],
+                row_to_request,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(requests)
+    }
+
+    /// Merge-patch every pending body and then the new one into the oldest
+    /// pending request, which keeps its place in the queue. Returns None if
+    /// there's nothing to merge into or a body isn't JSON.
+    fn merge_into(
+        &self,
+        conn: &Connection,
+        older: &[QueuedRequest],
+        headers_json: &str,
+        body: Option<&[u8]>,
+        options: &EnqueueOptions,
+    ) -> Result<Option<EnqueueOutcome>, QueueError> {
+        let (target, rest) = match older.split_first() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        let mut merged = match body_json(target.body.as_deref(), target.compress) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        for req in rest {
+            match body_json(req.body.as_deref(), req.compress) {
+                Some(patch) => merge_patch(&mut merged, &patch),
+                None => return Ok(None),
+            }
+        }
+        match body_json(body, options.compress) {
+            Some(patch) => merge_patch(&mut merged, &patch),
+            None => return Ok(None),
+        }
+
+        let merged = serde_json::to_vec(&merged)
+            .map_err(|e| QueueError::SerializationError(e.to_string()))?;
+        let (merged, compressed) = if options.compress {
+            let gz = optimization::compress(&merged)
+                .map_err(|e| QueueError::SerializationError(e.to_string()))?;
+            (gz, true)
+        } else {
+            (merged, false)
+        };
+
+        // The newest request's headers and priority describe the net change,
+        // but the target keeps its idempotency key, so it keeps the header too
+        let headers_json = with_header_from(headers_json, &target.headers_json, "Idempotency-Key")?;
+        let priority = options.priority.max(Priority::from_i32(target.priority));
+        conn.execute(
+            "UPDATE request_queue
+             SET body = ?1, compress = ?2, headers_json = ?3, priority = ?4,
+                 max_retries = MAX(max_retries, ?5)
+             WHERE id = ?6",
+            params![
+                merged,
+                compressed as i32,
+                headers_json,
+                priority as i32,
+                self.max_attempts(priority, options.retry_policy.as_ref()),
+                target.id,
             ],
         )?;
 
-        Ok(id)
+        let superseded: Vec<String> = rest.iter().map(|r| r.id.clone()).collect();
+        Self::supersede(conn, &superseded, &target.id)?;
+
+        Ok(Some(EnqueueOutcome {
+            id: target.id.clone(),
+            deduplicated: false,
+            merged: true,
+            superseded,
+            shed: Vec::new(),
+        }))
     }
 
-    /// Get the next request that should be sent, based on priority and timing
+    /// Get the next request that should be sent, based on priority and
+    /// timing, leased for `DEFAULT_LEASE_SECONDS`
     pub fn dequeue(&self, current_quality_score: u8) -> Result<Option<QueuedRequest>, QueueError> {
+        self.dequeue_with_lease(
+            current_quality_score,
+            Duration::from_secs(DEFAULT_LEASE_SECONDS),
+        )
+    }
+
+    /// Get the next request that should be sent and lease it: until `lease`
+    /// elapses no other dequeue returns it. Finish with `complete_leased` /
+    /// `fail_leased`; if neither happens in time (e.g. the app crashed
+    /// mid-send) that counts as a failed attempt and the request becomes
+    /// eligible again, or is dead-lettered if it has no attempts left.
+    ///
+    /// Only the quality score is known here, so requests with constraints
+    /// are held; use `dequeue_when` to evaluate them.
+    pub fn dequeue_with_lease(
+        &self,
+        current_quality_score: u8,
+        lease: Duration,
+    ) -> Result<Option<QueuedRequest>, QueueError> {
+        self.dequeue_when(&SendConditions::with_quality(current_quality_score), lease)
+    }
+
+    /// Like `dequeue_with_lease`, but also checks each request's constraints
+    /// against `conditions`. Requests past their deadline are dead-lettered
+    /// first.
+    pub fn dequeue_when(
+        &self,
+        conditions: &SendConditions,
+        lease: Duration,
+    ) -> Result<Option<QueuedRequest>, QueueError> {
         let conn = self.conn.lock().map_err(|e| QueueError::DatabaseError(e.to_string()))?;
-        let now = Utc::now().to_rfc3339();
+        let now = Utc::now();
+        Self::expire_deadlines(&conn, now)?;
+        Self::reclaim_lapsed_leases(&conn, now)?;
 
-        // Get highest priority request whose next_attempt_at has passed
-        // and whose priority allows sending at current quality
-        let result = conn.query_row(
-            "SELECT id, method, url, headers_json, body, priority, retry_count, max_retries, 
-                    created_at, next_attempt_at, compress, tag
-             FROM request_queue 
-             WHERE next_attempt_at <= ?1
-             ORDER BY priority DESC, created_at ASC 
-             LIMIT 1",
-            params![now],
-            |row| {
-                Ok(QueuedRequest {
-                    id: row.get(0)?,
-                    method: row.get(1)?,
-                    url: row.get(2)?,
-                    headers_json: row.get(3)?,
-                    body: row.get(4)?,
-                    priority: row.get(5)?,
-                    retry_count: row.get::<_, u32>(6)?,
-                    max_retries: row.get::<_, u32>(7)?,
-                    created_at: row.get(8)?,
-                    next_attempt_at: row.get(9)?,
-                    compress: row.get::<_, i32>(10)? != 0,
-                    tag: row.get(11)?,
-                })
-            },
-        );
+        let mut req = match self.pick(&conn, conditions, 1, now)?.into_iter().next() {
+            Some(candidate) => Self::load_request(&conn, &candidate.id)?,
+            None => return Ok(None),
+        };
+
+        let expires_at =
+            (now + chrono::Duration::milliseconds(lease.as_millis() as i64)).to_rfc3339();
+        Self::take_lease(&conn, &mut req, &expires_at)?;
+        Ok(Some(req))
+    }
+
+    /// Lease up to `max_requests` eligible requests whose combined
+    /// `wire_size` fits in `max_bytes`, in priority order, so they can be
+    /// sent in parallel. Requests whose quality threshold or constraints
+    /// aren't met are skipped. The first request is always returned, even if
+    /// it alone exceeds the budget, so large requests can't stall the queue.
+    pub fn dequeue_batch(
+        &self,
+        conditions: &SendConditions,
+        max_requests: u32,
+        max_bytes: u64,
+        lease: Duration,
+    ) -> Result<Vec<QueuedRequest>, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let now = Utc::now();
+        Self::expire_deadlines(&conn, now)?;
+        Self::reclaim_lapsed_leases(&conn, now)?;
+
+        let expires_at =
+            (now + chrono::Duration::milliseconds(lease.as_millis() as i64)).to_rfc3339();
+        let mut batch = Vec::new();
+        let mut used = 0u64;
+        for candidate in self.pick(&conn, conditions, max_requests as usize, now)? {
+            let mut req = Self::load_request(&conn, &candidate.id)?;
+            let size = req.wire_size();
+            if !batch.is_empty() && used + size > max_bytes {
+                break;
+            }
+            used += size;
+            Self::take_lease(&conn, &mut req, &expires_at)?;
+            batch.push(req);
+        }
+
+        Ok(batch)
+    }
+
+    /// Up to `limit` requests that may be sent now under `conditions`, in
+    /// the order the scheduler sends them
+    fn pick(
+        &self,
+        conn: &Connection,
+        conditions: &SendConditions,
+        limit: usize,
+        now: chrono::DateTime<Utc>,
+    ) -> Result<Vec<Candidate>, QueueError> {
+        let mut scheduler = self
+            .scheduler
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let eligible = Self::eligible(conn, conditions, &scheduler.config, limit, now)?;
+        Ok(scheduler.order(eligible, limit, now))
+    }
+
+    /// Requests that may be sent now under `conditions`: due, not leased,
+    /// not held by ordering, and allowed by quality and constraints.
+    ///
+    /// The scheduler only ever takes the oldest `limit` of one effective
+    /// priority, so only that many are read per priority and aging window.
+    /// Only what scheduling needs is read; bodies never load here.
+    fn eligible(
+        conn: &Connection,
+        conditions: &SendConditions,
+        config: &SchedulerConfig,
+        limit: usize,
+        now: chrono::DateTime<Utc>,
+    ) -> Result<Vec<Candidate>, QueueError> {
+        // Thresholds rise as priority falls, so this is the lowest priority
+        // the quality score allows
+        let min_priority = [
+            Priority::Low,
+            Priority::Normal,
+            Priority::High,
+            Priority::Critical,
+        ]
+        .into_iter()
+        .find(|p| conditions.quality_score >= p.min_quality_score());
+        let min_priority = match min_priority {
+            Some(p) => p,
+            None => return Ok(Vec::new()), // Network not good enough for any priority
+        };
 
-        match result {
-            Ok(req) => {
-                let priority = Priority::from_i32(req.priority);
-                if current_quality_score >= priority.min_quality_score() {
-                    Ok(Some(req))
-                } else {
-                    Ok(None) // Network not good enough for this priority
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, created_at, constraints FROM request_queue
+             WHERE next_attempt_at <= ?1
+               AND (lease_expires_at IS NULL OR lease_expires_at <= ?1)
+               AND priority = ?2
+               AND (?3 IS NULL OR created_at <= ?3)
+               AND (?4 IS NULL OR created_at > ?4)
+               AND {}
+             ORDER BY created_at ASC",
+            ORDERING_CONDITION
+        ))?;
+        let now_str = now.to_rfc3339();
+        let mut candidates = Vec::new();
+        for priority in [
+            Priority::Critical,
+            Priority::High,
+            Priority::Normal,
+            Priority::Low,
+        ] {
+            if priority < min_priority {
+                break;
+            }
+            for (newest, oldest) in config.aging_windows(priority, now) {
+                let newest = newest.map(|t| t.to_rfc3339());
+                let oldest = oldest.map(|t| t.to_rfc3339());
+                let mut rows = stmt.query(params![now_str, priority as i32, newest, oldest])?;
+                let mut taken = 0;
+                // Rows held by constraints don't count toward the window
+                while taken < limit
+                    && let Some(row) = rows.next()?
+                {
+                    let constraints: Option<RequestConstraints> = row
+                        .get::<_, Option<String>>(2)?
+                        .and_then(|c| serde_json::from_str(&c).ok());
+                    if conditions.admits(priority, constraints.as_ref()) {
+                        candidates.push(Candidate {
+                            id: row.get(0)?,
+                            priority,
+                            created_at: row.get(1)?,
+                        });
+                        taken += 1;
+                    }
                 }
             }
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(QueueError::DatabaseError(e.to_string())),
+        }
+        Ok(candidates)
+    }
+
+    /// The full row, body included, of a request about to be leased
+    fn load_request(conn: &Connection, id: &str) -> Result<QueuedRequest, QueueError> {
+        let req = conn.query_row(
+            &format!(
+                "SELECT {} FROM request_queue WHERE id = ?1",
+                REQUEST_COLUMNS
+            ),
+            params![id],
+            row_to_request,
+        )?;
+        Ok(req)
+    }
+
+    /// Dead-letter requests whose deadline has passed
+    fn expire_deadlines(conn: &Connection, now: chrono::DateTime<Utc>) -> Result<u64, QueueError> {
+        let failure = RequestFailure {
+            error: Some("Deadline passed before the request was sent".to_string()),
+            ..RequestFailure::default()
+        };
+        dead_letter::bury(
+            conn,
+            "deadline_at IS NOT NULL AND deadline_at <= ?1",
+            &[&now.to_rfc3339()],
+            DeadLetterReason::DeadlineExceeded,
+            &failure,
+        )
+    }
+
+    /// Count each lapsed lease as a failed attempt: an earlier send never
+    /// reported back, possibly because it crashed the app. The request is
+    /// released for an immediate retry, or dead-lettered once its attempts
+    /// run out.
+    fn reclaim_lapsed_leases(
+        conn: &Connection,
+        now: chrono::DateTime<Utc>,
+    ) -> Result<(), QueueError> {
+        let mut stmt = conn.prepare(
+            "SELECT id, retry_count + 1 >= max_retries FROM request_queue
+             WHERE lease_token IS NOT NULL AND lease_expires_at <= ?1",
+        )?;
+        let lapsed = stmt
+            .query_map(params![now.to_rfc3339()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let failure = RequestFailure {
+            error: Some("Lease expired before the request completed".to_string()),
+            ..RequestFailure::default()
+        };
+        for (id, exhausted) in lapsed {
+            dead_letter::record_attempt(conn, &id, &failure)?;
+            conn.execute(
+                "UPDATE request_queue
+                 SET retry_count = retry_count + 1, lease_token = NULL, lease_expires_at = NULL
+                 WHERE id = ?1",
+                params![id],
+            )?;
+            if exhausted {
+                dead_letter::bury(
+                    conn,
+                    "id = ?1",
+                    &[&id],
+                    DeadLetterReason::RetriesExhausted,
+                    &failure,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lease `req` until `expires_at` under a fresh token
+    fn take_lease(
+        conn: &Connection,
+        req: &mut QueuedRequest,
+        expires_at: &str,
+    ) -> Result<(), QueueError> {
+        let token = Uuid::new_v4().to_string();
+        conn.execute(
+            "UPDATE request_queue SET lease_token = ?1, lease_expires_at = ?2 WHERE id = ?3",
+            params![token, expires_at, req.id],
+        )?;
+        req.lease_token = Some(token);
+        req.lease_expires_at = Some(expires_at.to_string());
+        Ok(())
+    }
+
+    /// Fail with `LeaseMismatch` unless `lease_token` holds the request's
+    /// current lease
+    fn check_lease(
+        conn: &Connection,
+        request_id: &str,
+        lease_token: &str,
+    ) -> Result<(), QueueError> {
+        let current: Option<Option<String>> = conn
+            .query_row(
+                "SELECT lease_token FROM request_queue WHERE id = ?1",
+                params![request_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match current {
+            None => Err(QueueError::NotFound(request_id.to_string())),
+            Some(Some(ref t)) if t == lease_token => Ok(()),
+            Some(_) => Err(QueueError::LeaseMismatch(request_id.to_string())),
         }
     }
 
@@ -278,41 +1147,192 @@ impl RequestQueue {
             "DELETE FROM request_queue WHERE id = ?1",
             params![request_id],
         )?;
+        if rows > 0 {
+            Self::record_completion(&conn, request_id)?;
+        }
         Ok(rows > 0)
     }
 
+    /// Complete a request dequeued under `lease_token`. Fails with
+    /// `LeaseMismatch` if the lease lapsed and the request was leased again.
+    pub fn complete_leased(&self, request_id: &str, lease_token: &str) -> Result<(), QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        Self::check_lease(&conn, request_id, lease_token)?;
+        conn.execute(
+            "DELETE FROM request_queue WHERE id = ?1",
+            params![request_id],
+        )?;
+        Self::record_completion(&conn, request_id)
+    }
+
+    /// `fail_with` for a request dequeued under `lease_token`
+    pub fn fail_leased(
+        &self,
+        request_id: &str,
+        lease_token: &str,
+        failure: &RequestFailure,
+    ) -> Result<bool, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        Self::check_lease(&conn, request_id, lease_token)?;
+        self.fail_locked(&conn, request_id, failure)
+    }
+
     /// Mark a request as failed — increment retry count and set backoff
     pub fn fail(&self, request_id: &str) -> Result<bool, QueueError> {
-        let conn = self.conn.lock().map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        self.fail_with(request_id, &RequestFailure::default())
+    }
+
+    /// Mark a request as failed, recording what went wrong in its attempt
+    /// history, and schedule the next attempt under its retry policy.
+    ///
+    /// 4xx statuses other than 408 and 429 fail permanently. For 429 and 503
+    /// a `Retry-After` value replaces the computed delay. Returns false if the
+    /// request was moved to the dead-letter table.
+    pub fn fail_with(
+        &self,
+        request_id: &str,
+        failure: &RequestFailure,
+    ) -> Result<bool, QueueError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        self.fail_locked(&conn, request_id, failure)
+    }
 
+    fn fail_locked(
+        &self,
+        conn: &Connection,
+        request_id: &str,
+        failure: &RequestFailure,
+    ) -> Result<bool, QueueError> {
         // Get current state
-        let (retry_count, max_retries): (u32, u32) = conn.query_row(
-            "SELECT retry_count, max_retries FROM request_queue WHERE id = ?1",
-            params![request_id],
-            |row| Ok((row.get(0)?, row.get(1)?)),
-        ).map_err(|_| QueueError::NotFound(request_id.to_string()))?;
+        #[allow(clippy::type_complexity)]
+        let (retry_count, max_retries, priority, created_at, policy_json, last_backoff_ms, deadline_at): (
+            u32,
+            u32,
+            i32,
+            String,
+            Option<String>,
+            i64,
+            Option<String>,
+        ) = conn
+            .query_row(
+                "SELECT retry_count, max_retries, priority, created_at, retry_policy, last_backoff_ms, deadline_at
+                 FROM request_queue WHERE id = ?1",
+                params![request_id],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                    ))
+                },
+            )
+            .map_err(|_| QueueError::NotFound(request_id.to_string()))?;
 
         let new_retry_count = retry_count + 1;
+        dead_letter::record_attempt(conn, request_id, failure)?;
+        conn.execute(
+            "UPDATE request_queue SET retry_count = ?1 WHERE id = ?2",
+            params![new_retry_count, request_id],
+        )?;
+
+        if failure.status_code.is_some_and(is_permanent_status) {
+            dead_letter::bury(
+                conn,
+                "id = ?1",
+                &[&request_id],
+                DeadLetterReason::PermanentFailure,
+                failure,
+            )?;
+            return Ok(false);
+        }
 
         if new_retry_count >= max_retries {
-            // Max retries exceeded — remove from queue
-            conn.execute(
-                "DELETE FROM request_queue WHERE id = ?1",
-                params![request_id],
+            // Max retries exceeded — move to dead letters
+            dead_letter::bury(
+                conn,
+                "id = ?1",
+                &[&request_id],
+                DeadLetterReason::RetriesExhausted,
+                failure,
             )?;
             return Ok(false); // Request dropped
         }
 
-        // Exponential backoff with jitter: base * 2^retry + random(0..base)
-        let base_seconds: u64 = 2;
-        let backoff_seconds = base_seconds * 2u64.pow(new_retry_count);
-        let backoff_seconds = backoff_seconds.min(300); // Cap at 5 minutes
+        let policy = policy_json
+            .and_then(|p| serde_json::from_str::<RetryPolicy>(&p).ok())
+            .unwrap_or_else(|| self.retry_policy(Priority::from_i32(priority)));
+
+        let now = Utc::now();
+        let retry_after = match failure.status_code {
+            Some(429) | Some(503) => failure
+                .retry_after
+                .as_deref()
+                .and_then(|v| parse_retry_after(v, now)),
+            _ => None,
+        };
+        let delay_ms = match retry_after {
+            Some(seconds) => seconds * 1000,
+            None => policy.delay_ms(new_retry_count, last_backoff_ms.max(0) as u64),
+        };
+        let next_attempt = i64::try_from(delay_ms)
+            .ok()
+            .and_then(chrono::TimeDelta::try_milliseconds)
+            .and_then(|delay| now.checked_add_signed(delay))
+            .unwrap_or(now + chrono::Duration::milliseconds(retry::MAX_RETRY_DELAY_MS as i64));
+
+        if let Some(max_elapsed) = policy.max_elapsed_seconds {
+            let created_at = chrono::DateTime::parse_from_rfc3339(&created_at)
+                .map(|d| d.with_timezone(&Utc))
+                .unwrap_or(now);
+            let give_up_at = i64::try_from(max_elapsed)
+                .ok()
+                .and_then(chrono::TimeDelta::try_seconds)
+                .and_then(|budget| created_at.checked_add_signed(budget));
+            if give_up_at.is_some_and(|at| next_attempt > at) {
+                dead_letter::bury(
+                    conn,
+                    "id = ?1",
+                    &[&request_id],
+                    DeadLetterReason::RetriesExhausted,
+                    failure,
+                )?;
+                return Ok(false);
+            }
+        }
 
-        let next_attempt = Utc::now() + chrono::Duration::seconds(backoff_seconds as i64);
+        // No point waiting for a retry that would land after the deadline
+        if let Some(deadline) =
+            deadline_at.and_then(|d| chrono::DateTime::parse_from_rfc3339(&d).ok())
+            && next_attempt > deadline
+        {
+            dead_letter::bury(
+                conn,
+                "id = ?1",
+                &[&request_id],
+                DeadLetterReason::DeadlineExceeded,
+                failure,
+            )?;
+            return Ok(false);
+        }
 
         conn.execute(
-            "UPDATE request_queue SET retry_count = ?1, next_attempt_at = ?2 WHERE id = ?3",
-            params![new_retry_count, next_attempt.to_rfc3339(), request_id],
+            "UPDATE request_queue
+             SET next_attempt_at = ?1, last_backoff_ms = ?2, lease_token = NULL, lease_expires_at = NULL
+             WHERE id = ?3",
+            params![next_attempt.to_rfc3339(), delay_ms as i64, request_id],
         )?;
 
         Ok(true) // Request will be retried
@@ -340,23 +1360,39 @@ impl RequestQueue {
         Ok(count)
     }
 
-    /// Cancel all requests with a specific tag
+    /// Cancel all requests with a specific tag. Requests depending on a
+    /// cancelled one are dead-lettered as `DependencyFailed`.
     pub fn cancel_by_tag(&self, tag: &str) -> Result<u64, QueueError> {
         let conn = self.conn.lock().map_err(|e| QueueError::DatabaseError(e.to_string()))?;
+        let mut stmt = conn.prepare("SELECT id FROM request_queue WHERE tag = ?1")?;
+        let ids = stmt
+            .query_map(params![tag], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
         let rows = conn.execute(
             "DELETE FROM request_queue WHERE tag = ?1",
             params![tag],
         )?;
+        for id in &ids {
+            dead_letter::bury_dependents(&conn, id, "A request this one depends on was cancelled")?;
+        }
         Ok(rows as u64)
     }
 
-    /// Cancel a specific request
+    /// Cancel a specific request. Requests depending on it are
+    /// dead-lettered as `DependencyFailed`.
     pub fn cancel(&self, request_id: &str) -> Result<bool, QueueError> {
         let conn = self.conn.lock().map_err(|e| QueueError::DatabaseError(e.to_string()))?;
         let rows = conn.execute(
             "DELETE FROM request_queue WHERE id = ?1",
             params![request_id],
         )?;
+        if rows > 0 {
+            dead_letter::bury_dependents(
+                &conn,
+                request_id,
+                "A request this one depends on was cancelled",
+            )?;
+        }
         Ok(rows > 0)
     }
 
@@ -370,46 +1406,43 @@ impl RequestQueue {
     /// Get all pending requests (for debugging/display)
     pub fn list_pending(&self, limit: u32) -> Result<Vec<QueuedRequest>, QueueError> {
         let conn = self.conn.lock().map_err(|e| QueueError::DatabaseError(e.to_string()))?;
-        let mut stmt = conn.prepare(
-            "SELECT id, method, url, headers_json, body, priority, retry_count, max_retries,
-                    created_at, next_attempt_at, compress, tag
-             FROM request_queue 
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM request_queue 
              ORDER BY priority DESC, created_at ASC
              LIMIT ?1",
-        )?;
+            REQUEST_COLUMNS
+        ))?;
 
         let requests = stmt
-            .query_map(params![limit], |row| {
-                Ok(QueuedRequest {
-                    id: row.get(0)?,
-                    method: row.get(1)?,
-                    url: row.get(2)?,
-                    headers_json: row.get(3)?,
-                    body: row.get(4)?,
-                    priority: row.get(5)?,
-                    retry_count: row.get::<_, u32>(6)?,
-                    max_retries: row.get::<_, u32>(7)?,
-                    created_at: row.get(8)?,
-                    next_attempt_at: row.get(9)?,
-                    compress: row.get::<_, i32>(10)? != 0,
-                    tag: row.get(11)?,
-                })
-            })?
+            .query_map(params![limit], row_to_request)?
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(requests)
     }
 
-    /// Remove old completed/expired requests
+    /// Move non-critical requests older than `older_than_hours` to the
+    /// dead-letter table, and forget completions that old which nothing
+    /// queued depends on
     pub fn cleanup_old(&self, older_than_hours: u32) -> Result<u64, QueueError> {
         let conn = self.conn.lock().map_err(|e| QueueError::DatabaseError(e.to_string()))?;
-        let cutoff = Utc::now() - chrono::Duration::hours(older_than_hours as i64);
-        let rows = conn.execute(
-            "DELETE FROM request_queue WHERE created_at < ?1 AND priority < ?2",
-            params![cutoff.to_rfc3339(), Priority::Critical as i32],
+        let now = Utc::now();
+        let cutoff = (now - chrono::Duration::hours(older_than_hours as i64)).to_rfc3339();
+        let expired = Self::expire_deadlines(&conn, now)?;
+        let stale = dead_letter::bury(
+            &conn,
+            "created_at < ?1 AND priority < ?2 AND deadline_at IS NULL",
+            &[&cutoff, &(Priority::Critical as i32)],
+            DeadLetterReason::Expired,
+            &RequestFailure::default(),
         )?;
-        Ok(rows as u64)
-    }
+        conn.execute(
+            "DELETE FROM completed_requests
+             WHERE completed_at < ?1
+               AND id NOT IN (SELECT depends_on FROM request_queue WHERE depends_on IS NOT NULL)",
+            params![cutoff],
+        )?;
+        Ok(expired + stale)
+    }
 }
 
 #[cfg(test)]
@@ -440,9 +1473,39 @@ mod tests {
     fn test_priority_ordering() {
         let queue = create_test_queue();
 
-        queue.enqueue("GET", "https://low.com", "{}", None, Priority::Low, false, None).unwrap();
-        queue.enqueue("POST", "https://critical.com", "{}", Some("{\"amount\":100}"), Priority::Critical, false, None).unwrap();
-        queue.enqueue("GET", "https://normal.com", "{}", None, Priority::Normal, false, None).unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://low.com",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "POST",
+                "https://critical.com",
+                "{}",
+                Some(b"{\"amount\":100}".as_slice()),
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://normal.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
 
         // Should get Critical first
         let req = queue.dequeue(100).unwrap().unwrap();
@@ -527,8 +1590,28 @@ mod tests {
     fn test_list_pending() {
         let queue = create_test_queue();
 
-        queue.enqueue("GET", "https://a.com", "{}", None, Priority::Low, false, None).unwrap();
-        queue.enqueue("POST", "https://b.com", "{}", Some("body"), Priority::High, false, None).unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "POST",
+                "https://b.com",
+                "{}",
+                Some(b"body".as_slice()),
+                Priority::High,
+                false,
+                None,
+            )
+            .unwrap();
 
         let pending = queue.list_pending(10).unwrap();
         assert_eq!(pending.len(), 2);
@@ -536,6 +1619,1227 @@ mod tests {
         assert_eq!(pending[0].url, "https://b.com");
     }
 
+    #[test]
+    fn test_binary_body_roundtrip() {
+        let queue = create_test_queue();
+        let payload = vec![0x08u8, 0x96, 0x01, 0x00, 0xff];
+
+        queue
+            .enqueue(
+                "POST",
+                "https://api.com/proto",
+                "{}",
+                Some(&payload),
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let req = queue.dequeue(50).unwrap().unwrap();
+        assert_eq!(req.body, Some(payload));
+        assert!(req.body_text().is_none());
+    }
+
+    #[test]
+    fn test_idempotency_key_deduplicates() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            priority: Priority::Critical,
+            idempotency_key: Some("pay-123".to_string()),
+            ..EnqueueOptions::default()
+        };
+
+        let first = queue
+            .enqueue_with_options(
+                "POST",
+                "https://pay.com/charge",
+                "{}",
+                Some(b"{}".as_slice()),
+                &options,
+            )
+            .unwrap();
+        let second = queue
+            .enqueue_with_options(
+                "POST",
+                "https://pay.com/charge",
+                "{}",
+                Some(b"{}".as_slice()),
+                &options,
+            )
+            .unwrap();
+
+        assert!(!first.deduplicated);
+        assert!(second.deduplicated);
+        assert_eq!(first.id, second.id);
+        assert_eq!(queue.size().unwrap(), 1);
+
+        // Once sent, the key is free again
+        queue.complete(&first.id).unwrap();
+        let third = queue
+            .enqueue_with_options("POST", "https://pay.com/charge", "{}", None, &options)
+            .unwrap();
+        assert!(!third.deduplicated);
+    }
+
+    #[test]
+    fn test_idempotency_key_header() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            idempotency_key: Some("k1".to_string()),
+            ..EnqueueOptions::default()
+        };
+        queue
+            .enqueue_with_options(
+                "POST",
+                "https://a.com",
+                r#"{"Content-Type":"application/json"}"#,
+                None,
+                &options,
+            )
+            .unwrap();
+
+        let req = queue.dequeue(100).unwrap().unwrap();
+        let headers: serde_json::Value = serde_json::from_str(&req.headers_json).unwrap();
+        assert_eq!(headers["Idempotency-Key"], "k1");
+        assert_eq!(headers["Content-Type"], "application/json");
+        assert_eq!(req.idempotency_key.as_deref(), Some("k1"));
+
+        // A caller-supplied header wins
+        let options = EnqueueOptions {
+            idempotency_key: Some("k2".to_string()),
+            ..EnqueueOptions::default()
+        };
+        let id = queue
+            .enqueue_with_options(
+                "POST",
+                "https://b.com",
+                r#"{"idempotency-key":"custom"}"#,
+                None,
+                &options,
+            )
+            .unwrap()
+            .id;
+        let req = queue
+            .list_pending(10)
+            .unwrap()
+            .into_iter()
+            .find(|r| r.id == id)
+            .unwrap();
+        assert!(!req.headers_json.contains("k2"));
+    }
+
+    #[test]
+    fn test_coalesce_replace() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            coalesce: CoalescePolicy::Replace,
+            ..EnqueueOptions::default()
+        };
+
+        let first = queue
+            .enqueue_with_options(
+                "PUT",
+                "https://api.com/me",
+                "{}",
+                Some(b"{\"name\":\"A\"}".as_slice()),
+                &options,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "PUT",
+                "https://api.com/other",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+        let second = queue
+            .enqueue_with_options(
+                "PUT",
+                "https://api.com/me",
+                "{}",
+                Some(b"{\"name\":\"B\"}".as_slice()),
+                &options,
+            )
+            .unwrap();
+
+        assert_eq!(second.superseded, vec![first.id]);
+        assert_eq!(queue.size().unwrap(), 2);
+        let me = queue
+            .list_pending(10)
+            .unwrap()
+            .into_iter()
+            .find(|r| r.url == "https://api.com/me")
+            .unwrap();
+        assert_eq!(me.body_text(), Some("{\"name\":\"B\"}"));
+    }
+
+    #[test]
+    fn test_coalesce_merge_json() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            coalesce: CoalescePolicy::MergeJson,
+            coalesce_key: Some("profile".to_string()),
+            ..EnqueueOptions::default()
+        };
+
+        let first = queue
+            .enqueue_with_options(
+                "PATCH",
+                "https://api.com/me",
+                "{}",
+                Some(br#"{"name":"A","bio":"x","tz":"UTC"}"#.as_slice()),
+                &options,
+            )
+            .unwrap();
+        let second = queue
+            .enqueue_with_options(
+                "PATCH",
+                "https://api.com/me",
+                "{}",
+                Some(br#"{"name":"B","bio":null,"prefs":{"dark":true}}"#.as_slice()),
+                &options,
+            )
+            .unwrap();
+
+        assert!(second.merged);
+        assert_eq!(second.id, first.id);
+        assert_eq!(queue.size().unwrap(), 1);
+
+        let req = queue.dequeue(100).unwrap().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"name": "B", "tz": "UTC", "prefs": {"dark": true}})
+        );
+    }
+
+    #[test]
+    fn test_coalesce_merge_keeps_idempotency_key() {
+        let queue = create_test_queue();
+        let keyed = |key: &str| EnqueueOptions {
+            coalesce: CoalescePolicy::MergeJson,
+            idempotency_key: Some(key.to_string()),
+            ..EnqueueOptions::default()
+        };
+
+        queue
+            .enqueue_with_options(
+                "PATCH",
+                "https://a.com/me",
+                "{}",
+                Some(br#"{"a":1}"#.as_slice()),
+                &keyed("k1"),
+            )
+            .unwrap();
+        let outcome = queue
+            .enqueue_with_options(
+                "PATCH",
+                "https://a.com/me",
+                r#"{"X-Trace":"2"}"#,
+                Some(br#"{"b":2}"#.as_slice()),
+                &keyed("k2"),
+            )
+            .unwrap();
+        assert!(outcome.merged);
+
+        let req = queue.dequeue(100).unwrap().unwrap();
+        let headers: HashMap<String, String> = serde_json::from_str(&req.headers_json).unwrap();
+        assert_eq!(req.idempotency_key.as_deref(), Some("k1"));
+        assert_eq!(headers["Idempotency-Key"], "k1");
+        assert_eq!(headers["X-Trace"], "2");
+    }
+
+    #[test]
+    fn test_coalesce_merge_compressed_and_non_json() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            coalesce: CoalescePolicy::MergeJson,
+            compress: true,
+            ..EnqueueOptions::default()
+        };
+        let gz = |s: &str| optimization::compress(s.as_bytes()).unwrap();
+
+        queue
+            .enqueue_with_options(
+                "PATCH",
+                "https://a.com/x",
+                "{}",
+                Some(&gz(r#"{"a":1}"#)),
+                &options,
+            )
+            .unwrap();
+        let outcome = queue
+            .enqueue_with_options(
+                "PATCH",
+                "https://a.com/x",
+                "{}",
+                Some(&gz(r#"{"b":2}"#)),
+                &options,
+            )
+            .unwrap();
+        assert!(outcome.merged);
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert!(req.compress);
+        let body: serde_json::Value = serde_json::from_slice(
+            &optimization::decompress(req.body.as_deref().unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body, serde_json::json!({"a": 1, "b": 2}));
+
+        // Non-JSON bodies fall back to replacing
+        let options = EnqueueOptions {
+            coalesce: CoalescePolicy::MergeJson,
+            ..EnqueueOptions::default()
+        };
+        queue
+            .enqueue_with_options(
+                "PUT",
+                "https://a.com/raw",
+                "{}",
+                Some(b"one".as_slice()),
+                &options,
+            )
+            .unwrap();
+        let outcome = queue
+            .enqueue_with_options(
+                "PUT",
+                "https://a.com/raw",
+                "{}",
+                Some(b"two".as_slice()),
+                &options,
+            )
+            .unwrap();
+        assert!(!outcome.merged);
+        assert_eq!(outcome.superseded.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target = serde_json::json!({"a": {"b": 1, "c": 2}, "d": [1]});
+        merge_patch(
+            &mut target,
+            &serde_json::json!({"a": {"c": null, "e": 3}, "d": [2]}),
+        );
+        assert_eq!(target, serde_json::json!({"a": {"b": 1, "e": 3}, "d": [2]}));
+    }
+
+    #[test]
+    fn test_permanent_failure_dead_letters_immediately() {
+        let queue = create_test_queue();
+        let id = queue
+            .enqueue(
+                "POST",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let failure = RequestFailure {
+            status_code: Some(400),
+            ..RequestFailure::default()
+        };
+        assert!(!queue.fail_with(&id, &failure).unwrap());
+        let letter = queue.get_dead_letter(&id).unwrap().unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::PermanentFailure);
+
+        // 429 is a transient 4xx and is retried
+        let id = queue
+            .enqueue(
+                "POST",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+        let failure = RequestFailure {
+            status_code: Some(429),
+            ..RequestFailure::default()
+        };
+        assert!(queue.fail_with(&id, &failure).unwrap());
+    }
+
+    #[test]
+    fn test_retry_after_overrides_backoff() {
+        let queue = create_test_queue();
+        let id = queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::High,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let failure = RequestFailure {
+            status_code: Some(503),
+            error: None,
+            retry_after: Some("120".to_string()),
+        };
+        queue.fail_with(&id, &failure).unwrap();
+
+        let req = queue.list_pending(1).unwrap().remove(0);
+        let next = chrono::DateTime::parse_from_rfc3339(&req.next_attempt_at).unwrap();
+        let wait = (next.with_timezone(&Utc) - Utc::now()).num_seconds();
+        assert!((118..=120).contains(&wait));
+
+        // Retry-After on other statuses is ignored
+        let failure = RequestFailure {
+            status_code: Some(500),
+            error: None,
+            retry_after: Some("120".to_string()),
+        };
+        queue.fail_with(&id, &failure).unwrap();
+        let req = queue.list_pending(1).unwrap().remove(0);
+        let next = chrono::DateTime::parse_from_rfc3339(&req.next_attempt_at).unwrap();
+        assert!((next.with_timezone(&Utc) - Utc::now()).num_seconds() <= 8);
+    }
+
+    #[test]
+    fn test_per_priority_and_per_request_policies() {
+        let queue = create_test_queue();
+        queue
+            .set_retry_policy(
+                Priority::Normal,
+                RetryPolicy {
+                    max_attempts: Some(10),
+                    ..RetryPolicy::default()
+                },
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let options = EnqueueOptions {
+            retry_policy: Some(RetryPolicy {
+                strategy: BackoffStrategy::FullJitter,
+                max_attempts: Some(2),
+                max_elapsed_seconds: Some(3600),
+                ..RetryPolicy::default()
+            }),
+            ..EnqueueOptions::default()
+        };
+        let id = queue
+            .enqueue_with_options("GET", "https://b.com", "{}", None, &options)
+            .unwrap()
+            .id;
+
+        let pending = queue.list_pending(10).unwrap();
+        assert_eq!(
+            pending
+                .iter()
+                .find(|r| r.url == "https://a.com")
+                .unwrap()
+                .max_retries,
+            10
+        );
+        assert_eq!(pending.iter().find(|r| r.id == id).unwrap().max_retries, 2);
+
+        assert!(queue.fail(&id).unwrap());
+        assert!(!queue.fail(&id).unwrap());
+    }
+
+    #[test]
+    fn test_max_elapsed_gives_up() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            priority: Priority::Critical,
+            retry_policy: Some(RetryPolicy {
+                max_elapsed_seconds: Some(1),
+                ..RetryPolicy::default()
+            }),
+            ..EnqueueOptions::default()
+        };
+        let id = queue
+            .enqueue_with_options("POST", "https://a.com", "{}", None, &options)
+            .unwrap()
+            .id;
+
+        // The first backoff (4s) already overshoots the 1s budget
+        assert!(!queue.fail(&id).unwrap());
+        assert_eq!(queue.dead_letter_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_extreme_retry_policy() {
+        let queue = create_test_queue();
+        let extreme = RetryPolicy {
+            strategy: BackoffStrategy::FullJitter,
+            base_delay_ms: u64::MAX,
+            max_delay_ms: u64::MAX,
+            max_elapsed_seconds: Some(u64::MAX),
+            ..RetryPolicy::default()
+        };
+        assert!(matches!(
+            queue.set_retry_policy(Priority::Normal, extreme.clone()),
+            Err(QueueError::InvalidRetryPolicy(_))
+        ));
+        let options = EnqueueOptions {
+            retry_policy: Some(extreme.clone()),
+            ..EnqueueOptions::default()
+        };
+        assert!(matches!(
+            queue.enqueue_with_options("GET", "https://a.com", "{}", None, &options),
+            Err(QueueError::InvalidRetryPolicy(_))
+        ));
+
+        // A policy stored before validation existed is clamped, not trusted
+        let id = queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+        {
+            let conn = queue.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE request_queue SET retry_policy = ?1 WHERE id = ?2",
+                params![serde_json::to_string(&extreme).unwrap(), id],
+            )
+            .unwrap();
+        }
+        assert!(queue.fail(&id).unwrap());
+        let req = queue.list_pending(1).unwrap().remove(0);
+        let next = chrono::DateTime::parse_from_rfc3339(&req.next_attempt_at).unwrap();
+        assert!((next.with_timezone(&Utc) - Utc::now()).num_hours() <= 24);
+    }
+
+    #[test]
+    fn test_dequeue_leases_request() {
+        let queue = create_test_queue();
+        let id = queue
+            .enqueue(
+                "POST",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::High,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let first = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(first.id, id);
+        assert!(first.lease_token.is_some());
+        // A second worker doesn't get the same request
+        assert!(queue.dequeue(100).unwrap().is_none());
+
+        let token = first.lease_token.unwrap();
+        assert!(matches!(
+            queue.complete_leased(&id, "someone-else"),
+            Err(QueueError::LeaseMismatch(_))
+        ));
+        queue.complete_leased(&id, &token).unwrap();
+        assert_eq!(queue.size().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_expired_lease_returns_to_queue() {
+        let queue = create_test_queue();
+        let id = queue
+            .enqueue(
+                "POST",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::High,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let first = queue
+            .dequeue_with_lease(100, Duration::from_millis(0))
+            .unwrap()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        let second = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(second.id, id);
+        assert_ne!(second.lease_token, first.lease_token);
+        assert_eq!(second.retry_count, 1);
+
+        // The stale worker can no longer report back
+        let stale = first.lease_token.unwrap();
+        assert!(matches!(
+            queue.fail_leased(&id, &stale, &RequestFailure::default()),
+            Err(QueueError::LeaseMismatch(_))
+        ));
+
+        // Failing under the live lease releases it for the retry
+        assert!(
+            queue
+                .fail_leased(
+                    &id,
+                    &second.lease_token.unwrap(),
+                    &RequestFailure::default()
+                )
+                .unwrap()
+        );
+        let pending = queue.list_pending(1).unwrap().remove(0);
+        assert!(pending.lease_token.is_none());
+    }
+
+    #[test]
+    fn test_lapsed_leases_exhaust_retries() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            retry_policy: Some(RetryPolicy {
+                max_attempts: Some(2),
+                ..RetryPolicy::default()
+            }),
+            ..EnqueueOptions::default()
+        };
+        let id = queue
+            .enqueue_with_options("POST", "https://a.com/crash", "{}", None, &options)
+            .unwrap()
+            .id;
+
+        // A request that crashes the app every time it's sent
+        for _ in 0..2 {
+            assert!(
+                queue
+                    .dequeue_with_lease(100, Duration::from_millis(0))
+                    .unwrap()
+                    .is_some()
+            );
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(queue.dequeue(100).unwrap().is_none());
+        let letter = queue.get_dead_letter(&id).unwrap().unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::RetriesExhausted);
+        assert_eq!(letter.attempt_history.len(), 2);
+    }
+
+    #[test]
+    fn test_dequeue_batch_respects_budget_and_quality() {
+        let queue = create_test_queue();
+        let event = vec![b'e'; 1000];
+        for _ in 0..5 {
+            queue
+                .enqueue(
+                    "POST",
+                    "https://a.com/e",
+                    "{}",
+                    Some(&event),
+                    Priority::Normal,
+                    false,
+                    None,
+                )
+                .unwrap();
+        }
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com/low",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+
+        // Quality 30 excludes Low; ~3 events fit in 3.1 KB
+        let batch = queue
+            .dequeue_batch(
+                &SendConditions::with_quality(30),
+                10,
+                3_100,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(batch.len(), 3);
+        assert!(batch.iter().all(|r| r.lease_token.is_some()));
+
+        // Leased requests aren't handed out again
+        let rest = queue
+            .dequeue_batch(
+                &SendConditions::with_quality(30),
+                10,
+                1_000_000,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(rest.len(), 2);
+        assert!(
+            queue
+                .dequeue_batch(
+                    &SendConditions::with_quality(0),
+                    10,
+                    1_000_000,
+                    Duration::from_secs(60)
+                )
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_ordering_group_is_fifo() {
+        let queue = create_test_queue();
+        let lane = EnqueueOptions {
+            ordering_group: Some("order-42".to_string()),
+            ..EnqueueOptions::default()
+        };
+        let create = queue
+            .enqueue_with_options("POST", "https://a.com/orders", "{}", None, &lane)
+            .unwrap()
+            .id;
+        let high = EnqueueOptions {
+            priority: Priority::High,
+            ..lane.clone()
+        };
+        let photo = queue
+            .enqueue_with_options("POST", "https://a.com/photos", "{}", None, &high)
+            .unwrap()
+            .id;
+
+        // The later, higher-priority request waits for the head of its lane
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(req.id, create);
+        assert!(queue.dequeue(100).unwrap().is_none());
+
+        // A retry backoff on the head still holds the lane
+        queue
+            .fail_leased(
+                &create,
+                req.lease_token.as_deref().unwrap(),
+                &RequestFailure::default(),
+            )
+            .unwrap();
+        assert!(queue.dequeue(100).unwrap().is_none());
+        assert!(
+            queue
+                .dequeue_batch(
+                    &SendConditions::with_quality(100),
+                    10,
+                    1_000_000,
+                    Duration::from_secs(60)
+                )
+                .unwrap()
+                .is_empty()
+        );
+
+        queue.complete(&create).unwrap();
+        assert_eq!(queue.dequeue(100).unwrap().unwrap().id, photo);
+    }
+
+    #[test]
+    fn test_depends_on_waits_for_parent() {
+        let queue = create_test_queue();
+        let parent = queue
+            .enqueue(
+                "POST",
+                "https://a.com/orders",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        let options = EnqueueOptions {
+            priority: Priority::Critical,
+            depends_on: Some(parent.clone()),
+            ..EnqueueOptions::default()
+        };
+        let child = queue
+            .enqueue_with_options("POST", "https://a.com/photos", "{}", None, &options)
+            .unwrap()
+            .id;
+
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(req.id, parent);
+        assert!(queue.dequeue(100).unwrap().is_none());
+
+        queue
+            .complete_leased(&parent, req.lease_token.as_deref().unwrap())
+            .unwrap();
+        assert_eq!(queue.dequeue(100).unwrap().unwrap().id, child);
+    }
+
+    #[test]
+    fn test_depends_on_needs_completion() {
+        let queue = create_test_queue();
+        let child_of = |parent: &str| EnqueueOptions {
+            depends_on: Some(parent.to_string()),
+            ..EnqueueOptions::default()
+        };
+
+        // Cancelling the parent takes its dependents down with it
+        let parent = queue
+            .enqueue(
+                "POST",
+                "https://a.com/orders",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        let child = queue
+            .enqueue_with_options(
+                "POST",
+                "https://a.com/photos",
+                "{}",
+                None,
+                &child_of(&parent),
+            )
+            .unwrap()
+            .id;
+        assert!(queue.cancel(&parent).unwrap());
+        assert!(queue.dequeue(100).unwrap().is_none());
+        let letter = queue.get_dead_letter(&child).unwrap().unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::DependencyFailed);
+        assert!(matches!(
+            queue.enqueue_with_options(
+                "POST",
+                "https://a.com/late",
+                "{}",
+                None,
+                &child_of(&parent)
+            ),
+            Err(QueueError::DependencyFailed(_))
+        ));
+
+        // A coalesced parent hands its dependents to the request replacing it
+        let replace = EnqueueOptions {
+            coalesce: CoalescePolicy::Replace,
+            ..EnqueueOptions::default()
+        };
+        let old = queue
+            .enqueue_with_options("PUT", "https://a.com/me", "{}", None, &replace)
+            .unwrap()
+            .id;
+        let child = queue
+            .enqueue_with_options("POST", "https://a.com/avatar", "{}", None, &child_of(&old))
+            .unwrap()
+            .id;
+        let new = queue
+            .enqueue_with_options("PUT", "https://a.com/me", "{}", None, &replace)
+            .unwrap()
+            .id;
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(req.id, new);
+        assert!(queue.dequeue(100).unwrap().is_none());
+        queue.complete(&new).unwrap();
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(req.id, child);
+        assert_eq!(req.depends_on.as_deref(), Some(new.as_str()));
+
+        // Depending on a completed request is fine
+        queue.complete(&child).unwrap();
+        let late = queue
+            .enqueue_with_options("GET", "https://a.com/done", "{}", None, &child_of(&child))
+            .unwrap()
+            .id;
+        assert_eq!(queue.dequeue(100).unwrap().unwrap().id, late);
+    }
+
+    #[test]
+    fn test_constraints_hold_until_met() {
+        use crate::connectivity::{ConnectionType, NetworkStatus, PowerState};
+
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            priority: Priority::High,
+            constraints: Some(RequestConstraints {
+                unmetered: true,
+                ..RequestConstraints::default()
+            }),
+            ..EnqueueOptions::default()
+        };
+        let upload = queue
+            .enqueue_with_options("PUT", "https://a.com/video", "{}", None, &options)
+            .unwrap()
+            .id;
+        let ping = queue
+            .enqueue(
+                "GET",
+                "https://a.com/ping",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        // On cellular the constrained upload is skipped, not blocking the rest
+        let cellular = SendConditions::new(
+            &NetworkStatus::from_connection_type(ConnectionType::Cellular4G),
+            None,
+        );
+        let lease = Duration::from_secs(60);
+        assert_eq!(
+            queue.dequeue_when(&cellular, lease).unwrap().unwrap().id,
+            ping
+        );
+        assert!(queue.dequeue_when(&cellular, lease).unwrap().is_none());
+        assert!(queue.dequeue(100).unwrap().is_none());
+
+        let wifi = SendConditions::new(
+            &NetworkStatus::from_connection_type(ConnectionType::WiFi),
+            Some(PowerState::default()),
+        );
+        let req = queue.dequeue_when(&wifi, lease).unwrap().unwrap();
+        assert_eq!(req.id, upload);
+        assert!(req.constraints.unwrap().unmetered);
+    }
+
+    #[test]
+    fn test_deadline_expires_request() {
+        let queue = create_test_queue();
+        let past = EnqueueOptions {
+            deadline_at: Some((Utc::now() - chrono::Duration::seconds(1)).to_rfc3339()),
+            ..EnqueueOptions::default()
+        };
+        let stale = queue
+            .enqueue_with_options("POST", "https://a.com/stale", "{}", None, &past)
+            .unwrap()
+            .id;
+        let future = EnqueueOptions {
+            deadline_at: Some("2999-01-01T00:00:00+05:30".to_string()),
+            ..EnqueueOptions::default()
+        };
+        let fresh = queue
+            .enqueue_with_options("POST", "https://a.com/fresh", "{}", None, &future)
+            .unwrap()
+            .id;
+
+        let req = queue.dequeue(100).unwrap().unwrap();
+        assert_eq!(req.id, fresh);
+        assert_eq!(
+            req.deadline_at.as_deref(),
+            Some("2998-12-31T18:30:00+00:00")
+        );
+        let letter = queue.get_dead_letter(&stale).unwrap().unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::DeadlineExceeded);
+
+        // A request with its own deadline outlives the age-based sweep
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(queue.cleanup_old(0).unwrap(), 0);
+
+        let bad = EnqueueOptions {
+            deadline_at: Some("tomorrow".to_string()),
+            ..EnqueueOptions::default()
+        };
+        assert!(
+            queue
+                .enqueue_with_options("POST", "https://a.com", "{}", None, &bad)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_retry_past_deadline_is_dead_lettered() {
+        let queue = create_test_queue();
+        let options = EnqueueOptions {
+            priority: Priority::Critical,
+            deadline_at: Some((Utc::now() + chrono::Duration::seconds(1)).to_rfc3339()),
+            ..EnqueueOptions::default()
+        };
+        let id = queue
+            .enqueue_with_options("POST", "https://a.com", "{}", None, &options)
+            .unwrap()
+            .id;
+
+        // The first backoff (4s) overshoots the deadline
+        assert!(!queue.fail(&id).unwrap());
+        assert_eq!(
+            queue.get_dead_letter(&id).unwrap().unwrap().reason,
+            DeadLetterReason::DeadlineExceeded
+        );
+    }
+
+    #[test]
+    fn test_aging_lets_low_priority_through() {
+        let queue = create_test_queue();
+        let telemetry = queue
+            .enqueue(
+                "POST",
+                "https://a.com/t",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+        let hour_ago = (Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+        queue
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE request_queue SET created_at = ?1 WHERE id = ?2",
+                params![hour_ago, telemetry],
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://a.com/n",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        // A zero lease leaves requests eligible for the next peek
+        let peek = |quality| {
+            queue
+                .dequeue_batch(
+                    &SendConditions::with_quality(quality),
+                    1,
+                    u64::MAX,
+                    Duration::ZERO,
+                )
+                .unwrap()[0]
+                .id
+                .clone()
+        };
+        assert_ne!(peek(100), telemetry); // Strict priority: Normal first
+
+        queue.set_scheduler(SchedulerConfig {
+            aging_interval_seconds: Some(1800),
+            ..SchedulerConfig::default()
+        });
+        assert_eq!(queue.scheduler().aging_interval_seconds, Some(1800));
+        // An hour in queue lifts Low to High, ahead of the fresh Normal,
+        // but Low's own quality threshold still applies
+        assert_ne!(peek(30), telemetry);
+        assert_eq!(peek(100), telemetry);
+    }
+
+    #[test]
+    fn test_batch_round_robin_across_windows() {
+        let queue = create_test_queue();
+        for i in 0..8 {
+            queue
+                .enqueue(
+                    "GET",
+                    &format!("https://a.com/n{}", i),
+                    "{}",
+                    None,
+                    Priority::Normal,
+                    false,
+                    None,
+                )
+                .unwrap();
+        }
+        for i in 0..4 {
+            queue
+                .enqueue(
+                    "GET",
+                    &format!("https://a.com/l{}", i),
+                    "{}",
+                    None,
+                    Priority::Low,
+                    false,
+                    None,
+                )
+                .unwrap();
+        }
+        queue.set_scheduler(SchedulerConfig {
+            weights: HashMap::from([(Priority::Normal, 3), (Priority::Low, 1)]),
+            ..SchedulerConfig::default()
+        });
+
+        let batch = queue
+            .dequeue_batch(
+                &SendConditions::with_quality(100),
+                4,
+                u64::MAX,
+                Duration::ZERO,
+            )
+            .unwrap();
+        let urls: Vec<&str> = batch.iter().map(|r| r.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://a.com/n0",
+                "https://a.com/l0",
+                "https://a.com/n1",
+                "https://a.com/n2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enqueue_sheds_over_limits() {
+        let queue = create_test_queue();
+        queue.set_limits(QueueLimits {
+            overall: CapacityLimit {
+                max_requests: Some(3),
+                max_bytes: None,
+            },
+            per_tag: HashMap::from([(
+                "analytics".to_string(),
+                CapacityLimit {
+                    max_requests: None,
+                    max_bytes: Some(100),
+                },
+            )]),
+        });
+        let analytics = EnqueueOptions {
+            priority: Priority::Low,
+            tag: Some("analytics".to_string()),
+            ..EnqueueOptions::default()
+        };
+        // Each event is 20 (URL) + 2 (headers) + 40 (body) = 62 bytes
+        let body = [b'e'; 40];
+        let first = queue
+            .enqueue_with_options(
+                "POST",
+                "https://a.com/events",
+                "{}",
+                Some(&body),
+                &analytics,
+            )
+            .unwrap();
+        assert!(first.shed.is_empty());
+        let second = queue
+            .enqueue_with_options(
+                "POST",
+                "https://a.com/events",
+                "{}",
+                Some(&body),
+                &analytics,
+            )
+            .unwrap();
+        assert_eq!(second.shed, vec![first.id.clone()]);
+
+        let pay = queue
+            .enqueue(
+                "POST",
+                "https://pay.com",
+                "{}",
+                None,
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com/n",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+        // Over the overall cap: the Low event goes before Normal
+        let high_options = EnqueueOptions {
+            priority: Priority::High,
+            ..EnqueueOptions::default()
+        };
+        let high = queue
+            .enqueue_with_options("POST", "https://a.com/h", "{}", None, &high_options)
+            .unwrap();
+        assert_eq!(high.shed, vec![second.id]);
+        assert_eq!(queue.size().unwrap(), 3);
+
+        // A new Low request that doesn't fit is shed itself; Critical never is
+        let low_options = EnqueueOptions {
+            priority: Priority::Low,
+            ..EnqueueOptions::default()
+        };
+        let late = queue
+            .enqueue_with_options("POST", "https://a.com/l", "{}", None, &low_options)
+            .unwrap();
+        assert_eq!(late.shed, vec![late.id.clone()]);
+        for _ in 0..3 {
+            queue
+                .enqueue(
+                    "POST",
+                    "https://pay.com",
+                    "{}",
+                    None,
+                    Priority::Critical,
+                    false,
+                    None,
+                )
+                .unwrap();
+        }
+        assert_eq!(queue.size_by_priority(Priority::Critical).unwrap(), 4);
+        assert!(queue.list_pending(10).unwrap().iter().any(|r| r.id == pay));
+    }
+
+    #[test]
+    fn test_dequeue_batch_oversized_first_request() {
+        let queue = create_test_queue();
+        let big = vec![b'x'; 10_000];
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com/big",
+                "{}",
+                Some(&big),
+                Priority::High,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com/small",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let batch = queue
+            .dequeue_batch(
+                &SendConditions::with_quality(100),
+                10,
+                100,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].url, "https://a.com/big");
+    }
+
     #[test]
     fn test_size_by_priority() {
         let queue = create_test_queue();
@@ -0,0 +1,189 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::cache::parse_http_date;
+
+/// Longest `Retry-After` the queue will honor
+const MAX_RETRY_AFTER_SECONDS: u64 = 24 * 60 * 60;
+
+/// Longest single delay a policy may ask for
+pub const MAX_RETRY_DELAY_MS: u64 = MAX_RETRY_AFTER_SECONDS * 1000;
+
+/// How the delay before the next attempt grows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackoffStrategy {
+    /// `base * 2^attempt`, no randomness
+    Exponential,
+    /// Uniformly random between 0 and `base * 2^attempt`
+    FullJitter,
+    /// Uniformly random between `base` and 3× the previous delay
+    DecorrelatedJitter,
+}
+
+/// When and how often a failed request is retried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub strategy: BackoffStrategy,
+    pub base_delay_ms: u64,
+    /// Upper bound for a single delay
+    pub max_delay_ms: u64,
+    /// Total attempts allowed (None = the priority's `max_retries`)
+    pub max_attempts: Option<u32>,
+    /// Give up once this long has passed since the request was queued
+    pub max_elapsed_seconds: Option<u64>,
+}
+
+impl Default for RetryPolicy {
+    /// The historical schedule: `2s * 2^attempt`, capped at 5 minutes
+    fn default() -> Self {
+        RetryPolicy {
+            strategy: BackoffStrategy::Exponential,
+            base_delay_ms: 2_000,
+            max_delay_ms: 300_000,
+            max_attempts: None,
+            max_elapsed_seconds: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Reject delays above `MAX_RETRY_DELAY_MS`
+    pub fn validate(&self) -> Result<(), String> {
+        if self.base_delay_ms > MAX_RETRY_DELAY_MS || self.max_delay_ms > MAX_RETRY_DELAY_MS {
+            return Err(format!("delays must not exceed {} ms", MAX_RETRY_DELAY_MS));
+        }
+        Ok(())
+    }
+
+    /// Delay before retry number `attempt` (1-based). `previous_delay_ms` is
+    /// the last delay used, for decorrelated jitter.
+    /// Policies stored before validation existed are clamped to `MAX_RETRY_DELAY_MS`.
+    pub fn delay_ms(&self, attempt: u32, previous_delay_ms: u64) -> u64 {
+        let base = self.base_delay_ms.min(MAX_RETRY_DELAY_MS);
+        let max = self.max_delay_ms.min(MAX_RETRY_DELAY_MS);
+        let exponential = base.saturating_mul(2u64.saturating_pow(attempt)).min(max);
+        match self.strategy {
+            BackoffStrategy::Exponential => exponential,
+            BackoffStrategy::FullJitter => random_between(0, exponential),
+            BackoffStrategy::DecorrelatedJitter => {
+                let upper = previous_delay_ms.max(base).saturating_mul(3);
+                random_between(base, upper).min(max)
+            }
+        }
+    }
+}
+
+/// Uniform random value in `low..=high`
+fn random_between(low: u64, high: u64) -> u64 {
+    if high <= low {
+        return low;
+    }
+    let span = (high - low) as u128 + 1;
+    let random = Uuid::new_v4().as_u128() >> 64;
+    low + (random % span) as u64
+}
+
+/// Whether a status means retrying can't help: 4xx other than
+/// 408 Request Timeout and 429 Too Many Requests
+pub fn is_permanent_status(status_code: u16) -> bool {
+    (400..500).contains(&status_code) && status_code != 408 && status_code != 429
+}
+
+/// Delay a `Retry-After` value asks for (delta-seconds or HTTP-date)
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<u64> {
+    let value = value.trim();
+    let seconds = match value.parse::<u64>() {
+        Ok(s) => s,
+        Err(_) => {
+            let at = parse_http_date(value)?;
+            (at - now).num_seconds().max(0) as u64
+        }
+    };
+    Some(seconds.min(MAX_RETRY_AFTER_SECONDS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_historical_schedule() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay_ms(1, 0), 4_000);
+        assert_eq!(policy.delay_ms(2, 0), 8_000);
+        assert_eq!(policy.delay_ms(10, 0), 300_000);
+        assert_eq!(policy.delay_ms(200, 0), 300_000); // no overflow
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        let full = RetryPolicy {
+            strategy: BackoffStrategy::FullJitter,
+            ..RetryPolicy::default()
+        };
+        let decorrelated = RetryPolicy {
+            strategy: BackoffStrategy::DecorrelatedJitter,
+            max_delay_ms: 20_000,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            assert!(full.delay_ms(3, 0) <= 16_000);
+            let d = decorrelated.delay_ms(3, 5_000);
+            assert!((2_000..=15_000).contains(&d));
+        }
+    }
+
+    #[test]
+    fn test_extreme_policy() {
+        let extreme = RetryPolicy {
+            strategy: BackoffStrategy::FullJitter,
+            base_delay_ms: u64::MAX,
+            max_delay_ms: u64::MAX,
+            ..RetryPolicy::default()
+        };
+        assert!(extreme.validate().is_err());
+        assert!(RetryPolicy::default().validate().is_ok());
+        for strategy in [
+            BackoffStrategy::FullJitter,
+            BackoffStrategy::DecorrelatedJitter,
+        ] {
+            let policy = RetryPolicy {
+                strategy,
+                ..extreme.clone()
+            };
+            assert!(policy.delay_ms(64, u64::MAX) <= MAX_RETRY_DELAY_MS);
+        }
+        random_between(0, u64::MAX);
+        assert!(random_between(u64::MAX - 1, u64::MAX) >= u64::MAX - 1);
+    }
+
+    #[test]
+    fn test_permanent_status() {
+        assert!(is_permanent_status(400));
+        assert!(is_permanent_status(422));
+        assert!(!is_permanent_status(408));
+        assert!(!is_permanent_status(429));
+        assert!(!is_permanent_status(503));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = parse_http_date("Sat, 11 Jan 2025 10:00:00 GMT").unwrap();
+        assert_eq!(parse_retry_after("120", now), Some(120));
+        assert_eq!(
+            parse_retry_after("Sat, 11 Jan 2025 10:01:30 GMT", now),
+            Some(90)
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 10 Jan 2025 10:00:00 GMT", now),
+            Some(0)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(
+            parse_retry_after("9999999", now),
+            Some(MAX_RETRY_AFTER_SECONDS)
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use super::Priority;
+
+const PRIORITIES: [Priority; 4] = [
+    Priority::Low,
+    Priority::Normal,
+    Priority::High,
+    Priority::Critical,
+];
+
+/// How `RequestQueue` orders eligible requests of different priorities.
+/// The default is strict priority order with no aging.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// Time in queue that raises a request's effective priority by one
+    /// level (None = no aging). Only ordering changes; the quality threshold
+    /// of the real priority still applies.
+    pub aging_interval_seconds: Option<u64>,
+    /// Aging never raises a request above this
+    pub max_aged_priority: Priority,
+    /// Weighted round-robin shares by effective priority: of every
+    /// `sum(weights)` dequeues, a priority with work gets about its weight.
+    /// Empty means strict priority order; missing priorities count as 1.
+    pub weights: HashMap<Priority, u32>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            aging_interval_seconds: None,
+            max_aged_priority: Priority::High,
+            weights: HashMap::new(),
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// `priority` raised one level per `aging_interval_seconds` since `created_at`
+    pub fn effective_priority(
+        &self,
+        priority: Priority,
+        created_at: &str,
+        now: DateTime<Utc>,
+    ) -> Priority {
+        let interval = match self.aging_interval_seconds {
+            Some(s) if s > 0 => s as i64,
+            _ => return priority,
+        };
+        if priority >= self.max_aged_priority {
+            return priority;
+        }
+        let age = DateTime::parse_from_rfc3339(created_at)
+            .map(|c| (now - c.with_timezone(&Utc)).num_seconds().max(0))
+            .unwrap_or(0);
+        let level = (priority as i64 + age / interval).min(self.max_aged_priority as i64);
+        Priority::from_i32(level as i32)
+    }
+
+    /// Ranges of `created_at` in which requests of `priority` share one
+    /// effective priority, highest first. One unbounded range without aging.
+    pub(super) fn aging_windows(&self, priority: Priority, now: DateTime<Utc>) -> Vec<AgingWindow> {
+        let interval = match self.aging_interval_seconds {
+            Some(s) if s > 0 && priority < self.max_aged_priority => s as i64,
+            _ => return vec![(None, None)],
+        };
+        let steps = self.max_aged_priority as i64 - priority as i64;
+        (0..=steps)
+            .rev()
+            .map(|k| {
+                let newest = (k > 0).then(|| now - chrono::Duration::seconds(k * interval));
+                let oldest =
+                    (k < steps).then(|| now - chrono::Duration::seconds((k + 1) * interval));
+                (newest, oldest)
+            })
+            .collect()
+    }
+
+    fn weight(&self, priority: Priority) -> i64 {
+        self.weights.get(&priority).copied().unwrap_or(1).max(1) as i64
+    }
+}
+
+/// `created_at` bounds: (created no later than, created after)
+pub(super) type AgingWindow = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// What the scheduler needs to know about an eligible request
+#[derive(Debug, Clone)]
+pub(super) struct Candidate {
+    pub(super) id: String,
+    pub(super) priority: Priority,
+    pub(super) created_at: String,
+}
+
+/// Scheduler config plus smooth weighted round-robin credit, kept across
+/// dequeues
+#[derive(Debug, Default)]
+pub(super) struct Scheduler {
+    pub(super) config: SchedulerConfig,
+    credit: [i64; 4],
+}
+
+impl Scheduler {
+    pub(super) fn new(config: SchedulerConfig) -> Self {
+        Scheduler {
+            config,
+            credit: [0; 4],
+        }
+    }
+
+    /// Pick up to `limit` of `candidates` in the order they should be sent
+    pub(super) fn order(
+        &mut self,
+        candidates: Vec<Candidate>,
+        limit: usize,
+        now: DateTime<Utc>,
+    ) -> Vec<Candidate> {
+        let mut classes: [Vec<Candidate>; 4] = Default::default();
+        for candidate in candidates {
+            let priority =
+                self.config
+                    .effective_priority(candidate.priority, &candidate.created_at, now);
+            classes[priority as usize].push(candidate);
+        }
+        // Aged requests compete with native ones oldest first
+        let mut classes: [VecDeque<Candidate>; 4] = classes.map(|mut class| {
+            class.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+            class.into()
+        });
+
+        let mut picked = Vec::new();
+        while picked.len() < limit {
+            let class = if self.config.weights.is_empty() {
+                (0..4).rev().find(|&c| !classes[c].is_empty())
+            } else {
+                self.next_weighted(&classes)
+            };
+            match class.and_then(|c| classes[c].pop_front()) {
+                Some(req) => picked.push(req),
+                None => break,
+            }
+        }
+        picked
+    }
+
+    /// Smooth weighted round-robin over the classes that have work
+    fn next_weighted(&mut self, classes: &[VecDeque<Candidate>; 4]) -> Option<usize> {
+        let active: Vec<usize> = (0..4).filter(|&c| !classes[c].is_empty()).collect();
+        let total: i64 = active
+            .iter()
+            .map(|&c| self.config.weight(PRIORITIES[c]))
+            .sum();
+        for (credit, class) in self.credit.iter_mut().zip(classes) {
+            if class.is_empty() {
+                *credit = 0; // No banking credit while idle
+            }
+        }
+        for &c in &active {
+            self.credit[c] += self.config.weight(PRIORITIES[c]);
+        }
+        // Ties go to the higher priority
+        let best = active.into_iter().rev().max_by_key(|&c| self.credit[c])?;
+        self.credit[best] -= total;
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(priority: Priority, created_at: &str) -> Candidate {
+        Candidate {
+            id: format!("{:?}-{}", priority, created_at),
+            priority,
+            created_at: created_at.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_effective_priority() {
+        let config = SchedulerConfig {
+            aging_interval_seconds: Some(600),
+            ..SchedulerConfig::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2025-01-01T01:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let created = "2025-01-01T00:45:00Z";
+        assert_eq!(
+            config.effective_priority(Priority::Low, created, now),
+            Priority::Normal
+        );
+        assert_eq!(
+            config.effective_priority(Priority::Low, "2025-01-01T00:00:00Z", now),
+            Priority::High
+        );
+        assert_eq!(
+            config.effective_priority(Priority::Critical, created, now),
+            Priority::Critical
+        );
+        assert_eq!(
+            SchedulerConfig::default().effective_priority(
+                Priority::Low,
+                "2000-01-01T00:00:00Z",
+                now
+            ),
+            Priority::Low
+        );
+    }
+
+    #[test]
+    fn test_aging_windows() {
+        let config = SchedulerConfig {
+            aging_interval_seconds: Some(600),
+            ..SchedulerConfig::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2025-01-01T01:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let at = |s: &str| Some(DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc));
+
+        let windows = config.aging_windows(Priority::Low, now);
+        assert_eq!(
+            windows,
+            vec![
+                (at("2025-01-01T00:40:00Z"), None),
+                (at("2025-01-01T00:50:00Z"), at("2025-01-01T00:40:00Z")),
+                (None, at("2025-01-01T00:50:00Z")),
+            ]
+        );
+        // Each window holds exactly one effective priority
+        for ((newest, oldest), expected) in
+            windows
+                .into_iter()
+                .zip([Priority::High, Priority::Normal, Priority::Low])
+        {
+            for bound in [newest, oldest.map(|o| o + chrono::Duration::seconds(1))]
+                .into_iter()
+                .flatten()
+            {
+                assert_eq!(
+                    config.effective_priority(Priority::Low, &bound.to_rfc3339(), now),
+                    expected
+                );
+            }
+        }
+
+        assert_eq!(
+            config.aging_windows(Priority::High, now),
+            vec![(None, None)]
+        );
+        assert_eq!(
+            SchedulerConfig::default().aging_windows(Priority::Low, now),
+            vec![(None, None)]
+        );
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let mut scheduler = Scheduler::new(SchedulerConfig {
+            weights: HashMap::from([(Priority::Normal, 3), (Priority::Low, 1)]),
+            ..SchedulerConfig::default()
+        });
+        let requests: Vec<_> = (0..8)
+            .map(|i| request(Priority::Normal, &format!("2025-01-01T00:00:0{}Z", i)))
+            .chain((0..4).map(|i| request(Priority::Low, &format!("2025-01-01T00:00:0{}Z", i))))
+            .collect();
+
+        let order = scheduler.order(requests, 8, Utc::now());
+        let lows = order.iter().filter(|r| r.priority == Priority::Low).count();
+        assert_eq!(lows, 2);
+        assert_eq!(order[0].priority, Priority::Normal);
+    }
+}
@@ -0,0 +1,261 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::queue::{Priority, QueuedRequest};
+use crate::transport::{Transport, TransportError};
+
+/// Whether the internet is actually reachable, as opposed to the platform
+/// merely reporting a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reachability {
+    /// Not probed on this connection yet
+    #[default]
+    Unknown,
+    /// The probe got the expected response
+    Reachable,
+    /// Something answered, but not with the expected response (typically a
+    /// login page or redirect on hotel / airport Wi-Fi)
+    CaptivePortal,
+    /// The probe host name didn't resolve
+    DnsFailure,
+    /// Connecting failed or timed out
+    NoRoute,
+}
+
+/// The request used to check reachability and what it should return
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbeConfig {
+    pub url: String,
+    pub expected_status: u16,
+    /// Exact body to expect, if any (compared after trimming whitespace)
+    pub expected_body: Option<String>,
+    pub timeout_ms: u64,
+}
+
+impl Default for ProbeConfig {
+    /// A generate_204 endpoint, as used by Android's own connectivity check
+    fn default() -> Self {
+        ProbeConfig {
+            url: "https://connectivitycheck.gstatic.com/generate_204".to_string(),
+            expected_status: 204,
+            expected_body: None,
+            timeout_ms: 5_000,
+        }
+    }
+}
+
+/// Outcome of one probe
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub reachability: Reachability,
+    /// Status the probe URL answered with, if it answered
+    pub status_code: Option<u16>,
+    /// Round trip of a successful probe
+    pub latency_ms: Option<u64>,
+    /// Transport error, if the probe didn't get a response
+    pub error: Option<String>,
+}
+
+/// GET request for the probe URL, never cached along the way
+fn probe_request(url: &str) -> QueuedRequest {
+    let now = Utc::now().to_rfc3339();
+    QueuedRequest {
+        id: format!("reachability-{}", uuid::Uuid::new_v4()),
+        method: "GET".to_string(),
+        url: url.to_string(),
+        headers_json: r#"{"Cache-Control":"no-cache"}"#.to_string(),
+        body: None,
+        priority: Priority::Critical as i32,
+        retry_count: 0,
+        max_retries: 0,
+        created_at: now.clone(),
+        next_attempt_at: now,
+        compress: false,
+        tag: None,
+        idempotency_key: None,
+        lease_token: None,
+        lease_expires_at: None,
+        ordering_group: None,
+        depends_on: None,
+        deadline_at: None,
+        constraints: None,
+    }
+}
+
+/// Send the probe through `transport` and classify the outcome
+pub fn probe(transport: &dyn Transport, config: &ProbeConfig) -> ProbeResult {
+    let request = probe_request(&config.url);
+    match transport.send(&request, Duration::from_millis(config.timeout_ms)) {
+        Ok(response) => {
+            let body_matches = config.expected_body.as_deref().is_none_or(|expected| {
+                std::str::from_utf8(&response.body).is_ok_and(|b| b.trim() == expected.trim())
+            });
+            let reachable = response.status_code == config.expected_status && body_matches;
+            ProbeResult {
+                reachability: if reachable {
+                    Reachability::Reachable
+                } else {
+                    Reachability::CaptivePortal
+                },
+                status_code: Some(response.status_code),
+                latency_ms: reachable.then_some(response.duration_ms),
+                error: None,
+            }
+        }
+        Err(e) => ProbeResult {
+            reachability: match e {
+                TransportError::Dns(_) => Reachability::DnsFailure,
+                TransportError::Timeout
+                | TransportError::Connection(_)
+                | TransportError::Other(_) => Reachability::NoRoute,
+            },
+            status_code: None,
+            latency_ms: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportResponse;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+    use std::thread;
+
+    /// Minimal HTTP/1.1 client over std, standing in for a platform client
+    struct StdTransport;
+
+    impl Transport for StdTransport {
+        fn send(
+            &self,
+            request: &QueuedRequest,
+            timeout: Duration,
+        ) -> Result<TransportResponse, TransportError> {
+            let rest = request
+                .url
+                .strip_prefix("http://")
+                .ok_or(TransportError::Other("http only".into()))?;
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let addr = authority
+                .to_socket_addrs()
+                .map_err(|e| TransportError::Dns(e.to_string()))?
+                .next()
+                .ok_or_else(|| TransportError::Dns(authority.to_string()))?;
+
+            let started = std::time::Instant::now();
+            let mut stream = TcpStream::connect_timeout(&addr, timeout)
+                .map_err(|e| TransportError::Connection(e.to_string()))?;
+            stream.set_read_timeout(Some(timeout)).ok();
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                path, authority
+            )
+            .map_err(|e| TransportError::Connection(e.to_string()))?;
+            let mut raw = Vec::new();
+            stream
+                .read_to_end(&mut raw)
+                .map_err(|e| TransportError::Connection(e.to_string()))?;
+
+            let text = String::from_utf8_lossy(&raw);
+            let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+            let status_code = head
+                .split_whitespace()
+                .nth(1)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0);
+            Ok(TransportResponse {
+                status_code,
+                headers_json: "{}".to_string(),
+                body: body.as_bytes().to_vec(),
+                duration_ms: started.elapsed().as_millis() as u64,
+            })
+        }
+    }
+
+    /// Serve one canned response on a local port, returning its base URL
+    fn stub(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                // Read the whole request first: closing with unread input
+                // resets the connection before the client sees the response
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn config(url: String) -> ProbeConfig {
+        ProbeConfig {
+            url,
+            timeout_ms: 2_000,
+            ..ProbeConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_reachable() {
+        let url = stub("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n");
+        let result = probe(&StdTransport, &config(format!("{}/generate_204", url)));
+        assert_eq!(result.reachability, Reachability::Reachable);
+        assert_eq!(result.status_code, Some(204));
+        assert!(result.latency_ms.is_some());
+    }
+
+    #[test]
+    fn test_captive_portal() {
+        let url =
+            stub("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<html>Hotel login</html>");
+        let result = probe(&StdTransport, &config(url));
+        assert_eq!(result.reachability, Reachability::CaptivePortal);
+        assert_eq!(result.status_code, Some(200));
+
+        // A fixed-body probe matches only the exact body
+        let url = stub("HTTP/1.1 200 OK\r\n\r\nsuccess\n");
+        let fixed_body = ProbeConfig {
+            expected_status: 200,
+            expected_body: Some("success".to_string()),
+            ..config(url)
+        };
+        assert_eq!(
+            probe(&StdTransport, &fixed_body).reachability,
+            Reachability::Reachable
+        );
+    }
+
+    #[test]
+    fn test_no_route_and_dns_failure() {
+        // Grab a free port, then close it so nothing is listening
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let result = probe(
+            &StdTransport,
+            &config(format!("http://127.0.0.1:{}/", port)),
+        );
+        assert_eq!(result.reachability, Reachability::NoRoute);
+        assert!(result.error.is_some());
+
+        let result = probe(
+            &StdTransport,
+            &config("http://probe.invalid:80/".to_string()),
+        );
+        assert_eq!(result.reachability, Reachability::DnsFailure);
+    }
+}
@@ -0,0 +1,331 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::queue::{CoalescePolicy, EnqueueOptions, Priority, RequestConstraints, RetryPolicy};
+
+/// Errors from building a request out of untyped (FFI) input
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError {
+    #[error("Unknown HTTP method: {0}")]
+    InvalidMethod(String),
+    #[error("Unknown priority: {0}")]
+    InvalidPriority(String),
+    #[error("Invalid headers: {0}")]
+    InvalidHeaders(String),
+}
+
+/// HTTP method
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HttpMethod {
+    GET,
+    HEAD,
+    POST,
+    PUT,
+    PATCH,
+    DELETE,
+    OPTIONS,
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::OPTIONS => "OPTIONS",
+        }
+    }
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HttpMethod {
+    type Err = RequestError;
+
+    /// Case-insensitive method name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "GET" => Ok(HttpMethod::GET),
+            "HEAD" => Ok(HttpMethod::HEAD),
+            "POST" => Ok(HttpMethod::POST),
+            "PUT" => Ok(HttpMethod::PUT),
+            "PATCH" => Ok(HttpMethod::PATCH),
+            "DELETE" => Ok(HttpMethod::DELETE),
+            "OPTIONS" => Ok(HttpMethod::OPTIONS),
+            _ => Err(RequestError::InvalidMethod(s.to_string())),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = RequestError;
+
+    /// Case-insensitive priority name (`low`, `normal`, `high`, `critical`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            "critical" => Ok(Priority::Critical),
+            _ => Err(RequestError::InvalidPriority(s.to_string())),
+        }
+    }
+}
+
+/// Request body
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum RequestBody {
+    #[default]
+    Empty,
+    Bytes(Vec<u8>),
+    /// Sent as UTF-8, `text/plain` unless a Content-Type is set
+    Text(String),
+    /// Sent serialized, `application/json` unless a Content-Type is set
+    Json(serde_json::Value),
+}
+
+impl RequestBody {
+    /// Bytes to send, if any
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            RequestBody::Empty => None,
+            RequestBody::Bytes(b) => Some(b.clone()),
+            RequestBody::Text(t) => Some(t.as_bytes().to_vec()),
+            RequestBody::Json(v) => Some(v.to_string().into_bytes()),
+        }
+    }
+
+    fn default_content_type(&self) -> Option<&'static str> {
+        match self {
+            RequestBody::Text(_) => Some("text/plain; charset=utf-8"),
+            RequestBody::Json(_) => Some("application/json"),
+            RequestBody::Empty | RequestBody::Bytes(_) => None,
+        }
+    }
+}
+
+/// A request to queue, built with chained calls, e.g.
+/// `RequestSpec::post(url).json(body).priority(Priority::Low).tag("analytics")`
+#[derive(Debug, Clone)]
+pub struct RequestSpec {
+    pub method: HttpMethod,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: RequestBody,
+    /// Priority, tag, retry and coalescing policies and the rest
+    pub options: EnqueueOptions,
+}
+
+impl RequestSpec {
+    pub fn new(method: HttpMethod, url: impl Into<String>) -> Self {
+        RequestSpec {
+            method,
+            url: url.into(),
+            headers: BTreeMap::new(),
+            body: RequestBody::Empty,
+            options: EnqueueOptions::default(),
+        }
+    }
+
+    pub fn get(url: impl Into<String>) -> Self {
+        Self::new(HttpMethod::GET, url)
+    }
+
+    pub fn post(url: impl Into<String>) -> Self {
+        Self::new(HttpMethod::POST, url)
+    }
+
+    pub fn put(url: impl Into<String>) -> Self {
+        Self::new(HttpMethod::PUT, url)
+    }
+
+    pub fn patch(url: impl Into<String>) -> Self {
+        Self::new(HttpMethod::PATCH, url)
+    }
+
+    pub fn delete(url: impl Into<String>) -> Self {
+        Self::new(HttpMethod::DELETE, url)
+    }
+
+    /// Set a header, replacing any earlier value under the same name
+    /// (compared case-insensitively)
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        self.headers.retain(|n, _| !n.eq_ignore_ascii_case(&name));
+        self.headers.insert(name, value.into());
+        self
+    }
+
+    /// Add headers from a JSON object of strings
+    pub fn headers_json(mut self, headers_json: &str) -> Result<Self, RequestError> {
+        if headers_json.trim().is_empty() {
+            return Ok(self);
+        }
+        let headers: BTreeMap<String, String> = serde_json::from_str(headers_json)
+            .map_err(|e| RequestError::InvalidHeaders(e.to_string()))?;
+        for (name, value) in headers {
+            self = self.header(name, value);
+        }
+        Ok(self)
+    }
+
+    pub fn body(mut self, body: RequestBody) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn bytes(self, body: Vec<u8>) -> Self {
+        self.body(RequestBody::Bytes(body))
+    }
+
+    pub fn text(self, body: impl Into<String>) -> Self {
+        self.body(RequestBody::Text(body.into()))
+    }
+
+    pub fn json(self, body: serde_json::Value) -> Self {
+        self.body(RequestBody::Json(body))
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.options.priority = priority;
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.options.tag = Some(tag.into());
+        self
+    }
+
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.options.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Ask for the body to be gzipped if auto-compression is enabled and
+    /// it's worth it
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.options.compress = compress;
+        self
+    }
+
+    /// Coalesce with pending requests to the same resource (`key` defaults
+    /// to method + URL)
+    pub fn coalesce(mut self, policy: CoalescePolicy, key: Option<String>) -> Self {
+        self.options.coalesce = policy;
+        self.options.coalesce_key = key;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.options.retry_policy = Some(policy);
+        self
+    }
+
+    pub fn ordering_group(mut self, group: impl Into<String>) -> Self {
+        self.options.ordering_group = Some(group.into());
+        self
+    }
+
+    pub fn depends_on(mut self, request_id: impl Into<String>) -> Self {
+        self.options.depends_on = Some(request_id.into());
+        self
+    }
+
+    /// RFC 3339 time after which the request is dropped instead of sent
+    pub fn deadline_at(mut self, deadline: impl Into<String>) -> Self {
+        self.options.deadline_at = Some(deadline.into());
+        self
+    }
+
+    pub fn constraints(mut self, constraints: RequestConstraints) -> Self {
+        self.options.constraints = Some(constraints);
+        self
+    }
+
+    /// Headers as stored in the queue, with a Content-Type for text and
+    /// JSON bodies unless one was set
+    pub fn to_headers_json(&self) -> String {
+        let mut headers = self.headers.clone();
+        if let Some(content_type) = self.body.default_content_type()
+            && !headers
+                .keys()
+                .any(|n| n.eq_ignore_ascii_case("content-type"))
+        {
+            headers.insert("Content-Type".to_string(), content_type.to_string());
+        }
+        serde_json::to_string(&headers).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_method_and_priority() {
+        assert_eq!("head".parse::<HttpMethod>().unwrap(), HttpMethod::HEAD);
+        assert_eq!(
+            "OPTIONS".parse::<HttpMethod>().unwrap(),
+            HttpMethod::OPTIONS
+        );
+        assert!(matches!(
+            "FETCH".parse::<HttpMethod>(),
+            Err(RequestError::InvalidMethod(_))
+        ));
+
+        assert_eq!("Critical".parse::<Priority>().unwrap(), Priority::Critical);
+        assert!(matches!(
+            "hihg".parse::<Priority>(),
+            Err(RequestError::InvalidPriority(_))
+        ));
+    }
+
+    #[test]
+    fn test_builder() {
+        let spec = RequestSpec::post("https://a.com/events")
+            .header("content-type", "application/vnd.api+json")
+            .header("X-Trace", "1")
+            .header("x-trace", "2")
+            .json(serde_json::json!({ "a": 1 }))
+            .priority(Priority::High)
+            .tag("events");
+
+        assert_eq!(spec.method, HttpMethod::POST);
+        assert_eq!(spec.options.priority, Priority::High);
+        assert_eq!(spec.options.tag.as_deref(), Some("events"));
+        assert_eq!(spec.body.to_bytes().unwrap(), br#"{"a":1}"#);
+        // The explicit Content-Type wins; the later X-Trace replaces the earlier
+        assert_eq!(
+            spec.to_headers_json(),
+            r#"{"content-type":"application/vnd.api+json","x-trace":"2"}"#
+        );
+
+        let spec = RequestSpec::put("https://a.com").text("hi");
+        assert_eq!(
+            spec.to_headers_json(),
+            r#"{"Content-Type":"text/plain; charset=utf-8"}"#
+        );
+    }
+
+    #[test]
+    fn test_headers_json() {
+        let spec = RequestSpec::get("https://a.com")
+            .headers_json(r#"{"Accept":"text/html"}"#)
+            .unwrap();
+        assert_eq!(spec.headers["Accept"], "text/html");
+        assert!(RequestSpec::get("https://a.com").headers_json("").is_ok());
+        assert!(matches!(
+            RequestSpec::get("https://a.com").headers_json(r#"{"Accept":1}"#),
+            Err(RequestError::InvalidHeaders(_))
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, Row};
+
+/// Add a column to a table created by an older version of the schema.
+///
+/// `CREATE TABLE IF NOT EXISTS` leaves existing tables untouched, so every
+/// column added after the first release goes through here as well.
+pub(crate) fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if exists {
+        return Ok(false);
+    }
+
+    conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+        [],
+    )?;
+    Ok(true)
+}
+
+/// Read a body column as bytes.
+///
+/// Bodies used to be stored as TEXT; they are now written as BLOBs. SQLite
+/// keeps whichever storage class a value was written with, so old rows come
+/// back as text and must be accepted too.
+pub(crate) fn read_body(row: &Row, idx: usize) -> rusqlite::Result<Option<Vec<u8>>> {
+    match row.get_ref(idx)? {
+        ValueRef::Null => Ok(None),
+        ValueRef::Blob(b) => Ok(Some(b.to_vec())),
+        ValueRef::Text(t) => Ok(Some(t.to_vec())),
+        other => Err(rusqlite::Error::InvalidColumnType(
+            idx,
+            "body".to_string(),
+            other.data_type(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_column_if_missing() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE t (id TEXT PRIMARY KEY)", [])
+            .unwrap();
+        conn.execute("INSERT INTO t (id) VALUES ('a')", []).unwrap();
+
+        assert!(add_column_if_missing(&conn, "t", "extra", "INTEGER NOT NULL DEFAULT 7").unwrap());
+        assert!(!add_column_if_missing(&conn, "t", "extra", "INTEGER NOT NULL DEFAULT 7").unwrap());
+
+        let extra: i64 = conn
+            .query_row("SELECT extra FROM t", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(extra, 7);
+    }
+
+    #[test]
+    fn test_read_body_accepts_text_and_blob() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE t (n INTEGER, body TEXT)", [])
+            .unwrap();
+        conn.execute("INSERT INTO t VALUES (1, 'legacy text')", [])
+            .unwrap();
+        conn.execute("INSERT INTO t VALUES (2, ?1)", [vec![0u8, 159, 146, 150]])
+            .unwrap();
+        conn.execute("INSERT INTO t VALUES (3, NULL)", []).unwrap();
+
+        let mut stmt = conn.prepare("SELECT body FROM t ORDER BY n").unwrap();
+        let bodies: Vec<Option<Vec<u8>>> = stmt
+            .query_map([], |row| read_body(row, 0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(bodies[0], Some(b"legacy text".to_vec()));
+        assert_eq!(bodies[1], Some(vec![0u8, 159, 146, 150]));
+        assert_eq!(bodies[2], None);
+    }
+}
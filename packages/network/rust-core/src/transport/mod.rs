@@ -0,0 +1,473 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::parse_headers;
+use crate::connectivity::{BandwidthEstimator, NetworkStatus, PowerState};
+use crate::queue::{QueueError, QueuedRequest, RequestFailure, RequestQueue, SendConditions};
+
+/// Response returned by a transport after sending a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransportResponse {
+    /// HTTP status code
+    pub status_code: u16,
+    /// Response headers as JSON
+    pub headers_json: String,
+    /// Response body (base64 in JSON)
+    #[serde(with = "crate::optimization::base64_body")]
+    pub body: Vec<u8>,
+    /// Wall-clock time from first byte sent to last byte received
+    pub duration_ms: u64,
+}
+
+impl TransportResponse {
+    /// Whether the response status counts as a successful delivery. A 3xx
+    /// means the transport didn't follow a redirect (possibly to a captive
+    /// portal login page), so the request wasn't delivered and is retried.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status_code)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    #[error("Request timed out")]
+    Timeout,
+    #[error("DNS lookup failed: {0}")]
+    Dns(String),
+    #[error("Connection failed: {0}")]
+    Connection(String),
+    #[error("Transport error: {0}")]
+    Other(String),
+}
+
+/// Platform HTTP client used to actually send queued requests.
+///
+/// Implementations wrap URLSession, OkHttp, fetch, etc. The core never opens
+/// sockets itself — it only decides what to send and when.
+pub trait Transport: Send + Sync {
+    /// Send a single request, giving up after `timeout`
+    fn send(
+        &self,
+        request: &QueuedRequest,
+        timeout: Duration,
+    ) -> Result<TransportResponse, TransportError>;
+}
+
+/// Runs reconnect work where the platform wants it. Called from status
+/// updates, often on the main thread, so it must not send anything itself:
+/// it should have a background thread call
+/// `RajeevNetwork::drain_on_reconnect`.
+pub trait DrainExecutor: Send + Sync {
+    fn schedule_drain(&self);
+}
+
+impl<F> DrainExecutor for F
+where
+    F: Fn() + Send + Sync,
+{
+    fn schedule_drain(&self) {
+        self()
+    }
+}
+
+/// What to send, and where, when the device comes back online
+#[derive(Clone)]
+pub struct ReconnectDrain {
+    pub transport: Arc<dyn Transport>,
+    pub executor: Arc<dyn DrainExecutor>,
+    /// Queued requests to attempt per drain
+    pub max_requests: u32,
+    /// Chunks to send per unfinished upload per drain
+    pub max_upload_chunks: u32,
+}
+
+/// Summary of a `drain_queue` run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DrainReport {
+    /// Requests handed to the transport
+    pub attempted: u32,
+    /// Requests that succeeded and were removed from the queue
+    pub succeeded: u32,
+    /// Requests that failed and were scheduled for retry
+    pub retried: u32,
+    /// Requests that failed and were moved to the dead-letter table
+    pub dropped: u32,
+}
+
+/// Extra lease time on top of the send timeout, for bookkeeping after the send
+const LEASE_MARGIN: Duration = Duration::from_secs(30);
+
+/// Fail a leased request and count the outcome
+fn record_failure(
+    queue: &RequestQueue,
+    request_id: &str,
+    token: &str,
+    failure: &RequestFailure,
+    report: &mut DrainReport,
+) -> Result<(), QueueError> {
+    match queue.fail_leased(request_id, token, failure) {
+        Ok(true) => report.retried += 1,
+        Ok(false) => report.dropped += 1,
+        Err(QueueError::LeaseMismatch(_)) => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Send queued requests until the queue has nothing eligible left or
+/// `max_requests` have been attempted.
+///
+/// Each request is picked by the current network status and `power` (see
+/// `RequestQueue::dequeue_when`) and leased for the send timeout plus
+/// `LEASE_MARGIN`, sent through `transport`, and then completed or failed
+/// based on the result. Successful transfers feed the
+/// bandwidth estimator. A request whose lease was lost to another worker is
+/// left to that worker.
+pub fn drain_queue(
+    queue: &RequestQueue,
+    transport: &dyn Transport,
+    bandwidth: &BandwidthEstimator,
+    status: &NetworkStatus,
+    power: Option<PowerState>,
+    max_requests: u32,
+) -> Result<DrainReport, QueueError> {
+    let mut report = DrainReport::default();
+    if !status.is_online {
+        return Ok(report);
+    }
+
+    let timeout = status.suggested_timeout();
+    let conditions = SendConditions::new(status, power);
+
+    while report.attempted < max_requests {
+        let request = match queue.dequeue_when(&conditions, timeout + LEASE_MARGIN)? {
+            Some(r) => r,
+            None => break,
+        };
+        let token = request.lease_token.clone().unwrap_or_default();
+        report.attempted += 1;
+
+        match transport.send(&request, timeout) {
+            Ok(response) => {
+                let bytes =
+                    request.body.as_ref().map(|b| b.len()).unwrap_or(0) + response.body.len();
+                bandwidth.record_transfer(bytes as u64, response.duration_ms);
+
+                if response.is_success() {
+                    match queue.complete_leased(&request.id, &token) {
+                        Ok(()) => report.succeeded += 1,
+                        Err(QueueError::LeaseMismatch(_)) => {}
+                        Err(e) => return Err(e),
+                    }
+                    continue;
+                }
+                let failure = RequestFailure {
+                    status_code: Some(response.status_code),
+                    error: None,
+                    retry_after: parse_headers(&response.headers_json).remove("retry-after"),
+                };
+                record_failure(queue, &request.id, &token, &failure, &mut report)?;
+            }
+            Err(e) => {
+                let failure = RequestFailure {
+                    status_code: None,
+                    error: Some(e.to_string()),
+                    retry_after: None,
+                };
+                record_failure(queue, &request.id, &token, &failure, &mut report)?;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connectivity::ConnectionType;
+    use crate::queue::Priority;
+    use std::sync::Mutex;
+
+    /// Transport that answers every request with a fixed status and records URLs
+    struct MockTransport {
+        status_code: u16,
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl MockTransport {
+        fn new(status_code: u16) -> Self {
+            MockTransport {
+                status_code,
+                sent: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn send(
+            &self,
+            request: &QueuedRequest,
+            _timeout: Duration,
+        ) -> Result<TransportResponse, TransportError> {
+            self.sent.lock().unwrap().push(request.url.clone());
+            Ok(TransportResponse {
+                status_code: self.status_code,
+                headers_json: "{}".to_string(),
+                body: vec![b'x'; 10_000],
+                duration_ms: 50,
+            })
+        }
+    }
+
+    struct FailingTransport;
+
+    impl Transport for FailingTransport {
+        fn send(
+            &self,
+            _request: &QueuedRequest,
+            _timeout: Duration,
+        ) -> Result<TransportResponse, TransportError> {
+            Err(TransportError::Connection("connection reset".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_drain_sends_in_priority_order() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://normal.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "POST",
+                "https://critical.com",
+                "{}",
+                None,
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let transport = MockTransport::new(200);
+        let bandwidth = BandwidthEstimator::new(10);
+        let status = NetworkStatus::from_connection_type(ConnectionType::WiFi);
+
+        let report = drain_queue(&queue, &transport, &bandwidth, &status, None, 10).unwrap();
+        assert_eq!(report.attempted, 2);
+        assert_eq!(report.succeeded, 2);
+        assert_eq!(queue.size().unwrap(), 0);
+
+        let sent = transport.sent.lock().unwrap();
+        assert_eq!(sent[0], "https://critical.com");
+        assert!(bandwidth.estimate_kbps() > 0);
+    }
+
+    #[test]
+    fn test_drain_fails_on_server_error() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Normal,
+                false,
+                None,
+            )
+            .unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://b.com",
+                "{}",
+                None,
+                Priority::Low,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let transport = MockTransport::new(500);
+        let bandwidth = BandwidthEstimator::new(10);
+        let status = NetworkStatus::from_connection_type(ConnectionType::WiFi);
+
+        let report = drain_queue(&queue, &transport, &bandwidth, &status, None, 10).unwrap();
+        assert_eq!(report.attempted, 2);
+        assert_eq!(report.retried, 1); // Normal backs off
+        assert_eq!(report.dropped, 1); // Low has a single attempt
+        assert_eq!(queue.size().unwrap(), 1);
+
+        let letters = queue.list_dead_letters(10).unwrap();
+        assert_eq!(letters[0].request.url, "https://b.com");
+        assert_eq!(letters[0].last_status_code, Some(500));
+    }
+
+    #[test]
+    fn test_drain_permanent_failure_and_retry_after() {
+        struct StatusTransport(u16, &'static str);
+        impl Transport for StatusTransport {
+            fn send(
+                &self,
+                _request: &QueuedRequest,
+                _timeout: Duration,
+            ) -> Result<TransportResponse, TransportError> {
+                Ok(TransportResponse {
+                    status_code: self.0,
+                    headers_json: self.1.to_string(),
+                    body: Vec::new(),
+                    duration_ms: 5,
+                })
+            }
+        }
+
+        let queue = RequestQueue::new(":memory:").unwrap();
+        let bandwidth = BandwidthEstimator::new(10);
+        let status = NetworkStatus::from_connection_type(ConnectionType::WiFi);
+
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+        let report = drain_queue(
+            &queue,
+            &StatusTransport(404, "{}"),
+            &bandwidth,
+            &status,
+            None,
+            10,
+        )
+        .unwrap();
+        assert_eq!(report.dropped, 1);
+
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+        let report = drain_queue(
+            &queue,
+            &StatusTransport(429, r#"{"Retry-After":"600"}"#),
+            &bandwidth,
+            &status,
+            None,
+            10,
+        )
+        .unwrap();
+        assert_eq!(report.retried, 1);
+        let req = queue.list_pending(1).unwrap().remove(0);
+        let next = chrono::DateTime::parse_from_rfc3339(&req.next_attempt_at).unwrap();
+        assert!((next.with_timezone(&chrono::Utc) - chrono::Utc::now()).num_seconds() > 590);
+    }
+
+    #[test]
+    fn test_drain_transport_error_retries() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::High,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let bandwidth = BandwidthEstimator::new(10);
+        let status = NetworkStatus::from_connection_type(ConnectionType::Cellular4G);
+
+        let report = drain_queue(&queue, &FailingTransport, &bandwidth, &status, None, 10).unwrap();
+        assert_eq!(report.attempted, 1);
+        assert_eq!(report.retried, 1);
+        assert_eq!(bandwidth.estimate_kbps(), 0);
+    }
+
+    #[test]
+    fn test_drain_retries_unfollowed_redirect() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        queue
+            .enqueue(
+                "POST",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::High,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let bandwidth = BandwidthEstimator::new(10);
+        let status = NetworkStatus::from_connection_type(ConnectionType::WiFi);
+
+        let report = drain_queue(
+            &queue,
+            &MockTransport::new(302),
+            &bandwidth,
+            &status,
+            None,
+            10,
+        )
+        .unwrap();
+        assert_eq!(report.succeeded, 0);
+        assert_eq!(report.retried, 1);
+        assert_eq!(queue.size().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_drain_offline_does_nothing() {
+        let queue = RequestQueue::new(":memory:").unwrap();
+        queue
+            .enqueue(
+                "GET",
+                "https://a.com",
+                "{}",
+                None,
+                Priority::Critical,
+                false,
+                None,
+            )
+            .unwrap();
+
+        let transport = MockTransport::new(200);
+        let bandwidth = BandwidthEstimator::new(10);
+
+        let report = drain_queue(
+            &queue,
+            &transport,
+            &bandwidth,
+            &NetworkStatus::offline(),
+            None,
+            10,
+        )
+        .unwrap();
+        assert_eq!(report.attempted, 0);
+        assert_eq!(queue.size().unwrap(), 1);
+    }
+}
@@ -0,0 +1,946 @@
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::cache::parse_headers;
+use crate::queue::{Priority, QueuedRequest};
+use crate::transport::{Transport, TransportError, TransportResponse};
+
+/// Protocol version sent in `Tus-Resumable`
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// Chunk size when none is given
+pub const DEFAULT_CHUNK_SIZE: u64 = 256 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Database error: {0}")]
+    DatabaseError(String),
+    #[error("Upload not found: {0}")]
+    NotFound(String),
+    /// The file couldn't be read; the job keeps its offset and can resume
+    #[error("File error: {0}")]
+    IoError(String),
+    /// The file's size changed since the upload started; the job is failed
+    #[error("File changed: {0}")]
+    FileChanged(String),
+    /// The server answered in a way retrying won't fix; the job is failed
+    #[error("Upload rejected: {0}")]
+    Rejected(String),
+    /// Network or server trouble; the job keeps its offset and can resume
+    #[error("Transport error: {0}")]
+    Transport(String),
+}
+
+impl From<rusqlite::Error> for UploadError {
+    fn from(e: rusqlite::Error) -> Self {
+        UploadError::DatabaseError(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        UploadError::IoError(e.to_string())
+    }
+}
+
+impl From<TransportError> for UploadError {
+    fn from(e: TransportError) -> Self {
+        UploadError::Transport(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UploadState {
+    /// Not created on the server yet
+    Pending,
+    /// Created; `offset` bytes confirmed by the server
+    Uploading,
+    Completed,
+    Failed,
+}
+
+impl UploadState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UploadState::Pending => "pending",
+            UploadState::Uploading => "uploading",
+            UploadState::Completed => "completed",
+            UploadState::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "uploading" => UploadState::Uploading,
+            "completed" => UploadState::Completed,
+            "failed" => UploadState::Failed,
+            _ => UploadState::Pending,
+        }
+    }
+}
+
+/// Options for a new upload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadOptions {
+    /// Bytes sent per PATCH
+    pub chunk_size: u64,
+    /// Extra headers for every request (e.g. Authorization)
+    pub headers: BTreeMap<String, String>,
+    /// Sent as `Upload-Metadata` when the upload is created
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            headers: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+/// A file upload sent in chunks with the tus 1.0 protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadJob {
+    pub id: String,
+    /// File to upload; read a chunk at a time, never stored in the database
+    pub file_path: String,
+    /// tus creation endpoint
+    pub endpoint: String,
+    /// Upload resource returned by the server on creation
+    pub upload_url: Option<String>,
+    pub file_size: u64,
+    pub chunk_size: u64,
+    /// Bytes the server has acknowledged
+    pub offset: u64,
+    pub headers: BTreeMap<String, String>,
+    pub metadata: BTreeMap<String, String>,
+    pub state: UploadState,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+const JOB_COLUMNS: &str =
+    "id, file_path, endpoint, upload_url, file_size, chunk_size, upload_offset,
+    headers_json, metadata_json, state, last_error, created_at, updated_at";
+
+fn job_from_row(row: &Row) -> rusqlite::Result<UploadJob> {
+    let headers_json: String = row.get(7)?;
+    let metadata_json: String = row.get(8)?;
+    let state: String = row.get(9)?;
+    Ok(UploadJob {
+        id: row.get(0)?,
+        file_path: row.get(1)?,
+        endpoint: row.get(2)?,
+        upload_url: row.get(3)?,
+        file_size: row.get::<_, i64>(4)? as u64,
+        chunk_size: row.get::<_, i64>(5)? as u64,
+        offset: row.get::<_, i64>(6)? as u64,
+        headers: serde_json::from_str(&headers_json).unwrap_or_default(),
+        metadata: serde_json::from_str(&metadata_json).unwrap_or_default(),
+        state: UploadState::parse(&state),
+        last_error: row.get(10)?,
+        created_at: row.get(11)?,
+        updated_at: row.get(12)?,
+    })
+}
+
+/// Persistent resumable uploads backed by SQLite
+pub struct UploadQueue {
+    conn: Mutex<Connection>,
+}
+
+impl UploadQueue {
+    pub fn new(db_path: &str) -> Result<Self, UploadError> {
+        let conn = if db_path == ":memory:" {
+            Connection::open_in_memory()?
+        } else {
+            Connection::open(db_path)?
+        };
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS upload_jobs (
+                id TEXT PRIMARY KEY,
+                file_path TEXT NOT NULL,
+                endpoint TEXT NOT NULL,
+                upload_url TEXT,
+                file_size INTEGER NOT NULL,
+                chunk_size INTEGER NOT NULL,
+                upload_offset INTEGER NOT NULL DEFAULT 0,
+                headers_json TEXT NOT NULL DEFAULT '{}',
+                metadata_json TEXT NOT NULL DEFAULT '{}',
+                state TEXT NOT NULL DEFAULT 'pending',
+                last_error TEXT,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_upload_state ON upload_jobs(state, created_at);
+            ",
+        )?;
+        Ok(UploadQueue {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Register an upload of `file_path` to the tus `endpoint`. Nothing is
+    /// sent until `resume`.
+    pub fn create(
+        &self,
+        file_path: &str,
+        endpoint: &str,
+        options: &UploadOptions,
+    ) -> Result<UploadJob, UploadError> {
+        let file_size = std::fs::metadata(file_path)?.len();
+        let now = Utc::now().to_rfc3339();
+        let job = UploadJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            file_path: file_path.to_string(),
+            endpoint: endpoint.to_string(),
+            upload_url: None,
+            file_size,
+            chunk_size: options.chunk_size.max(1),
+            offset: 0,
+            headers: options.headers.clone(),
+            metadata: options.metadata.clone(),
+            state: UploadState::Pending,
+            last_error: None,
+            created_at: now.clone(),
+            updated_at: now,
+        };
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| UploadError::DatabaseError(e.to_string()))?;
+        conn.execute(
+            &format!(
+                "INSERT INTO upload_jobs ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                JOB_COLUMNS
+            ),
+            params![
+                job.id,
+                job.file_path,
+                job.endpoint,
+                job.upload_url,
+                job.file_size as i64,
+                job.chunk_size as i64,
+                job.offset as i64,
+                serde_json::to_string(&job.headers).unwrap_or_else(|_| "{}".to_string()),
+                serde_json::to_string(&job.metadata).unwrap_or_else(|_| "{}".to_string()),
+                job.state.as_str(),
+                job.last_error,
+                job.created_at,
+                job.updated_at,
+            ],
+        )?;
+        Ok(job)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<UploadJob>, UploadError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| UploadError::DatabaseError(e.to_string()))?;
+        let job = conn
+            .query_row(
+                &format!("SELECT {} FROM upload_jobs WHERE id = ?1", JOB_COLUMNS),
+                params![id],
+                job_from_row,
+            )
+            .optional()?;
+        Ok(job)
+    }
+
+    /// All uploads, oldest first
+    pub fn list(&self) -> Result<Vec<UploadJob>, UploadError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| UploadError::DatabaseError(e.to_string()))?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM upload_jobs ORDER BY created_at ASC, rowid ASC",
+            JOB_COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map([], job_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(jobs)
+    }
+
+    /// IDs of uploads that still have bytes to send, oldest first
+    pub fn unfinished(&self) -> Result<Vec<String>, UploadError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| UploadError::DatabaseError(e.to_string()))?;
+        let mut stmt = conn.prepare(
+            "SELECT id FROM upload_jobs WHERE state IN ('pending', 'uploading')
+             ORDER BY created_at ASC, rowid ASC",
+        )?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ids)
+    }
+
+    /// Forget an upload. The partial upload on the server is left to expire.
+    pub fn remove(&self, id: &str) -> Result<bool, UploadError> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| UploadError::DatabaseError(e.to_string()))?;
+        Ok(conn.execute("DELETE FROM upload_jobs WHERE id = ?1", params![id])? > 0)
+    }
+
+    /// Put a failed upload back in line to resume from its last offset.
+    /// Returns false if the upload hadn't failed.
+    pub fn retry(&self, id: &str) -> Result<bool, UploadError> {
+        let mut job = self
+            .get(id)?
+            .ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        if job.state != UploadState::Failed {
+            return Ok(false);
+        }
+        job.state = match job.upload_url {
+            Some(_) => UploadState::Uploading,
+            None => UploadState::Pending,
+        };
+        job.last_error = None;
+        self.save(&mut job)?;
+        Ok(true)
+    }
+
+    fn save(&self, job: &mut UploadJob) -> Result<(), UploadError> {
+        job.updated_at = Utc::now().to_rfc3339();
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| UploadError::DatabaseError(e.to_string()))?;
+        conn.execute(
+            "UPDATE upload_jobs
+             SET upload_url = ?2, upload_offset = ?3, state = ?4, last_error = ?5, updated_at = ?6
+             WHERE id = ?1",
+            params![
+                job.id,
+                job.upload_url,
+                job.offset as i64,
+                job.state.as_str(),
+                job.last_error,
+                job.updated_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Send up to `max_chunks` chunks of upload `id`, picking up from the
+    /// last offset the server acknowledged. The offset is saved after every
+    /// chunk, so an interrupted upload loses at most the chunk in flight.
+    /// On a transport or file read error the job keeps its progress and the
+    /// error is returned; a rejection or a changed file fails the job.
+    pub fn resume(
+        &self,
+        id: &str,
+        transport: &dyn Transport,
+        timeout: Duration,
+        max_chunks: u32,
+    ) -> Result<UploadJob, UploadError> {
+        let mut job = self
+            .get(id)?
+            .ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        if matches!(job.state, UploadState::Completed | UploadState::Failed) {
+            return Ok(job);
+        }
+
+        match self.advance(&mut job, transport, timeout, max_chunks) {
+            Ok(()) => {
+                job.last_error = None;
+                self.save(&mut job)?;
+                Ok(job)
+            }
+            Err(e) => {
+                if matches!(e, UploadError::Rejected(_) | UploadError::FileChanged(_)) {
+                    job.state = UploadState::Failed;
+                }
+                job.last_error = Some(e.to_string());
+                self.save(&mut job)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn advance(
+        &self,
+        job: &mut UploadJob,
+        transport: &dyn Transport,
+        timeout: Duration,
+        max_chunks: u32,
+    ) -> Result<(), UploadError> {
+        let mut file = File::open(&job.file_path)?;
+        if file.metadata()?.len() != job.file_size {
+            return Err(UploadError::FileChanged(format!(
+                "{} changed since the upload started",
+                job.file_path
+            )));
+        }
+
+        // The last PATCH may have landed without its response getting back
+        if job.upload_url.is_some() {
+            sync_offset(job, transport, timeout)?;
+        }
+
+        let mut chunks = 0;
+        loop {
+            if job.upload_url.is_none() {
+                create_upload(job, transport, timeout)?;
+                self.save(job)?;
+            }
+            if job.offset >= job.file_size {
+                job.state = UploadState::Completed;
+                return Ok(());
+            }
+            if chunks >= max_chunks {
+                return Ok(());
+            }
+            send_chunk(job, &mut file, transport, timeout)?;
+            self.save(job)?;
+            chunks += 1;
+        }
+    }
+}
+
+/// A request carrying the tus headers plus the job's own
+fn tus_request(
+    job: &UploadJob,
+    method: &str,
+    url: &str,
+    extra: &[(&str, String)],
+    body: Option<Vec<u8>>,
+) -> QueuedRequest {
+    let mut headers = job.headers.clone();
+    headers.insert("Tus-Resumable".to_string(), TUS_VERSION.to_string());
+    for (name, value) in extra {
+        headers.insert(name.to_string(), value.clone());
+    }
+    let now = Utc::now().to_rfc3339();
+    QueuedRequest {
+        id: format!("{}-{}", job.id, method.to_lowercase()),
+        method: method.to_string(),
+        url: url.to_string(),
+        headers_json: serde_json::to_string(&headers).unwrap_or_else(|_| "{}".to_string()),
+        body,
+        priority: Priority::Normal as i32,
+        retry_count: 0,
+        max_retries: 0,
+        created_at: now.clone(),
+        next_attempt_at: now,
+        compress: false,
+        tag: None,
+        idempotency_key: None,
+        lease_token: None,
+        lease_expires_at: None,
+        ordering_group: None,
+        depends_on: None,
+        deadline_at: None,
+        constraints: None,
+    }
+}
+
+/// Server errors, timeouts, locks, throttling and conflicts a resync
+/// didn't settle are worth retrying; other statuses aren't
+fn unexpected(step: &str, response: &TransportResponse) -> UploadError {
+    let message = format!("{} answered HTTP {}", step, response.status_code);
+    if response.status_code >= 500 || matches!(response.status_code, 408 | 409 | 423 | 429) {
+        UploadError::Transport(message)
+    } else {
+        UploadError::Rejected(message)
+    }
+}
+
+fn upload_offset(response: &TransportResponse) -> Option<u64> {
+    parse_headers(&response.headers_json)
+        .get("upload-offset")
+        .and_then(|o| o.trim().parse().ok())
+}
+
+/// `Upload-Metadata`: comma-separated keys with base64 values
+fn encode_metadata(metadata: &BTreeMap<String, String>) -> String {
+    metadata
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{} {}",
+                key,
+                base64::Engine::encode(&base64::engine::general_purpose::STANDARD, value)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Resolve a `Location` header against the creation endpoint
+fn resolve_location(endpoint: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    let scheme_end = endpoint.find("://").map(|i| i + 3).unwrap_or(0);
+    if location.starts_with('/') {
+        let origin_end = endpoint[scheme_end..]
+            .find('/')
+            .map(|i| i + scheme_end)
+            .unwrap_or(endpoint.len());
+        format!("{}{}", &endpoint[..origin_end], location)
+    } else {
+        format!("{}/{}", endpoint.trim_end_matches('/'), location)
+    }
+}
+
+/// POST to the endpoint to create the upload resource
+fn create_upload(
+    job: &mut UploadJob,
+    transport: &dyn Transport,
+    timeout: Duration,
+) -> Result<(), UploadError> {
+    let mut extra = vec![("Upload-Length", job.file_size.to_string())];
+    if !job.metadata.is_empty() {
+        extra.push(("Upload-Metadata", encode_metadata(&job.metadata)));
+    }
+    let request = tus_request(job, "POST", &job.endpoint, &extra, None);
+    let response = transport.send(&request, timeout)?;
+    if response.status_code != 201 {
+        return Err(unexpected("Upload creation", &response));
+    }
+    let location = parse_headers(&response.headers_json)
+        .remove("location")
+        .ok_or_else(|| UploadError::Rejected("Upload creation returned no Location".to_string()))?;
+    job.upload_url = Some(resolve_location(&job.endpoint, &location));
+    job.offset = 0;
+    job.state = UploadState::Uploading;
+    Ok(())
+}
+
+/// HEAD the upload to learn how much the server has. If it no longer
+/// knows the upload, start over.
+fn sync_offset(
+    job: &mut UploadJob,
+    transport: &dyn Transport,
+    timeout: Duration,
+) -> Result<(), UploadError> {
+    let url = job.upload_url.clone().unwrap_or_default();
+    let response = transport.send(&tus_request(job, "HEAD", &url, &[], None), timeout)?;
+    match response.status_code {
+        200 | 204 => {
+            let offset = upload_offset(&response)
+                .filter(|o| *o <= job.file_size)
+                .ok_or_else(|| {
+                    UploadError::Rejected(
+                        "Offset check returned no valid Upload-Offset".to_string(),
+                    )
+                })?;
+            job.offset = offset;
+            Ok(())
+        }
+        403 | 404 | 410 => {
+            job.upload_url = None;
+            job.offset = 0;
+            job.state = UploadState::Pending;
+            Ok(())
+        }
+        _ => Err(unexpected("Offset check", &response)),
+    }
+}
+
+/// PATCH the next chunk from the acknowledged offset
+fn send_chunk(
+    job: &mut UploadJob,
+    file: &mut File,
+    transport: &dyn Transport,
+    timeout: Duration,
+) -> Result<(), UploadError> {
+    let len = job.chunk_size.min(job.file_size - job.offset);
+    let mut chunk = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(job.offset))?;
+    file.read_exact(&mut chunk)?;
+
+    let url = job.upload_url.clone().unwrap_or_default();
+    let extra = [
+        ("Upload-Offset", job.offset.to_string()),
+        (
+            "Content-Type",
+            "application/offset+octet-stream".to_string(),
+        ),
+    ];
+    let response = transport.send(
+        &tus_request(job, "PATCH", &url, &extra, Some(chunk)),
+        timeout,
+    )?;
+    match response.status_code {
+        200 | 204 => {
+            let offset = upload_offset(&response)
+                .filter(|o| *o > job.offset && *o <= job.file_size)
+                .ok_or_else(|| {
+                    UploadError::Rejected(
+                        "Chunk upload returned no valid Upload-Offset".to_string(),
+                    )
+                })?;
+            job.offset = offset;
+            Ok(())
+        }
+        // Offset mismatch, or the upload expired: resync and carry on
+        409 | 403 | 404 | 410 => sync_offset(job, transport, timeout),
+        _ => Err(unexpected("Chunk upload", &response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Write;
+
+    /// In-process tus 1.0 server
+    #[derive(Default)]
+    struct TusStub {
+        /// Upload path -> (declared length, bytes received)
+        uploads: Mutex<HashMap<String, (u64, Vec<u8>)>>,
+        /// (method, Upload-Offset header) of every request seen
+        log: Mutex<Vec<(String, Option<u64>)>>,
+        /// Fail the Nth PATCH (1-based) with a connection error
+        drop_patch: Option<usize>,
+        /// Apply the Nth PATCH but lose the response
+        lose_ack: Option<usize>,
+        /// Answer the Nth PATCH with this status without applying it
+        refuse_patch: Option<(usize, u16)>,
+    }
+
+    impl TusStub {
+        fn respond(
+            status_code: u16,
+            headers: serde_json::Value,
+        ) -> Result<TransportResponse, TransportError> {
+            Ok(TransportResponse {
+                status_code,
+                headers_json: headers.to_string(),
+                body: Vec::new(),
+                duration_ms: 5,
+            })
+        }
+
+        fn received(&self) -> Vec<u8> {
+            self.uploads
+                .lock()
+                .unwrap()
+                .values()
+                .next()
+                .map(|(_, b)| b.clone())
+                .unwrap_or_default()
+        }
+
+        fn patches(&self) -> Vec<Option<u64>> {
+            let log = self.log.lock().unwrap();
+            log.iter()
+                .filter(|(m, _)| m == "PATCH")
+                .map(|(_, o)| *o)
+                .collect()
+        }
+    }
+
+    impl Transport for TusStub {
+        fn send(
+            &self,
+            request: &QueuedRequest,
+            _timeout: Duration,
+        ) -> Result<TransportResponse, TransportError> {
+            let headers = parse_headers(&request.headers_json);
+            assert_eq!(
+                headers.get("tus-resumable").map(String::as_str),
+                Some(TUS_VERSION)
+            );
+            let offset = headers.get("upload-offset").and_then(|o| o.parse().ok());
+            self.log
+                .lock()
+                .unwrap()
+                .push((request.method.clone(), offset));
+            let path = request
+                .url
+                .trim_start_matches("http://tus.test")
+                .to_string();
+            let mut uploads = self.uploads.lock().unwrap();
+
+            match request.method.as_str() {
+                "POST" => {
+                    let length = headers["upload-length"].parse().unwrap();
+                    let location = format!("/files/{}", uploads.len() + 1);
+                    uploads.insert(location.clone(), (length, Vec::new()));
+                    Self::respond(201, serde_json::json!({ "Location": location }))
+                }
+                "HEAD" => match uploads.get(&path) {
+                    Some((length, data)) => Self::respond(
+                        200,
+                        serde_json::json!({ "Upload-Offset": data.len().to_string(), "Upload-Length": length.to_string() }),
+                    ),
+                    None => Self::respond(404, serde_json::json!({})),
+                },
+                "PATCH" => {
+                    let n = self.patches().len();
+                    if self.drop_patch == Some(n) {
+                        return Err(TransportError::Connection("reset by peer".to_string()));
+                    }
+                    if let Some((nth, status)) = self.refuse_patch
+                        && nth == n
+                    {
+                        return Self::respond(status, serde_json::json!({}));
+                    }
+                    let Some((_, data)) = uploads.get_mut(&path) else {
+                        return Self::respond(404, serde_json::json!({}));
+                    };
+                    if offset != Some(data.len() as u64) {
+                        return Self::respond(409, serde_json::json!({}));
+                    }
+                    data.extend_from_slice(request.body.as_deref().unwrap_or_default());
+                    if self.lose_ack == Some(n) {
+                        return Err(TransportError::Timeout);
+                    }
+                    Self::respond(
+                        204,
+                        serde_json::json!({ "Upload-Offset": data.len().to_string() }),
+                    )
+                }
+                _ => Self::respond(405, serde_json::json!({})),
+            }
+        }
+    }
+
+    fn file_of(dir: &tempfile::TempDir, len: usize) -> (String, Vec<u8>) {
+        let contents: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let path = dir.path().join("document.pdf");
+        File::create(&path).unwrap().write_all(&contents).unwrap();
+        (path.to_string_lossy().to_string(), contents)
+    }
+
+    fn options() -> UploadOptions {
+        UploadOptions {
+            chunk_size: 4096,
+            metadata: BTreeMap::from([("filename".to_string(), "document.pdf".to_string())]),
+            ..UploadOptions::default()
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_upload_in_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, contents) = file_of(&dir, 10_000);
+        let uploads = UploadQueue::new(":memory:").unwrap();
+        let server = TusStub::default();
+
+        let job = uploads
+            .create(&path, "http://tus.test/files", &options())
+            .unwrap();
+        let job = uploads.resume(&job.id, &server, TIMEOUT, 2).unwrap();
+        assert_eq!(job.state, UploadState::Uploading);
+        assert_eq!(job.offset, 8192);
+        assert_eq!(job.upload_url.as_deref(), Some("http://tus.test/files/1"));
+
+        let job = uploads.resume(&job.id, &server, TIMEOUT, 10).unwrap();
+        assert_eq!(job.state, UploadState::Completed);
+        assert_eq!(server.received(), contents);
+        assert_eq!(server.patches(), vec![Some(0), Some(4096), Some(8192)]);
+        assert!(uploads.unfinished().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resume_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, contents) = file_of(&dir, 10_000);
+        let db = dir.path().join("uploads.db").to_string_lossy().to_string();
+        let server = TusStub {
+            drop_patch: Some(2),
+            ..TusStub::default()
+        };
+
+        let id = {
+            let uploads = UploadQueue::new(&db).unwrap();
+            let job = uploads
+                .create(&path, "http://tus.test/files", &options())
+                .unwrap();
+            let err = uploads.resume(&job.id, &server, TIMEOUT, 10).unwrap_err();
+            assert!(matches!(err, UploadError::Transport(_)));
+            let job = uploads.get(&job.id).unwrap().unwrap();
+            assert_eq!(job.offset, 4096);
+            assert!(job.last_error.is_some());
+            job.id
+        };
+
+        // A fresh process picks up from the acknowledged byte
+        let uploads = UploadQueue::new(&db).unwrap();
+        assert_eq!(uploads.unfinished().unwrap(), vec![id.clone()]);
+        let job = uploads.resume(&id, &server, TIMEOUT, 10).unwrap();
+        assert_eq!(job.state, UploadState::Completed);
+        assert_eq!(job.last_error, None);
+        assert_eq!(server.received(), contents);
+        assert_eq!(
+            server.patches(),
+            vec![Some(0), Some(4096), Some(4096), Some(8192)]
+        );
+    }
+
+    #[test]
+    fn test_lost_ack_and_expired_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, contents) = file_of(&dir, 10_000);
+        let uploads = UploadQueue::new(":memory:").unwrap();
+
+        // The server kept the second chunk but its response never arrived:
+        // the HEAD on resume finds that out, so nothing is sent twice
+        let server = TusStub {
+            lose_ack: Some(2),
+            ..TusStub::default()
+        };
+        let job = uploads
+            .create(&path, "http://tus.test/files", &options())
+            .unwrap();
+        assert!(uploads.resume(&job.id, &server, TIMEOUT, 10).is_err());
+        assert_eq!(uploads.get(&job.id).unwrap().unwrap().offset, 4096);
+        uploads.resume(&job.id, &server, TIMEOUT, 10).unwrap();
+        assert_eq!(server.received(), contents);
+        assert_eq!(server.patches(), vec![Some(0), Some(4096), Some(8192)]);
+
+        // The server forgot the upload: start again
+        let job = uploads
+            .create(&path, "http://tus.test/files", &options())
+            .unwrap();
+        let job = uploads.resume(&job.id, &server, TIMEOUT, 1).unwrap();
+        server.uploads.lock().unwrap().clear();
+        let job = uploads.resume(&job.id, &server, TIMEOUT, 10).unwrap();
+        assert_eq!(job.state, UploadState::Completed);
+        assert_eq!(job.upload_url.as_deref(), Some("http://tus.test/files/1"));
+        assert_eq!(server.received(), contents);
+    }
+
+    #[test]
+    fn test_retry_failed_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, contents) = file_of(&dir, 10_000);
+        let uploads = UploadQueue::new(":memory:").unwrap();
+
+        // A locked upload is only interrupted
+        let server = TusStub {
+            refuse_patch: Some((2, 423)),
+            ..TusStub::default()
+        };
+        let job = uploads
+            .create(&path, "http://tus.test/files", &options())
+            .unwrap();
+        assert!(matches!(
+            uploads.resume(&job.id, &server, TIMEOUT, 10),
+            Err(UploadError::Transport(_))
+        ));
+        assert_eq!(
+            uploads.get(&job.id).unwrap().unwrap().state,
+            UploadState::Uploading
+        );
+
+        // A rejected one fails and stays failed until retried
+        let server = TusStub {
+            refuse_patch: Some((2, 400)),
+            ..TusStub::default()
+        };
+        let job = uploads
+            .create(&path, "http://tus.test/files", &options())
+            .unwrap();
+        assert!(matches!(
+            uploads.resume(&job.id, &server, TIMEOUT, 10),
+            Err(UploadError::Rejected(_))
+        ));
+        let job = uploads.resume(&job.id, &server, TIMEOUT, 10).unwrap();
+        assert_eq!(job.state, UploadState::Failed);
+        assert_eq!(job.offset, 4096);
+        assert!(!uploads.unfinished().unwrap().contains(&job.id));
+
+        assert!(uploads.retry(&job.id).unwrap());
+        assert!(!uploads.retry(&job.id).unwrap());
+        assert!(uploads.unfinished().unwrap().contains(&job.id));
+        let job = uploads.resume(&job.id, &server, TIMEOUT, 10).unwrap();
+        assert_eq!(job.state, UploadState::Completed);
+        assert_eq!(server.received(), contents);
+        assert_eq!(
+            server.patches(),
+            vec![Some(0), Some(4096), Some(4096), Some(8192)]
+        );
+        assert!(matches!(
+            uploads.retry("missing"),
+            Err(UploadError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_changed_file_fails_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, _) = file_of(&dir, 10_000);
+        let uploads = UploadQueue::new(":memory:").unwrap();
+        let server = TusStub::default();
+
+        let job = uploads
+            .create(&path, "http://tus.test/files", &options())
+            .unwrap();
+        uploads.resume(&job.id, &server, TIMEOUT, 1).unwrap();
+
+        // Unreadable for now: keep the offset
+        std::fs::rename(&path, dir.path().join("moved.pdf")).unwrap();
+        assert!(matches!(
+            uploads.resume(&job.id, &server, TIMEOUT, 10),
+            Err(UploadError::IoError(_))
+        ));
+        assert_eq!(
+            uploads.get(&job.id).unwrap().unwrap().state,
+            UploadState::Uploading
+        );
+
+        // Back, but a different size: the upload can't continue
+        file_of(&dir, 12_000);
+        assert!(matches!(
+            uploads.resume(&job.id, &server, TIMEOUT, 10),
+            Err(UploadError::FileChanged(_))
+        ));
+        assert_eq!(
+            uploads.get(&job.id).unwrap().unwrap().state,
+            UploadState::Failed
+        );
+    }
+
+    #[test]
+    fn test_metadata_and_location() {
+        let metadata = BTreeMap::from([
+            ("filename".to_string(), "a.txt".to_string()),
+            ("type".to_string(), "text/plain".to_string()),
+        ]);
+        assert_eq!(
+            encode_metadata(&metadata),
+            "filename YS50eHQ=,type dGV4dC9wbGFpbg=="
+        );
+
+        let endpoint = "https://up.example.com/api/files";
+        assert_eq!(
+            resolve_location(endpoint, "/api/files/7"),
+            "https://up.example.com/api/files/7"
+        );
+        assert_eq!(
+            resolve_location(endpoint, "7"),
+            "https://up.example.com/api/files/7"
+        );
+        assert_eq!(
+            resolve_location(endpoint, "https://cdn.example.com/7"),
+            "https://cdn.example.com/7"
+        );
+    }
+}